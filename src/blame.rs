@@ -0,0 +1,230 @@
+use crate::diff::{self, Edit};
+use crate::objects::Commit;
+use crate::repository::BlocRepo;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::rc::Rc;
+use colored::*;
+
+/// The commit a line of the blamed file was last changed in, and the
+/// line's number (0-based) in that commit's version of the file.
+struct Origin {
+    hash: String,
+    commit: Rc<Commit>,
+    line: usize,
+}
+
+/// Show which commit last changed each line of `path` as of `revision`
+/// (HEAD by default). With `range` (`<start>,<end>`, `<start>,+<count>` or
+/// `<start>`, 1-based and inclusive), only those lines are attributed and
+/// history is walked no further than they need. With `porcelain`, print
+/// the machine-readable format editors consume instead.
+pub fn blame(repo: &BlocRepo, path: &str, revision: Option<&str>, range: Option<&str>, porcelain: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let start_commit = repo.resolve_revision(revision.unwrap_or("HEAD"))?;
+    let blob = repo.read_tree(&start_commit)?.get(path).cloned().ok_or_else(|| {
+        format!("No such path '{}' in {}", path, &start_commit[..8])
+    })?;
+    let content = String::from_utf8_lossy(&repo.read_object(&blob)?).to_string();
+    let lines: Vec<&str> = content.lines().collect();
+
+    let (first, last) = match range {
+        Some(range) => parse_range(range, lines.len())?,
+        None => (0, lines.len()),
+    };
+
+    let mut origins: Vec<Option<Origin>> = (0..lines.len()).map(|_| None).collect();
+
+    // Lines still to attribute, keyed by the commit being examined:
+    // (line number in that commit's version, line number in the blamed file)
+    let mut pending: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+    pending.insert(start_commit.clone(), (first..last).map(|n| (n, n)).collect());
+    let mut queue = BinaryHeap::new();
+    queue.push((repo.read_commit(&start_commit)?.timestamp, start_commit));
+
+    while let Some((_, hash)) = queue.pop() {
+        let Some(mut tracked) = pending.remove(&hash) else {
+            continue;
+        };
+        let commit = repo.read_commit(&hash)?;
+        let Some(commit_blob) = commit.tree_entries().get(path).cloned() else {
+            continue;
+        };
+        let commit_content = String::from_utf8_lossy(&repo.read_object(&commit_blob)?).to_string();
+        let commit_lines: Vec<&str> = commit_content.lines().collect();
+
+        // Hand lines a parent already had over to that parent
+        for parent in commit.parents() {
+            if tracked.is_empty() {
+                break;
+            }
+            let Ok(parent_commit) = repo.read_commit(parent) else {
+                continue;
+            };
+            let Some(parent_blob) = parent_commit.tree_entries().get(path).cloned() else {
+                continue;
+            };
+
+            let handed_over: Vec<(usize, usize)> = if parent_blob == commit_blob {
+                std::mem::take(&mut tracked)
+            } else {
+                let parent_content = String::from_utf8_lossy(&repo.read_object(&parent_blob)?).to_string();
+                let parent_lines: Vec<&str> = parent_content.lines().collect();
+                let mut in_parent = HashMap::new();
+                for edit in diff::diff(&parent_lines, &commit_lines) {
+                    if let Edit::Equal(i, j) = edit {
+                        in_parent.insert(j, i);
+                    }
+                }
+                let mut handed_over = Vec::new();
+                tracked.retain(|&(line, target)| match in_parent.get(&line) {
+                    Some(&parent_line) => {
+                        handed_over.push((parent_line, target));
+                        false
+                    }
+                    None => true,
+                });
+                handed_over
+            };
+
+            if !handed_over.is_empty() {
+                if !pending.contains_key(parent) {
+                    queue.push((parent_commit.timestamp, parent.clone()));
+                }
+                pending.entry(parent.clone()).or_default().extend(handed_over);
+            }
+        }
+
+        // Whatever no parent had was introduced here
+        if !tracked.is_empty() {
+            let commit = Rc::new(commit);
+            for (line, target) in tracked {
+                origins[target] = Some(Origin { hash: hash.clone(), commit: Rc::clone(&commit), line });
+            }
+        }
+    }
+
+    if porcelain {
+        print_porcelain(path, &lines, &origins, first, last);
+    } else {
+        print_annotated(&lines, &origins, first, last);
+    }
+    Ok(())
+}
+
+/// The name part of a `Name <email>` signature, and the email with its
+/// angle brackets.
+fn split_signature(signature: &str) -> (&str, &str) {
+    match signature.find(" <") {
+        Some(pos) => (&signature[..pos], &signature[pos + 1..]),
+        None => (signature, "<>"),
+    }
+}
+
+/// `<hash> (<author> <date> <line>) <content>` for each line.
+fn print_annotated(lines: &[&str], origins: &[Option<Origin>], first: usize, last: usize) {
+    let number_width = last.to_string().len();
+    let author_width = origins
+        .iter()
+        .flatten()
+        .map(|origin| split_signature(&origin.commit.author_signature()).0.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (n, line) in lines.iter().enumerate().take(last).skip(first) {
+        let Some(origin) = &origins[n] else {
+            continue;
+        };
+        let signature = origin.commit.author_signature();
+        println!("{} ({} {} {:>number_width$}) {}",
+                origin.hash[..8].bright_yellow(),
+                format!("{:<author_width$}", split_signature(&signature).0).bright_blue(),
+                origin.commit.timestamp.format("%Y-%m-%d").to_string().bright_black(),
+                n + 1,
+                line);
+    }
+}
+
+/// The porcelain format: for each line a `<hash> <original line> <final
+/// line>` header, with the size of the group when it starts a run of lines
+/// from the same commit, then the commit's `author`, `author-mail`,
+/// `author-time`, `author-tz`, `committer*`, `summary` and `filename` lines
+/// the first time that commit appears, then the content after a tab.
+fn print_porcelain(path: &str, lines: &[&str], origins: &[Option<Origin>], first: usize, last: usize) {
+    let mut described = HashSet::new();
+    let mut n = first;
+    while n < last {
+        let Some(origin) = &origins[n] else {
+            n += 1;
+            continue;
+        };
+
+        // A group is a run of lines that were consecutive in the origin too
+        let mut group_end = n + 1;
+        while group_end < last && origins[group_end].as_ref().is_some_and(|next| {
+            next.hash == origin.hash && next.line == origin.line + (group_end - n)
+        }) {
+            group_end += 1;
+        }
+
+        for (offset, line) in lines[n..group_end].iter().enumerate() {
+            let origin = origins[n + offset].as_ref().expect("grouped lines are attributed");
+            if offset == 0 {
+                println!("{} {} {} {}", origin.hash, origin.line + 1, n + 1, group_end - n);
+            } else {
+                println!("{} {} {}", origin.hash, origin.line + 1, n + offset + 1);
+            }
+            if described.insert(origin.hash.clone()) {
+                let commit = &origin.commit;
+                let time = commit.timestamp.timestamp();
+                let author_signature = commit.author_signature();
+                // Older commits stored only the author's email as committer
+                let committer_signature = if commit.committer.contains('<') {
+                    commit.committer.clone()
+                } else {
+                    author_signature.clone()
+                };
+                let (author, author_mail) = split_signature(&author_signature);
+                let (committer, committer_mail) = split_signature(&committer_signature);
+                println!("author {}", author);
+                println!("author-mail {}", author_mail);
+                println!("author-time {}", time);
+                println!("author-tz +0000");
+                println!("committer {}", committer);
+                println!("committer-mail {}", committer_mail);
+                println!("committer-time {}", time);
+                println!("committer-tz +0000");
+                println!("summary {}", commit.message.lines().next().unwrap_or_default());
+                if let Some(parent) = &commit.parent {
+                    println!("previous {} {}", parent, path);
+                } else {
+                    println!("boundary");
+                }
+                println!("filename {}", path);
+            }
+            println!("\t{}", line);
+        }
+        n = group_end;
+    }
+}
+
+/// Parse `-L` into a 0-based, end-exclusive line range.
+fn parse_range(range: &str, line_count: usize) -> Result<(usize, usize), String> {
+    let invalid = || format!("Invalid line range '{}': expected <start>,<end> or <start>,+<count>", range);
+    let parse = |n: &str| n.trim().parse::<usize>().map_err(|_| invalid());
+
+    let (start, end) = match range.split_once(',') {
+        Some((start, "")) => (parse(start)?, line_count),
+        Some((start, count)) if count.starts_with('+') => {
+            let start = parse(start)?;
+            (start, (start + parse(&count[1..])?).saturating_sub(1))
+        }
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(range)?, line_count),
+    };
+
+    if start > line_count {
+        return Err(format!("File has only {} lines", line_count));
+    }
+    if start == 0 || end < start {
+        return Err(invalid());
+    }
+    Ok((start - 1, end.min(line_count)))
+}
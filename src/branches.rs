@@ -1,11 +1,36 @@
+use crate::commands;
+use crate::merge;
 use crate::repository::BlocRepo;
+use crate::refsort::{self, RefSort};
+use std::collections::HashSet;
 use std::fs;
 use std::io;
+use std::rc::Rc;
 // use std::path::Path;
 use colored::*;
 
+/// Whether `name` can name a branch: a relative path under `refs/heads/`
+/// with no empty, `.`-led or `..` components, and nothing revision syntax
+/// or the shell would read differently.
+pub fn is_valid_branch_name(name: &str) -> bool {
+    !name.is_empty() &&
+        !name.starts_with('-') &&
+        !name.contains("..") &&
+        !name.contains("@{") &&
+        !name.contains(|c: char| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c)) &&
+        name.split('/').all(|component| !component.is_empty() && !component.starts_with('.'))
+}
+
 pub fn create_branch(repo: &mut BlocRepo, name: &str) -> io::Result<()> {
-    let refs_dir = repo.bloc_dir.join("refs");
+    if !is_valid_branch_name(name) {
+        println!("{}: '{}' {}",
+                "Cannot create branch".bright_red().bold(),
+                name.bright_cyan(),
+                "is not a valid branch name".bright_red());
+        return Ok(());
+    }
+
+    let refs_dir = repo.refs_dir();
     let branch_ref_path = refs_dir.join("heads").join(name);
     
     if branch_ref_path.exists() {
@@ -18,7 +43,11 @@ pub fn create_branch(repo: &mut BlocRepo, name: &str) -> io::Result<()> {
 
     // Get current commit hash
     if let Ok(current_hash) = get_current_commit_hash(repo) {
-        fs::write(branch_ref_path, current_hash)?;
+        if let Some(parent) = branch_ref_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(branch_ref_path, &current_hash)?;
+        repo.append_reflog(&format!("refs/heads/{}", name), None, &current_hash, "branch: Created from HEAD")?;
         println!("{} '{}'", 
                 "Created branch".bright_green().bold(), 
                 name.bright_cyan().bold());
@@ -31,70 +60,108 @@ pub fn create_branch(repo: &mut BlocRepo, name: &str) -> io::Result<()> {
     Ok(())
 }
 
-pub fn delete_branch(repo: &mut BlocRepo, name: &str, force: bool) -> io::Result<()> {
-    let current_branch = repo.get_current_branch().map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-    
-    if current_branch == name {
-        println!("{}: {}", 
-                "Cannot delete branch".bright_red().bold(), 
-                "currently checked out".bright_red());
-        return Ok(());
-    }
+/// Delete each of the `names` branches. A branch whose tip is not
+/// reachable from HEAD is refused unless `force` is set, and the current
+/// branch is always refused; each branch is judged on its own, so one
+/// refusal doesn't stop the rest. With `dry_run`, only report what would
+/// be deleted and what would be refused.
+pub fn delete_branches(repo: &mut BlocRepo, names: &[String], force: bool, dry_run: bool) -> io::Result<()> {
+    let current_branch = repo.get_current_branch().map_err(io::Error::other)?;
+    let merged = match repo.head_commit()? {
+        Some(head) => merge::ancestors(repo, &head)?,
+        None => HashSet::new(),
+    };
 
-    let refs_dir = repo.bloc_dir.join("refs");
-    let branch_ref_path = refs_dir.join("heads").join(name);
-    
-    if !branch_ref_path.exists() {
-        println!("{} '{}' {}", 
-                "Branch".bright_yellow(), 
-                name.bright_cyan(), 
-                "does not exist".bright_yellow());
-        return Ok(());
-    }
+    let mut upstreams_removed = false;
+    for name in names {
+        if current_branch == *name {
+            println!("{} '{}': {}", 
+                    "Cannot delete branch".bright_red().bold(), 
+                    name.bright_cyan(), 
+                    "currently checked out".bright_red());
+            continue;
+        }
 
-    if !force {
-        // TODO: Check if branch is merged
-        println!("{}: {} {}", 
-                "Use --force to delete".bright_yellow().bold(), 
+        let branch_ref_path = repo.refs_dir().join("heads").join(name);
+        if !branch_ref_path.is_file() {
+            println!("{} '{}' {}", 
+                    "Branch".bright_yellow(), 
+                    name.bright_cyan(), 
+                    "does not exist".bright_yellow());
+            continue;
+        }
+
+        let tip = fs::read_to_string(&branch_ref_path)?.trim().to_string();
+        if !force && !merged.contains(&tip) {
+            println!("{} '{}' {}; {}", 
+                    if dry_run { "Would refuse branch" } else { "Cannot delete branch" }.bright_red().bold(), 
+                    name.bright_cyan(), 
+                    "(not fully merged)".bright_red(), 
+                    "use --force to delete it anyway".bright_yellow());
+            continue;
+        }
+
+        if dry_run {
+            println!("{} '{}' ({})", 
+                    "Would delete branch".bright_yellow().bold(), 
+                    name.bright_cyan(), 
+                    tip[..8.min(tip.len())].bright_black());
+            continue;
+        }
+
+        fs::remove_file(branch_ref_path)?;
+        let log_path = repo.bloc_dir.join("logs/refs/heads").join(name);
+        if log_path.exists() {
+            fs::remove_file(log_path)?;
+        }
+        upstreams_removed |= repo.config.branches.remove(name).is_some();
+        println!("{} '{}' ({})", 
+                "Deleted branch".bright_red().bold(), 
                 name.bright_cyan(), 
-                "(branch merge check not implemented)".bright_yellow());
-        return Ok(());
+                tip[..8.min(tip.len())].bright_black());
     }
 
-    fs::remove_file(branch_ref_path)?;
-    println!("{} '{}'", 
-            "Deleted branch".bright_red().bold(), 
-            name.bright_cyan());
-    
+    if upstreams_removed {
+        repo.config.save()?;
+    }
     Ok(())
 }
 
-pub fn list_branches(repo: &BlocRepo) -> io::Result<()> {
-    let refs_dir = repo.bloc_dir.join("refs").join("heads");
-    
-    if !refs_dir.exists() {
+/// List local branches, remote-tracking branches under `refs/remotes`, or
+/// both. When both are shown, remote ones are prefixed with `remotes/`.
+pub fn list_branches(repo: &BlocRepo, local: bool, remote: bool, sort: RefSort) -> io::Result<()> {
+    let heads_dir = repo.refs_dir().join("heads");
+    let remotes_dir = repo.refs_dir().join("remotes");
+    let mut local_branches = if local { refsort::ref_names(&heads_dir)? } else { Vec::new() };
+    let mut remote_branches = if remote { refsort::ref_names(&remotes_dir)? } else { Vec::new() };
+    sort.sort(repo, &heads_dir, &mut local_branches);
+    sort.sort(repo, &remotes_dir, &mut remote_branches);
+
+    if local_branches.is_empty() && remote_branches.is_empty() {
         println!("{}", "No branches found".bright_yellow());
         return Ok(());
     }
 
     let current_branch = repo.get_current_branch().unwrap_or_else(|_| "master".to_string());
     
-    for entry in fs::read_dir(refs_dir)? {
-        let entry = entry?;
-        let branch_name = entry.file_name().to_string_lossy().to_string();
-        
+    for branch_name in local_branches {
         if branch_name == current_branch {
             println!("{} {}", "*".bright_green().bold(), branch_name.bright_green().bold());
         } else {
             println!("  {}", branch_name.white());
         }
     }
+
+    for branch_name in remote_branches {
+        let display_name = if local { format!("remotes/{}", branch_name) } else { branch_name };
+        println!("  {}", display_name.bright_red());
+    }
     
     Ok(())
 }
 
 pub fn checkout(repo: &mut BlocRepo, branch_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let refs_dir = repo.bloc_dir.join("refs").join("heads");
+    let refs_dir = repo.refs_dir().join("heads");
     let branch_ref_path = refs_dir.join(branch_name);
     
     if !branch_ref_path.exists() {
@@ -105,20 +172,87 @@ pub fn checkout(repo: &mut BlocRepo, branch_name: &str) -> Result<(), Box<dyn st
         return Ok(());
     }
 
+    if repo.get_current_branch()? == branch_name {
+        println!("{} '{}'", 
+                "Already on".bright_yellow(), 
+                branch_name.bright_cyan().bold());
+        return Ok(());
+    }
+
+    // Refuse to clobber local changes to files that differ between the branches
+    let current_tree = match repo.head_commit()? {
+        Some(head_hash) => repo.read_tree(&head_hash)?,
+        None => Rc::default(),
+    };
+    let target_hash = fs::read_to_string(&branch_ref_path)?.trim().to_string();
+    let target_tree = repo.read_tree(&target_hash)?;
+
+    let blocked = repo.overwritten_paths(&current_tree, &target_tree);
+    if !blocked.is_empty() {
+        println!("{}: {}", 
+                "Cannot switch branches".bright_red().bold(), 
+                "local changes to the following files would be overwritten".bright_red());
+        for path in &blocked {
+            println!("  {}", path.bright_red());
+        }
+        println!("{}", "Commit or restore your changes before switching branches".bright_yellow());
+        return Ok(());
+    }
+
+    repo.update_working_tree(&current_tree, &target_tree)?;
+
+    // Staged entries that now match the checked-out files are no longer changes
+    repo.index.entries.retain(|path, entry| target_tree.get(path) != entry.staged_hash());
+    repo.index.save()?;
+
     // Update HEAD to point to the new branch
-    let head_path = repo.bloc_dir.join("HEAD");
+    let old_branch = repo.get_current_branch()?;
+    let old_head = repo.head_commit()?;
+    let head_path = repo.head_path();
     let head_content = format!("ref: refs/heads/{}", branch_name);
     fs::write(head_path, head_content)?;
+    repo.append_reflog("HEAD", old_head.as_deref(), &target_hash,
+                       &format!("checkout: moving from {} to {}", old_branch, branch_name))?;
     
     println!("{} '{}'", 
             "Switched to branch".bright_green().bold(), 
             branch_name.bright_cyan().bold());
+    if let Some(old_head) = old_head {
+        commands::warn_abandoned_commits(repo, &old_head)?;
+    }
     
     Ok(())
 }
 
+pub fn switch(repo: &mut BlocRepo, branch_name: &str, create: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if create {
+        if repo.refs_dir().join("heads").join(branch_name).exists() {
+            println!("{} '{}' {}", 
+                    "Branch".bright_red().bold(), 
+                    branch_name.bright_cyan(), 
+                    "already exists".bright_red());
+            return Ok(());
+        }
+
+        match get_current_commit_hash(repo) {
+            Ok(_) => create_branch(repo, branch_name)?,
+            Err(_) => {
+                // An unborn branch has nothing to copy; just repoint HEAD
+                let head_content = format!("ref: refs/heads/{}", branch_name);
+                fs::write(repo.head_path(), head_content)?;
+                println!("{} '{}'", 
+                        "Switched to a new branch".bright_green().bold(), 
+                        branch_name.bright_cyan().bold());
+                return Ok(());
+            }
+        }
+    }
+
+    checkout(repo, branch_name)
+}
+
 pub fn rename_branch(repo: &mut BlocRepo, old_name: &str, new_name: &str) -> io::Result<()> {
-    let refs_dir = repo.bloc_dir.join("refs").join("heads");
+    let refs_dir = repo.refs_dir().join("heads");
     let old_path = refs_dir.join(old_name);
     let new_path = refs_dir.join(new_name);
     
@@ -138,15 +272,32 @@ pub fn rename_branch(repo: &mut BlocRepo, old_name: &str, new_name: &str) -> io:
         return Ok(());
     }
 
-    fs::rename(old_path, new_path)?;
+    fs::rename(old_path, &new_path)?;
+
+    // The reflog moves with the branch, noting the rename
+    let logs_dir = repo.bloc_dir.join("logs/refs/heads");
+    if logs_dir.join(old_name).exists() {
+        if let Some(parent) = logs_dir.join(new_name).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(logs_dir.join(old_name), logs_dir.join(new_name))?;
+    }
+    let hash = fs::read_to_string(&new_path)?.trim().to_string();
+    repo.append_reflog(&format!("refs/heads/{}", new_name), Some(&hash), &hash,
+                       &format!("Branch: renamed refs/heads/{} to refs/heads/{}", old_name, new_name))?;
     
     // Update HEAD if it was pointing to the renamed branch
-    let head_path = repo.bloc_dir.join("HEAD");
-    if let Ok(head_content) = fs::read_to_string(&head_path) {
-        if head_content.trim() == format!("ref: refs/heads/{}", old_name) {
-            let new_head_content = format!("ref: refs/heads/{}", new_name);
-            fs::write(head_path, new_head_content)?;
-        }
+    let head_path = repo.head_path();
+    if let Ok(head_content) = fs::read_to_string(&head_path)
+        && head_content.trim() == format!("ref: refs/heads/{}", old_name) {
+        let new_head_content = format!("ref: refs/heads/{}", new_name);
+        fs::write(head_path, new_head_content)?;
+    }
+
+    // The upstream moves with the branch
+    if let Some(upstream) = repo.config.branches.remove(old_name) {
+        repo.config.branches.insert(new_name.to_string(), upstream);
+        repo.config.save()?;
     }
     
     println!("{} '{}' {} '{}'", 
@@ -159,13 +310,36 @@ pub fn rename_branch(repo: &mut BlocRepo, old_name: &str, new_name: &str) -> io:
 }
 
 fn get_current_commit_hash(repo: &BlocRepo) -> Result<String, Box<dyn std::error::Error>> {
-    let current_branch = repo.get_current_branch()?;
-    let refs_dir = repo.bloc_dir.join("refs").join("heads");
-    let branch_ref_path = refs_dir.join(current_branch);
-    
-    if branch_ref_path.exists() {
-        Ok(fs::read_to_string(branch_ref_path)?.trim().to_string())
-    } else {
-        Err("No commits found".into())
+    repo.head_commit()?.ok_or_else(|| "No commits found".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use crate::repository::test_support::temp_repo;
+
+    #[test]
+    fn status_is_clean_right_after_checkout() {
+        let (_guard, mut repo, dir) = temp_repo("checkout-clean");
+        fs::write("kept.txt", "on both branches\n").unwrap();
+        commands::add_files(&mut repo, &["kept.txt".to_string()], false).unwrap();
+        commands::commit(&mut repo, Some("initial"), None, false).unwrap();
+        create_branch(&mut repo, "feature").unwrap();
+        checkout(&mut repo, "feature").unwrap();
+        fs::write("feature.txt", "only on feature\n").unwrap();
+        commands::add_files(&mut repo, &["feature.txt".to_string()], false).unwrap();
+        commands::commit(&mut repo, Some("feature"), None, false).unwrap();
+        checkout(&mut repo, "main").unwrap();
+        checkout(&mut repo, "feature").unwrap();
+
+        // A fresh process sees nothing staged and every file unmodified
+        let repo = BlocRepo::new().unwrap();
+        let head_tree = repo.read_tree(&repo.head_commit().unwrap().unwrap()).unwrap();
+        assert_eq!(repo.staged_tree().unwrap(), *head_tree);
+        for (path, hash) in head_tree.iter() {
+            assert_eq!(repo.working_file_hash(path).as_ref(), Some(hash), "{} is modified", path);
+        }
+        fs::remove_dir_all(dir).unwrap();
     }
 }
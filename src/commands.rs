@@ -1,80 +1,139 @@
-use crate::repository::BlocRepo;
+use crate::repository::{in_sparse_checkout, BlocRepo};
+use crate::branches;
 use crate::objects::{Commit, IndexEntry};
+use crate::signing::{self, SignatureStatus};
+use crate::diff;
+use crate::editor;
+use crate::merge;
+use crate::refsort;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashSet};
 use std::fs;
-use std::io;
+use std::io::{self, BufRead, Write};
 use std::path::Path;
+use std::rc::Rc;
 use walkdir::WalkDir;
 use colored::*;
-use chrono::Utc;
-use sha2::{Digest, Sha256};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 
-pub fn add_files(repo: &mut BlocRepo, files: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+pub fn add_files(repo: &mut BlocRepo, files: &[String], force: bool) -> Result<(), Box<dyn std::error::Error>> {
     if repo.is_bare {
         println!("{}", "Cannot add files to a bare repository".bright_red().bold());
         return Ok(());
     }
 
+    let head_tree = head_tree(repo)?;
+    let tracked = repo.staged_tree()?;
+    let sparse_patterns = repo.sparse_patterns();
+
+    // Tracked paths to compare new paths against on case-insensitive filesystems
+    let mut known_paths: BTreeSet<String> = if repo.config.core.ignorecase {
+        tracked.keys().cloned().collect()
+    } else {
+        BTreeSet::new()
+    };
+
+    // --force overrides ignore rules, but never for the repository's own files
+    let skip = |repo: &BlocRepo, path: &Path| {
+        (!force || BlocRepo::is_internal_path(path)) && repo.should_ignore(path)
+    };
+
     for pattern in files {
+        let path = Path::new(pattern);
         if pattern == "." {
             // Add all files recursively
             for entry in WalkDir::new(".").into_iter().filter_map(|e| e.ok()) {
                 let path = entry.path();
-                if path.is_file() && !repo.should_ignore(path) {
-                    add_single_file(repo, path)?;
+                if path.is_file() && !skip(repo, path) {
+                    add_single_file(repo, path, &head_tree, &mut known_paths)?;
                 }
             }
-        } else {
-            let path = Path::new(pattern);
-            if path.is_file() {
-                if !repo.should_ignore(path) {
-                    add_single_file(repo, path)?;
-                }
-            } else if path.is_dir() {
-                for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
-                    let file_path = entry.path();
-                    if file_path.is_file() && !repo.should_ignore(file_path) {
-                        add_single_file(repo, file_path)?;
-                    }
-                }
-            } else {
+        } else if path.is_file() {
+            if !skip(repo, path) {
+                add_single_file(repo, path, &head_tree, &mut known_paths)?;
+            } else if !BlocRepo::is_internal_path(path) {
                 println!("{}: {} {}", 
                         "Warning".bright_yellow().bold(), 
-                        pattern.white(), 
-                        "does not exist".bright_yellow());
+                        pattern.bright_cyan(), 
+                        "is ignored by .blocignore (use -f to add it anyway)".bright_yellow());
+            }
+        } else if path.is_dir() {
+            for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+                let file_path = entry.path();
+                if file_path.is_file() && !skip(repo, file_path) {
+                    add_single_file(repo, file_path, &head_tree, &mut known_paths)?;
+                }
             }
         }
+
+        // Tracked files under the pattern that are gone from disk are staged as removed
+        let removed: Vec<String> = match_pathspec(&tracked, pattern)
+            .into_iter()
+            .map(|(tracked_path, _)| tracked_path.clone())
+            .filter(|tracked_path| !Path::new(tracked_path).exists() &&
+                                   in_sparse_checkout(sparse_patterns.as_deref(), tracked_path))
+            .collect();
+        for removed_path in &removed {
+            stage_deletion(repo, removed_path, &head_tree);
+            println!("{} {}", "Removed".bright_red().bold(), removed_path.bright_cyan());
+        }
+
+        if !path.exists() && removed.is_empty() {
+            println!("{}: {} {}", 
+                    "Warning".bright_yellow().bold(), 
+                    pattern.white(), 
+                    "does not exist".bright_yellow());
+        }
     }
     
     repo.index.save()?;
     Ok(())
 }
 
-fn add_single_file(repo: &mut BlocRepo, path: &Path) -> io::Result<()> {
+fn add_single_file(repo: &mut BlocRepo, path: &Path, head_tree: &BTreeMap<String, String>, known_paths: &mut BTreeSet<String>) -> io::Result<()> {
+    let relative_path = relative_path(path);
+    let metadata = fs::metadata(path)?;
+
+    if repo.config.core.ignorecase && !known_paths.contains(&relative_path) {
+        if let Some(existing) = known_paths.iter().find(|known| known.eq_ignore_ascii_case(&relative_path)) {
+            println!("{}: {} {} {} {}", 
+                    "Warning".bright_yellow().bold(), 
+                    relative_path.bright_cyan(), 
+                    "differs only in case from tracked path".bright_yellow(),
+                    existing.bright_cyan(),
+                    "(core.ignorecase is set, so both will collide on checkout)".bright_yellow());
+        }
+        known_paths.insert(relative_path.clone());
+    }
+
+    // Unchanged since it was checked out or last hashed, and identical to HEAD
+    if let Some(hash) = repo.index.cached_hash(&relative_path, &metadata)
+        && head_tree.get(&relative_path) == Some(&hash) {
+        repo.index.entries.remove(&relative_path);
+        return Ok(());
+    }
+
     let content = fs::read_to_string(path)?;
-    let mut hasher = Sha256::new();
-    hasher.update(content.as_bytes());
-    let hash = format!("{:x}", hasher.finalize());
-    
-    let relative_path = if let Ok(rel_path) = path.strip_prefix(".") {
-        rel_path.to_string_lossy().to_string()
-    } else {
-        path.to_string_lossy().to_string()
-    };
+
+    // Content identical to HEAD is not a change, so nothing stays staged
+    let hash = repo.hash_object(content.as_bytes());
+    repo.index.record_stat(&relative_path, &hash, &metadata);
+    if head_tree.get(&relative_path) == Some(&hash) {
+        repo.index.entries.remove(&relative_path);
+        return Ok(());
+    }
     
     // Store the content as an object
-    let objects_dir = repo.bloc_dir.join("objects");
-    let object_dir = objects_dir.join(&hash[..2]);
-    fs::create_dir_all(&object_dir)?;
+    let hash = repo.write_object(content.as_bytes())?;
     
-    let object_path = object_dir.join(&hash[2..]);
-    fs::write(&object_path, content.as_bytes())?;
-    
-    // Add to index
+    // Add to index, with the file's own mtime rather than the time of adding
     let entry = IndexEntry {
-        hash: hash,
+        hash,
         size: content.len() as u64,
         mode: "100644".to_string(), // Regular file
-        mtime: Utc::now(),
+        mtime: metadata.modified().map(Into::into).unwrap_or_else(|_| Utc::now()),
+        deleted: false,
+        conflicted: false,
     };
     
     repo.index.entries.insert(relative_path.clone(), entry);
@@ -104,145 +163,1540 @@ pub fn reset_files(repo: &mut BlocRepo, files: &[String]) -> Result<(), Box<dyn
     Ok(())
 }
 
-pub fn commit(repo: &mut BlocRepo, message: &str) -> Result<(), Box<dyn std::error::Error>> {
-    if repo.index.entries.is_empty() {
+pub fn reset_paths_to_commit(repo: &mut BlocRepo, revision: &str, paths: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        println!("{}", "Cannot reset files in a bare repository".bright_red().bold());
+        return Ok(());
+    }
+
+    let commit_hash = repo.resolve_revision(revision)?;
+    let source_tree = repo.read_tree(&commit_hash)?;
+    let head_tree = head_tree(repo)?;
+    let staged_tree = repo.staged_tree()?;
+
+    for pathspec in paths {
+        let matching = match_pathspec(&source_tree, pathspec);
+        // Tracked paths the commit doesn't have leave the staging area
+        let missing: Vec<String> = match_pathspec(&staged_tree, pathspec)
+            .into_iter()
+            .map(|(path, _)| path.clone())
+            .filter(|path| !source_tree.contains_key(path))
+            .collect();
+        if matching.is_empty() && missing.is_empty() {
+            println!("{}: {} {} {}", 
+                    "Warning".bright_yellow().bold(), 
+                    pathspec.bright_cyan(), 
+                    "did not match any file in".bright_yellow(),
+                    commit_hash[..8].bright_yellow());
+            continue;
+        }
+
+        for path in &missing {
+            stage_deletion(repo, path, &head_tree);
+            println!("{} {} {} {}", 
+                    "Unstaged".bright_yellow().bold(), 
+                    path.bright_cyan(), 
+                    "(not in".bright_yellow(),
+                    format!("{})", &commit_hash[..8]).bright_yellow());
+        }
+
+        for (path, hash) in matching {
+            stage_blob(repo, path, hash, &head_tree)?;
+            println!("{} {} {} {}", 
+                    "Reset".bright_yellow().bold(), 
+                    path.bright_cyan(), 
+                    "to".bright_yellow(),
+                    commit_hash[..8].bright_yellow());
+        }
+    }
+
+    repo.index.save()?;
+    Ok(())
+}
+
+/// `reset --keep <commit>`: move the current branch to `revision` and update
+/// the files that differ between HEAD and it. Local changes to any of those
+/// files, staged or not, abort the reset before anything is touched; local
+/// changes to other files are carried over untouched.
+pub fn reset_keep(repo: &mut BlocRepo, revision: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        println!("{}", "Cannot reset the working tree of a bare repository".bright_red().bold());
+        return Ok(());
+    }
+
+    let head_tree = head_tree(repo)?;
+    let target_hash = repo.resolve_revision(revision)?;
+    let target_tree = repo.read_tree(&target_hash)?;
+
+    let sparse_patterns = repo.sparse_patterns();
+    let mut blocked = Vec::new();
+    for path in head_tree.keys().chain(target_tree.keys()).collect::<BTreeSet<_>>() {
+        let head_hash = head_tree.get(path);
+        if head_hash == target_tree.get(path) {
+            continue;
+        }
+        let staged = repo.index.entries.contains_key(path);
+        let working_changed = in_sparse_checkout(sparse_patterns.as_deref(), path) &&
+            repo.working_file_hash(path).as_ref() != head_hash;
+        if staged || working_changed {
+            blocked.push(path);
+        }
+    }
+    if !blocked.is_empty() {
+        println!("{}: {}", 
+                "Cannot reset".bright_red().bold(), 
+                "local changes to the following files would be overwritten".bright_red());
+        for path in &blocked {
+            println!("  {}", path.bright_red());
+        }
+        println!("{}", "Commit or restore your changes before resetting".bright_yellow());
+        return Ok(());
+    }
+
+    let old_head = repo.head_commit()?;
+    repo.update_working_tree(&head_tree, &target_tree)?;
+    // Remaining entries only touch paths HEAD and the target agree on
+    repo.index.save()?;
+    repo.update_head(old_head.as_deref(), &target_hash, &format!("reset: moving to {}", revision))?;
+
+    let subject = repo.read_commit(&target_hash)?.message.lines().next().unwrap_or_default().to_string();
+    println!("{} {} {}", 
+            "HEAD is now at".bright_green().bold(), 
+            target_hash[..8].bright_yellow(), 
+            subject.white());
+    if let Some(old_head) = old_head {
+        warn_abandoned_commits(repo, &old_head)?;
+    }
+    Ok(())
+}
+
+/// After HEAD moved away from `old_head`, list the commits no branch, tag
+/// or other ref reaches any more, pointing at the reflog to recover them.
+pub fn warn_abandoned_commits(repo: &BlocRepo, old_head: &str) -> io::Result<()> {
+    let abandoned = abandoned_commits(repo, old_head)?;
+    if abandoned.is_empty() {
+        return Ok(());
+    }
+
+    let count = if abandoned.len() == 1 { "1 commit".to_string() } else { format!("{} commits", abandoned.len()) };
+    println!("\n{} {} {}",
+            "Warning: you are leaving".bright_yellow().bold(),
+            count.bright_yellow().bold(),
+            "behind, not connected to any of your branches:".bright_yellow().bold());
+    println!();
+    for hash in &abandoned {
+        let subject = repo.read_commit(hash)?.message.lines().next().unwrap_or_default().to_string();
+        println!("  {} {}", hash[..8].bright_yellow(), subject.white());
+    }
+    println!();
+    println!("{}", "They can still be found with 'bloc reflog' and brought back with".bright_black());
+    println!("{}", format!("'bloc reset --keep {}'.", &abandoned[0][..8]).bright_black());
+    Ok(())
+}
+
+/// Commits reachable from `old_head` but from no ref, newest first.
+fn abandoned_commits(repo: &BlocRepo, old_head: &str) -> io::Result<Vec<String>> {
+    let mut kept = HashSet::new();
+    for entry in WalkDir::new(repo.refs_dir()).into_iter().filter_map(|e| e.ok()) {
+        if !entry.path().is_file() {
+            continue;
+        }
+        let mut hash = fs::read_to_string(entry.path())?.trim().to_string();
+        if let Ok(tag) = repo.read_tag(&hash) {
+            hash = tag.object.clone();
+        }
+        if !kept.contains(&hash) {
+            kept.extend(merge::ancestors(repo, &hash)?);
+        }
+    }
+
+    let mut abandoned = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![old_head.to_string()];
+    while let Some(hash) = pending.pop() {
+        if kept.contains(&hash) || !seen.insert(hash.clone()) {
+            continue;
+        }
+        let Ok(commit) = repo.read_commit(&hash) else {
+            continue;
+        };
+        pending.extend(commit.parents().cloned());
+        abandoned.push((commit.timestamp, hash));
+    }
+    abandoned.sort_by(|a, b| b.cmp(a));
+    Ok(abandoned.into_iter().map(|(_, hash)| hash).collect())
+}
+
+/// `reflog [<ref>]`: list where a ref has pointed, most recent first.
+pub fn reflog(repo: &BlocRepo, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let ref_name = if name == "HEAD" || name.starts_with("refs/") {
+        name.to_string()
+    } else {
+        format!("refs/heads/{}", name)
+    };
+    let entries = repo.read_reflog(&ref_name)?;
+    if entries.is_empty() {
+        println!("{} '{}'", "No reflog entries for".bright_yellow(), name.bright_cyan());
+        return Ok(());
+    }
+    for (i, entry) in entries.iter().rev().enumerate() {
+        println!("{} {}: {}",
+                entry.new[..8.min(entry.new.len())].bright_yellow(),
+                format!("{}@{{{}}}", name, i).bright_cyan(),
+                entry.message.white());
+    }
+    Ok(())
+}
+
+/// Stage `hash` as the content of `path`, or drop the entry when that is
+/// what HEAD already has.
+fn stage_blob(repo: &mut BlocRepo, path: &str, hash: &str, head_tree: &BTreeMap<String, String>) -> io::Result<()> {
+    if head_tree.get(path).map(String::as_str) == Some(hash) {
+        // Same content as HEAD, so nothing needs to stay staged
+        repo.index.entries.remove(path);
+    } else {
+        let size = repo.read_object(hash)?.len() as u64;
+        repo.index.entries.insert(path.to_string(), IndexEntry {
+            hash: hash.to_string(),
+            size,
+            mode: "100644".to_string(),
+            mtime: Utc::now(),
+            deleted: false,
+            conflicted: false,
+        });
+    }
+    Ok(())
+}
+
+/// `checkout [<commit>] -- <paths>`: without a commit, overwrite the paths
+/// in the working tree with their staged content; with one, take them from
+/// that commit's tree and stage them as well. Nothing is touched unless
+/// every pathspec matches.
+pub fn checkout_paths(repo: &mut BlocRepo, revision: Option<&str>, paths: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(revision) = revision else {
+        return restore(repo, paths, false, None);
+    };
+    if repo.is_bare {
+        println!("{}", "Cannot check out files in a bare repository".bright_red().bold());
+        return Ok(());
+    }
+
+    let commit_hash = repo.resolve_revision(revision)?;
+    let source_tree = repo.read_tree(&commit_hash)?;
+    let head_tree = head_tree(repo)?;
+
+    let mut matching = BTreeMap::new();
+    for pathspec in paths {
+        let matched = match_pathspec(&source_tree, pathspec);
+        if matched.is_empty() {
+            return Err(format!("pathspec '{}' did not match any file in {}", pathspec, &commit_hash[..8]).into());
+        }
+        matching.extend(matched.into_iter().map(|(path, hash)| (path.clone(), hash.clone())));
+    }
+
+    for (path, hash) in &matching {
+        repo.checkout_file(path, hash)?;
+        stage_blob(repo, path, hash, &head_tree)?;
+    }
+    repo.index.save()?;
+
+    println!("{} {} {} {}", 
+            "Updated".bright_green().bold(), 
+            matching.len().to_string().bright_yellow(), 
+            if matching.len() == 1 { "path from" } else { "paths from" }.bright_green(),
+            commit_hash[..8].bright_yellow());
+    Ok(())
+}
+
+pub fn restore(repo: &mut BlocRepo, paths: &[String], staged: bool, source: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        println!("{}", "Cannot restore files in a bare repository".bright_red().bold());
+        return Ok(());
+    }
+
+    if staged {
+        return reset_paths_to_commit(repo, source.unwrap_or("HEAD"), paths);
+    }
+
+    // The working tree is restored from the staging area unless a source is given
+    let source_tree = match source {
+        Some(revision) => repo.read_tree(&repo.resolve_revision(revision)?)?,
+        None => Rc::new(repo.staged_tree()?),
+    };
+
+    for pathspec in paths {
+        let matching = match_pathspec(&source_tree, pathspec);
+        if matching.is_empty() {
+            println!("{}: {} {}", 
+                    "Warning".bright_yellow().bold(), 
+                    pathspec.bright_cyan(), 
+                    "did not match any tracked file".bright_yellow());
+            continue;
+        }
+
+        for (path, hash) in matching {
+            repo.checkout_file(path, hash)?;
+            println!("{} {}", "Restored".bright_green().bold(), path.bright_cyan());
+        }
+    }
+
+    Ok(())
+}
+
+/// Entries of `tree` named by `pathspec`, either exactly or as a directory prefix.
+fn match_pathspec<'a>(tree: &'a BTreeMap<String, String>, pathspec: &str) -> Vec<(&'a String, &'a String)> {
+    let pathspec = pathspec.trim_start_matches("./").trim_end_matches('/');
+    if pathspec.is_empty() || pathspec == "." {
+        return tree.iter().collect();
+    }
+
+    let dir_prefix = format!("{}/", pathspec);
+    tree.iter()
+        .filter(|(path, _)| path.as_str() == pathspec || path.starts_with(&dir_prefix))
+        .collect()
+}
+
+pub fn remove_files(repo: &mut BlocRepo, files: &[String], recursive: bool, cached: bool, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        println!("{}", "Cannot remove files in a bare repository".bright_red().bold());
+        return Ok(());
+    }
+
+    let head_tree = head_tree(repo)?;
+    let tracked = repo.staged_tree()?;
+
+    for pathspec in files {
+        let matching = match_pathspec(&tracked, pathspec);
+        if matching.is_empty() {
+            println!("{}: {} {}", 
+                    "Warning".bright_yellow().bold(), 
+                    pathspec.bright_cyan(), 
+                    "did not match any tracked file".bright_yellow());
+            continue;
+        }
+
+        let is_single_file = matching.len() == 1 && matching[0].0 == pathspec.trim_start_matches("./");
+        if !is_single_file && !recursive {
+            println!("{}: {} '{}' {}", 
+                    "Error".bright_red().bold(), 
+                    "not removing".bright_red(), 
+                    pathspec.bright_cyan(),
+                    "recursively without -r".bright_red());
+            continue;
+        }
+
+        for (path, hash) in matching {
+            let modified = repo.working_file_hash(path).is_some_and(|working_hash| &working_hash != hash);
+            if modified && !cached && !force {
+                println!("{}: {} {}", 
+                        "Error".bright_red().bold(), 
+                        path.bright_cyan(), 
+                        "has local modifications (use --cached to keep the file, or -f to force removal)".bright_red());
+                continue;
+            }
+
+            stage_deletion(repo, path, &head_tree);
+            if !cached {
+                repo.remove_working_file(path)?;
+            }
+            println!("{} {}", "Removed".bright_red().bold(), path.bright_cyan());
+        }
+    }
+
+    repo.index.save()?;
+    Ok(())
+}
+
+/// Move or rename tracked files and directories, in the working tree and
+/// in the index. With several sources, or when `destination` is an existing
+/// directory, each source is moved into it under its own name; directories
+/// keep the layout of everything inside them. Every move is checked before
+/// anything happens; with `keep_going`, moves that would fail are skipped
+/// and reported at the end instead of aborting the whole command.
+pub fn move_paths(repo: &mut BlocRepo, sources: &[String], destination: &str, keep_going: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        println!("{}", "Cannot move files in a bare repository".bright_red().bold());
+        return Ok(());
+    }
+
+    let head_tree = head_tree(repo)?;
+    let tracked = repo.staged_tree()?;
+    let destination = destination.trim_start_matches("./").trim_end_matches('/');
+    let into_directory = Path::new(destination).is_dir();
+    if sources.len() > 1 && !into_directory {
+        return Err(format!("destination '{}' is not a directory", destination).into());
+    }
+
+    let mut planned = Vec::new();
+    let mut skipped = Vec::new();
+    let mut targets = BTreeSet::new();
+    for source in sources {
+        let source = source.trim_start_matches("./").trim_end_matches('/');
+        let target = match (into_directory, Path::new(source).file_name()) {
+            (true, Some(name)) => format!("{}/{}", destination, name.to_string_lossy()),
+            _ => destination.to_string(),
+        };
+        let plan = plan_move(repo, &tracked, source, &target).and_then(|entries| {
+            if targets.insert(target.clone()) {
+                Ok(entries)
+            } else {
+                Err(format!("multiple sources for the same destination '{}'", target))
+            }
+        });
+        match plan {
+            Ok(entries) => planned.push((source.to_string(), target, entries)),
+            Err(reason) if keep_going => skipped.push((source.to_string(), reason)),
+            Err(reason) => return Err(format!("cannot move '{}': {}", source, reason).into()),
+        }
+    }
+
+    for (source, target, entries) in planned {
+        if let Some(parent) = Path::new(&target).parent()
+            && !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Err(e) = fs::rename(&source, &target) {
+            if keep_going {
+                skipped.push((source, e.to_string()));
+                continue;
+            }
+            return Err(format!("renaming '{}' failed: {}", source, e).into());
+        }
+
+        for (old_path, new_path, hash) in entries {
+            repo.index.forget_stat(&old_path);
+            stage_deletion(repo, &old_path, &head_tree);
+            stage_blob(repo, &new_path, &hash, &head_tree)?;
+        }
+        println!("{} {} -> {}", "Renamed".bright_green().bold(), source.bright_cyan(), target.bright_cyan());
+    }
+    repo.index.save()?;
+
+    if !skipped.is_empty() {
+        println!("{}", "Skipped:".bright_yellow().bold());
+        for (source, reason) in &skipped {
+            println!("  {}: {}", source.bright_cyan(), reason.bright_yellow());
+        }
+    }
+    Ok(())
+}
+
+/// The index entries moving `source` to `target` would rewrite, as
+/// (old path, new path, staged blob), or why the move cannot be done.
+fn plan_move(repo: &BlocRepo, tracked: &BTreeMap<String, String>, source: &str, target: &str) -> Result<Vec<(String, String, String)>, String> {
+    if source == target || target.starts_with(&format!("{}/", source)) {
+        return Err("cannot move a directory into itself".to_string());
+    }
+    let matching = match_pathspec(tracked, source);
+    if matching.is_empty() {
+        return Err("not under version control".to_string());
+    }
+    if !Path::new(source).exists() {
+        return Err("missing from the working tree".to_string());
+    }
+    if Path::new(target).exists() || !match_pathspec(tracked, target).is_empty() {
+        return Err(format!("destination '{}' already exists", target));
+    }
+    if matching.iter().any(|(path, _)| repo.index.entries.get(*path).is_some_and(|entry| entry.conflicted)) {
+        return Err("has unresolved merge conflicts".to_string());
+    }
+
+    Ok(matching
+        .into_iter()
+        .map(|(path, hash)| (path.clone(), format!("{}{}", target, &path[source.len()..]), hash.clone()))
+        .collect())
+}
+
+/// Record `path` as removed in the staging area. A path that only exists
+/// in the staging area is simply unstaged.
+fn stage_deletion(repo: &mut BlocRepo, path: &str, head_tree: &BTreeMap<String, String>) {
+    match head_tree.get(path) {
+        Some(head_hash) => {
+            repo.index.entries.insert(path.to_string(), IndexEntry {
+                hash: head_hash.clone(),
+                size: 0,
+                mode: "100644".to_string(),
+                mtime: Utc::now(),
+                deleted: true,
+                conflicted: false,
+            });
+        }
+        None => {
+            repo.index.entries.remove(path);
+        }
+    }
+}
+
+fn head_tree(repo: &BlocRepo) -> io::Result<Rc<BTreeMap<String, String>>> {
+    match repo.head_commit()? {
+        Some(head_hash) => repo.read_tree(&head_hash),
+        None => Ok(Rc::default()),
+    }
+}
+
+pub fn ls_tree(repo: &BlocRepo, revision: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let commit_hash = repo.resolve_revision(revision)?;
+    for (path, hash) in repo.read_tree(&commit_hash)?.iter() {
+        println!("100644 blob {}\t{}", hash, path);
+    }
+    Ok(())
+}
+
+/// `show-ref`: print every ref as `<hash> <refname>`, sorted by name, or
+/// only branches and/or tags. Returns whether any ref was printed.
+pub fn show_ref(repo: &BlocRepo, heads: bool, tags: bool) -> io::Result<bool> {
+    let names = refsort::ref_names(&repo.refs_dir())?;
+    let mut found = false;
+    for name in names {
+        let shown = (!heads && !tags) ||
+            (heads && name.starts_with("heads/")) ||
+            (tags && name.starts_with("tags/"));
+        if shown {
+            let hash = fs::read_to_string(repo.refs_dir().join(&name))?;
+            println!("{} refs/{}", hash.trim(), name);
+            found = true;
+        }
+    }
+    Ok(found)
+}
+
+/// `symbolic-ref HEAD [<ref>]`: print the ref HEAD points to (just the
+/// branch name with `short`), or repoint HEAD at `target`. Only HEAD is a
+/// symbolic ref, and the working tree is left alone.
+pub fn symbolic_ref(repo: &BlocRepo, name: &str, target: Option<&str>, short: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if name != "HEAD" {
+        return Err(format!("'{}' is not a symbolic ref; only HEAD is", name).into());
+    }
+
+    let Some(target) = target else {
+        let head_content = fs::read_to_string(repo.head_path())?;
+        let target = head_content.trim().strip_prefix("ref: ").ok_or("HEAD is not a symbolic ref (it is detached)")?;
+        match target.strip_prefix("refs/heads/") {
+            Some(branch) if short => println!("{}", branch),
+            _ => println!("{}", target),
+        }
+        return Ok(());
+    };
+
+    // Everything reading HEAD expects it to name a branch
+    match target.strip_prefix("refs/heads/") {
+        Some(branch) if branches::is_valid_branch_name(branch) => {}
+        Some(_) => return Err(format!("'{}' is not a valid branch name", target).into()),
+        None => return Err(format!("Refusing to point HEAD outside of refs/heads/: '{}'", target).into()),
+    }
+    fs::write(repo.head_path(), format!("ref: {}\n", target))?;
+    Ok(())
+}
+
+/// What `cat-file` prints about a single object.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CatFileMode {
+    Type,
+    Size,
+    Contents,
+}
+
+/// Print the type, size or contents of the object `name` names: a full or
+/// abbreviated object hash, or any revision.
+pub fn cat_file(repo: &BlocRepo, name: &str, mode: CatFileMode) -> Result<(), Box<dyn std::error::Error>> {
+    let hash = match repo.resolve_object(name) {
+        Ok(hash) => hash,
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => return Err(e.into()),
+        Err(_) => repo.resolve_revision(name)?,
+    };
+    let data = repo.read_object(&hash)?;
+    match mode {
+        CatFileMode::Type => println!("{}", repo.object_type(&hash)?.name()),
+        CatFileMode::Size => println!("{}", data.len()),
+        CatFileMode::Contents => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Read object hashes (full or abbreviated) from stdin, one per line, and
+/// print each object as `<hash> <type> <size>`, its contents and a newline;
+/// unknown ids print `<id> missing` and abbreviations matching several
+/// objects `<id> ambiguous`.
+pub fn cat_file_batch(repo: &BlocRepo) -> io::Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let id = line.trim();
+        if id.is_empty() {
+            continue;
+        }
+        let hash = match repo.resolve_object(id) {
+            Ok(hash) => hash,
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                writeln!(out, "{} ambiguous", id)?;
+                continue;
+            }
+            Err(_) => {
+                writeln!(out, "{} missing", id)?;
+                continue;
+            }
+        };
+        // An object that resolves but can't be read is reported like an
+        // unknown one, so the rest of the batch still gets answered
+        let (data, object_type) = match repo.read_object(&hash).and_then(|data| Ok((data, repo.object_type(&hash)?))) {
+            Ok(object) => object,
+            Err(_) => {
+                writeln!(out, "{} missing", id)?;
+                continue;
+            }
+        };
+        writeln!(out, "{} {} {}", hash, object_type.name(), data.len())?;
+        out.write_all(&data)?;
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+/// Message for a commit meant to be folded into `revision` by
+/// `rebase -i --autosquash`: `fixup! <subject>` (or `squash! <subject>`
+/// followed by `extra` when given).
+pub fn autosquash_message(repo: &BlocRepo, revision: &str, squash: bool, extra: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    let target = repo.read_commit(&repo.resolve_revision(revision)?)?;
+    let subject = target.message.lines().next().unwrap_or_default();
+    let mut message = format!("{}! {}", if squash { "squash" } else { "fixup" }, subject);
+    if let Some(extra) = extra {
+        message.push_str("\n\n");
+        message.push_str(extra);
+    }
+    Ok(message)
+}
+
+pub fn commit(repo: &mut BlocRepo, message: Option<&str>, author: Option<&str>, sign: bool) -> Result<(), Box<dyn std::error::Error>> {
+    commit_as(repo, message, author, sign, None)
+}
+
+/// Commit like `commit`, logging the update in the reflogs as
+/// `<reflog_action>: <subject>` instead of the usual `commit: <subject>`.
+pub fn commit_as(
+    repo: &mut BlocRepo,
+    message: Option<&str>,
+    author: Option<&str>,
+    sign: bool,
+    reflog_action: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let signing_key = if sign {
+        Some(repo.config.user.signingkey.clone().ok_or(
+            "No signing key configured (set one with 'bloc config set user.signingkey <secret>')"
+        )?)
+    } else {
+        None
+    };
+
+    // The committer is always the configured identity; --author only changes the author
+    let author = match author {
+        Some(author) => {
+            let (name, email) = parse_signature(author).ok_or_else(|| {
+                format!("Invalid author '{}': expected the form 'Name <email>'", author)
+            })?;
+            format!("{} <{}>", name, email)
+        }
+        None => repo.get_author_signature(),
+    };
+
+    let mut conflicted: Vec<&String> = repo.index.entries
+        .iter()
+        .filter(|(_, entry)| entry.conflicted)
+        .map(|(path, _)| path)
+        .collect();
+    if !conflicted.is_empty() {
+        conflicted.sort();
+        println!("{}: {}", 
+                "Cannot commit".bright_red().bold(), 
+                "the following files have unresolved conflicts".bright_red());
+        for path in conflicted {
+            println!("  {}", path.bright_red());
+        }
+        println!("{}", "Fix them and mark them resolved with 'bloc add <file>'".bright_yellow());
+        return Ok(());
+    }
+
+    // A merge in progress is concluded even when it changed nothing
+    let merge_head = repo.merge_head()?;
+    if repo.index.entries.is_empty() && merge_head.is_none() {
         println!("{}", "Nothing to commit (no files in staging area)".bright_yellow());
         return Ok(());
     }
+
+    let message = match message {
+        Some(message) => message.to_string(),
+        None => match repo.merge_message()? {
+            Some(message) => message,
+            None => {
+                let path = repo.bloc_dir.join("COMMIT_EDITMSG");
+                let message = editor::edit_message(repo.config.core.editor.as_deref(), &path, "")?;
+                if message.is_empty() {
+                    return Err("Aborting commit due to empty commit message".into());
+                }
+                message
+            }
+        },
+    };
     
-    // Get current head
-    let head_path = repo.bloc_dir.join("refs").join("heads").join(&repo.get_current_branch()?);
-    let parent = if head_path.exists() {
-        Some(fs::read_to_string(&head_path)?.trim().to_string())
+    // Get current head; on a detached HEAD the commit moves HEAD itself
+    let detached = repo.detached_head()?.is_some();
+    let head_path = if detached {
+        repo.head_path()
     } else {
-        None
+        repo.refs_dir().join("heads").join(repo.get_current_branch()?)
+    };
+    let parent = repo.head_commit()?;
+    
+    // Start from the parent's files so the new tree is a full snapshot
+    let mut tree = match &parent {
+        Some(parent_hash) => Rc::unwrap_or_clone(repo.read_tree(parent_hash)?),
+        None => BTreeMap::new(),
     };
+    for (path, entry) in &repo.index.entries {
+        match entry.staged_hash() {
+            Some(hash) => tree.insert(path.clone(), hash.clone()),
+            None => tree.remove(path),
+        };
+    }
     
     // Create commit object
-    let commit = Commit {
-        message: message.to_string(),
-        author: repo.config.user.name.clone(),
-        committer: repo.config.user.email.clone(),
+    let mut commit = Commit {
+        message: message.clone(),
+        author,
+        committer: repo.get_author_signature(),
         timestamp: Utc::now(),
         parent,
-        tree: serialize_tree(&repo.index)?,
+        merge_parents: merge_head.into_iter().collect(),
+        tree: serialize_tree(&tree),
+        signature: None,
     };
+    if let Some(key) = &signing_key {
+        commit.signature = Some(signing::sign_commit(&commit, key)?);
+    }
     
-    // Serialize and hash the commit
+    // Serialize and store the commit
     let commit_json = serde_json::to_string_pretty(&commit)?;
-    let mut hasher = Sha256::new();
-    hasher.update(commit_json.as_bytes());
-    let commit_hash = format!("{:x}", hasher.finalize());
-    
-    // Store commit object
-    let objects_dir = repo.bloc_dir.join("objects");
-    let commit_dir = objects_dir.join(&commit_hash[..2]);
-    fs::create_dir_all(&commit_dir)?;
-    let commit_path = commit_dir.join(&commit_hash[2..]);
-    fs::write(&commit_path, commit_json.as_bytes())?;
+    let commit_hash = repo.write_object(commit_json.as_bytes())?;
     
     // Update HEAD
     fs::write(&head_path, &commit_hash)?;
+    let action = match reflog_action {
+        Some(action) => action,
+        None if commit.parent.is_none() => "commit (initial)",
+        None if !commit.merge_parents.is_empty() => "commit (merge)",
+        None => "commit",
+    };
+    let reflog_message = format!("{}: {}", action, message);
+    if detached {
+        repo.append_reflog("HEAD", commit.parent.as_deref(), &commit_hash, &reflog_message)?;
+    } else {
+        repo.record_ref_update(&repo.get_current_branch()?, commit.parent.as_deref(), &commit_hash, &reflog_message)?;
+    }
     
     // Clear the index
     repo.index.entries.clear();
     repo.index.save()?;
+    repo.clear_merge_state()?;
     
     println!("{} {} {}", 
              "Committed".bright_green().bold(), 
              &commit_hash[..8].bright_yellow(), 
-             message.white());
+             message.lines().next().unwrap_or_default().white());
     
     Ok(())
 }
 
-fn serialize_tree(index: &crate::objects::Index) -> Result<String, Box<dyn std::error::Error>> {
+/// Split a `Name <email>` signature into its name and email.
+fn parse_signature(signature: &str) -> Option<(&str, &str)> {
+    let (name, rest) = signature.trim().split_once('<')?;
+    let email = rest.strip_suffix('>')?;
+    let name = name.trim();
+    if name.is_empty() || email.is_empty() || email.contains(['<', '>']) || email.trim() != email {
+        return None;
+    }
+    Some((name, email))
+}
+
+pub fn serialize_tree(tree: &BTreeMap<String, String>) -> String {
     let mut tree_entries = Vec::new();
     
-    for (path, entry) in &index.entries {
-        tree_entries.push(format!("{}:{}", path, entry.hash));
+    for (path, hash) in tree {
+        tree_entries.push(format!("{}:{}", path, hash));
     }
     
-    Ok(tree_entries.join("\n"))
+    tree_entries.join("\n")
 }
 
-pub fn log(repo: &BlocRepo, oneline: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let current_branch = repo.get_current_branch()?;
-    let head_path = repo.bloc_dir.join("refs").join("heads").join(&current_branch);
-    
-    if !head_path.exists() {
+/// Which commits `log` shows and how.
+#[derive(Default)]
+pub struct LogOptions {
+    pub oneline: bool,
+    pub show_signature: bool,
+    /// Start from every ref instead of HEAD
+    pub all: bool,
+    /// Start from every branch tip
+    pub branches: bool,
+    /// Start from every tag
+    pub tags: bool,
+    /// Show at most this many commits, counting from the newest
+    pub max_count: Option<usize>,
+    /// Show the selected commits oldest first
+    pub reverse: bool,
+}
+
+/// Show the history reachable from the selected starting points. Returns
+/// `false` when a starting point or a parent object could not be read; the
+/// end of a shallow clone's history is not counted as missing.
+pub fn log(repo: &BlocRepo, options: &LogOptions) -> Result<bool, Box<dyn std::error::Error>> {
+    let head = repo.head_commit()?.map(|hash| ("HEAD".to_string(), hash));
+    let mut tips = Vec::new();
+    if options.all {
+        tips.extend(ref_tips(repo, "")?);
+        tips.extend(head);
+    } else {
+        if options.branches {
+            tips.extend(ref_tips(repo, "heads")?);
+        }
+        if options.tags {
+            tips.extend(ref_tips(repo, "tags")?);
+        }
+        if !options.branches && !options.tags {
+            tips.extend(head);
+        }
+    }
+
+    if tips.is_empty() {
         println!("{}", "No commits yet".bright_yellow());
-        return Ok(());
+        return Ok(true);
     }
-    
-    let mut commit_hash = fs::read_to_string(&head_path)?.trim().to_string();
-    
-    loop {
-        // Read commit object
-        let objects_dir = repo.bloc_dir.join("objects");
-        let commit_dir = objects_dir.join(&commit_hash[..2]);
-        let commit_path = commit_dir.join(&commit_hash[2..]);
-        
-        if !commit_path.exists() {
-            break;
+
+    // Walk newest first across all starting points, showing shared history once
+    let shallow = repo.shallow_commits()?;
+    let mut visited = BTreeSet::new();
+    let mut missing = Vec::new();
+    let mut broken_tips = false;
+    let mut pending = BinaryHeap::new();
+    for (name, hash) in tips {
+        if !visited.insert(hash.clone()) {
+            continue;
         }
+        match repo.read_commit(&hash) {
+            Ok(commit) => pending.push((commit.timestamp, hash)),
+            Err(_) => {
+                broken_tips = true;
+                println!("{}: {} {}",
+                        "Warning".bright_yellow().bold(),
+                        "ignoring broken ref".bright_yellow(),
+                        name.bright_red());
+            }
+        }
+    }
+
+    // Collect the newest commits first so a limit applies before any reversal
+    let limit = options.max_count.unwrap_or(usize::MAX);
+    let mut shown = Vec::new();
+    while shown.len() < limit && let Some((_, commit_hash)) = pending.pop() {
+        let commit = repo.read_commit(&commit_hash)?;
+
+        // A shallow clone's history ends here even though the commit has parents
+        if shown.len() + 1 < limit && !shallow.contains(&commit_hash) {
+            for parent in commit.parents() {
+                if !visited.insert(parent.clone()) {
+                    continue;
+                }
+                match repo.read_commit(parent) {
+                    Ok(parent_commit) => pending.push((parent_commit.timestamp, parent.clone())),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => missing.push((parent.clone(), commit_hash.clone())),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        shown.push((commit_hash, commit));
+    }
+    if options.reverse {
+        shown.reverse();
+    }
+
+    for (commit_hash, commit) in &shown {
+        let signature_note = if options.show_signature {
+            signature_note(repo, commit)?
+        } else {
+            None
+        };
         
-        let commit_json = fs::read_to_string(&commit_path)?;
-        let commit: Commit = serde_json::from_str(&commit_json)?;
-        
-        if oneline {
-            println!("{} {}", 
-                    commit_hash[..8].bright_yellow(), 
-                    commit.message.white());
+        if options.oneline {
+            let subject = commit.message.lines().next().unwrap_or_default();
+            match signature_note {
+                Some(note) => println!("{} {} {}", 
+                                      commit_hash[..8].bright_yellow(), 
+                                      note,
+                                      subject.white()),
+                None => println!("{} {}", 
+                                commit_hash[..8].bright_yellow(), 
+                                subject.white()),
+            }
         } else {
-            println!("{} {}", "commit".bright_yellow().bold(), commit_hash.bright_yellow());
-            println!("{}: {} <{}>", "Author".bright_blue(), commit.author.white(), commit.committer.white());
-            println!("{}: {}", "Date".bright_blue(), commit.timestamp.format("%a %b %d %H:%M:%S %Y %z").to_string().white());
-            println!();
-            println!("    {}", commit.message.white());
-            println!();
+            print_commit_header(commit_hash, commit, signature_note);
         }
-        
-        // Move to parent commit
-        if let Some(parent) = commit.parent {
-            commit_hash = parent;
+    }
+
+    for (parent, child) in &missing {
+        println!("{}: {} {} {} {}",
+                "Warning".bright_yellow().bold(),
+                "history is truncated due to a missing object:".bright_yellow(),
+                parent[..8].bright_red(),
+                "parent of".bright_yellow(),
+                child[..8].bright_yellow());
+    }
+    Ok(missing.is_empty() && !broken_tips)
+}
+
+/// Every ref under `refs/<namespace>` by full name, with its commit hash.
+fn ref_tips(repo: &BlocRepo, namespace: &str) -> io::Result<Vec<(String, String)>> {
+    let refs_dir = repo.refs_dir();
+    let mut tips = Vec::new();
+    for entry in WalkDir::new(refs_dir.join(namespace)).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            let name = entry.path().strip_prefix(&refs_dir).unwrap_or(entry.path());
+            let name = format!("refs/{}", name.to_string_lossy().replace('\\', "/"));
+            tips.push((name, repo.peel_to_commit(fs::read_to_string(entry.path())?.trim())?));
+        }
+    }
+    Ok(tips)
+}
+
+/// What `diff` compares and how it reports the result.
+pub struct DiffOptions {
+    /// Compare against the staging area instead of the working tree
+    pub staged: bool,
+    pub find_renames: Option<u8>,
+    /// Highlight changed words within lines instead of whole lines
+    pub word_diff: Option<diff::WordDiff>,
+    /// Print nothing; only the return value says whether anything differs
+    pub quiet: bool,
+}
+
+/// Show changes between two snapshots, chosen by how many commits are
+/// given: none compares the staging area with the working tree (or HEAD
+/// with the staging area when `staged`), one compares that commit with the
+/// working tree (or staging area), and two compare the commits. Returns
+/// whether there were any differences.
+pub fn diff(repo: &BlocRepo, commits: &[String], options: &DiffOptions) -> Result<bool, Box<dyn std::error::Error>> {
+    let (old_tree, new_tree) = match commits {
+        [old, new] => {
+            if options.staged {
+                return Err("--staged compares with the staging area and cannot be used with two commits".into());
+            }
+            (repo.read_tree(&repo.resolve_revision(old)?)?, repo.read_tree(&repo.resolve_revision(new)?)?)
+        }
+        [commit] => {
+            let commit_tree = repo.read_tree(&repo.resolve_revision(commit)?)?;
+            let new_tree = if options.staged {
+                repo.staged_tree()?
+            } else {
+                let mut tracked = repo.staged_tree()?;
+                tracked.extend(commit_tree.iter().map(|(path, hash)| (path.clone(), hash.clone())));
+                working_tree(repo, tracked)?
+            };
+            (commit_tree, Rc::new(new_tree))
+        }
+        [] if options.staged => (head_tree(repo)?, Rc::new(repo.staged_tree()?)),
+        [] => {
+            let staged_tree = repo.staged_tree()?;
+            let working_tree = working_tree(repo, staged_tree.clone())?;
+            let sparse_patterns = repo.sparse_patterns();
+            let old_tree = staged_tree
+                .into_iter()
+                .filter(|(path, _)| in_sparse_checkout(sparse_patterns.as_deref(), path))
+                .collect();
+            (Rc::new(old_tree), Rc::new(working_tree))
+        }
+        _ => return Err("diff takes at most two commits".into()),
+    };
+
+    // Working-tree contents are not in the object store yet
+    let read = |path: &str, hash: &str| {
+        if repo.has_object(hash)? { repo.read_object(hash) } else { fs::read(path).map(Rc::new) }
+    };
+
+    let changes = diff::tree_changes(&old_tree, &new_tree, options.find_renames, read)?;
+    if !options.quiet {
+        let colors = diff::DiffColors::from_config(&repo.config.color.diff);
+        print_patch(&changes, &old_tree, &new_tree, read, options.word_diff, &colors)?;
+    }
+    Ok(!changes.is_empty())
+}
+
+/// The working tree's version of each of the `tracked` paths that is
+/// present on disk, skipping paths outside the sparse checkout.
+fn working_tree(repo: &BlocRepo, tracked: BTreeMap<String, String>) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        return Err("Cannot diff the working tree of a bare repository".into());
+    }
+    let sparse_patterns = repo.sparse_patterns();
+    Ok(tracked
+        .into_keys()
+        .filter(|path| in_sparse_checkout(sparse_patterns.as_deref(), path))
+        .filter_map(|path| repo.working_file_hash(&path).map(|hash| (path, hash)))
+        .collect())
+}
+
+/// List the files changed by a commit relative to its parent, or between
+/// two commits, one `<status>\t<path>` line each. With `find_renames`,
+/// renames are listed as `R<score>\t<old>\t<new>`.
+pub fn diff_tree(repo: &BlocRepo, revision: &str, other: Option<&str>, find_renames: Option<u8>, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (old_tree, new_tree) = match other {
+        Some(other) => (repo.read_tree(&repo.resolve_revision(revision)?)?, repo.read_tree(&repo.resolve_revision(other)?)?),
+        None => {
+            let commit_hash = repo.resolve_revision(revision)?;
+            let parent_tree = match &repo.read_commit(&commit_hash)?.parent {
+                Some(parent) => repo.read_tree(parent)?,
+                None => Rc::default(),
+            };
+            (parent_tree, repo.read_tree(&commit_hash)?)
+        }
+    };
+
+    let changes = diff::tree_changes(&old_tree, &new_tree, find_renames, |_, hash| repo.read_object(hash))?;
+    if json {
+        let records = change_records(&changes, &old_tree, &new_tree);
+        println!("{}", serde_json::to_string_pretty(&records)?);
+    } else {
+        print_name_status(&changes);
+    }
+    Ok(())
+}
+
+/// How `show` presents a commit's changes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ShowFormat {
+    /// The full patch
+    Patch,
+    /// A per-file histogram of added and removed lines
+    Stat,
+    /// A status letter and path per file
+    NameStatus,
+    /// Commit metadata and change records as JSON, for tools
+    Json,
+}
+
+/// One changed file in `--json` output.
+#[derive(Serialize)]
+struct ChangeRecord<'a> {
+    /// `A`, `D`, `M` or `R`
+    status: &'static str,
+    path: &'a str,
+    /// The previous path of a renamed file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_path: Option<&'a str>,
+    old_hash: Option<&'a str>,
+    new_hash: Option<&'a str>,
+}
+
+/// A commit and its changes in `show --json` output.
+#[derive(Serialize)]
+struct CommitRecord<'a> {
+    hash: &'a str,
+    parents: Vec<&'a String>,
+    author: &'a str,
+    committer: &'a str,
+    timestamp: DateTime<Utc>,
+    message: &'a str,
+    changes: Vec<ChangeRecord<'a>>,
+}
+
+fn change_records<'a>(
+    changes: &'a [diff::TreeChange],
+    old_tree: &'a BTreeMap<String, String>,
+    new_tree: &'a BTreeMap<String, String>,
+) -> Vec<ChangeRecord<'a>> {
+    changes
+        .iter()
+        .map(|change| {
+            let (old_path, new_path) = change.sides();
+            let status = match change {
+                diff::TreeChange::Added(_) => "A",
+                diff::TreeChange::Deleted(_) => "D",
+                diff::TreeChange::Modified(_) => "M",
+                diff::TreeChange::Renamed { .. } => "R",
+            };
+            ChangeRecord {
+                status,
+                path: change.path(),
+                old_path: old_path.filter(|_| status == "R"),
+                old_hash: old_path.and_then(|path| old_tree.get(path)).map(String::as_str),
+                new_hash: new_path.and_then(|path| new_tree.get(path)).map(String::as_str),
+            }
+        })
+        .collect()
+}
+
+/// Show a commit's metadata and its changes against its first parent, or
+/// with `<revision>:<path>` the content of a file at that commit.
+pub fn show(repo: &BlocRepo, target: &str, format: ShowFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some((revision, path)) = target.split_once(':') {
+        // An empty revision names the staged version
+        let tree = if revision.is_empty() {
+            Rc::new(repo.staged_tree()?)
         } else {
-            break;
+            repo.read_tree(&repo.resolve_revision(revision)?)?
+        };
+        let hash = tree.get(path).ok_or_else(|| format!("Path '{}' does not exist in '{}'", path, revision))?;
+        io::Write::write_all(&mut io::stdout(), &repo.read_object(hash)?)?;
+        return Ok(());
+    }
+
+    let commit_hash = repo.resolve_revision(target)?;
+    let commit = repo.read_commit(&commit_hash)?;
+    if format != ShowFormat::Json {
+        print_commit_header(&commit_hash, &commit, None);
+    }
+
+    let parent_tree = match &commit.parent {
+        Some(parent) => repo.read_tree(parent)?,
+        None => Rc::default(),
+    };
+    let tree = repo.read_tree(&commit_hash)?;
+    let read = |_: &str, hash: &str| repo.read_object(hash);
+    let changes = diff::tree_changes(&parent_tree, &tree, Some(diff::DEFAULT_RENAME_THRESHOLD), read)?;
+
+    match format {
+        ShowFormat::Patch => {
+            let colors = diff::DiffColors::from_config(&repo.config.color.diff);
+            print_patch(&changes, &parent_tree, &tree, read, None, &colors)?
+        }
+        ShowFormat::Stat => print_stat(&changes, &parent_tree, &tree, read)?,
+        ShowFormat::NameStatus => print_name_status(&changes),
+        ShowFormat::Json => {
+            let record = CommitRecord {
+                hash: &commit_hash,
+                parents: commit.parents().collect(),
+                author: &commit.author,
+                committer: &commit.committer,
+                timestamp: commit.timestamp,
+                message: &commit.message,
+                changes: change_records(&changes, &parent_tree, &tree),
+            };
+            println!("{}", serde_json::to_string_pretty(&record)?);
         }
     }
-    
     Ok(())
 }
 
-pub fn status(repo: &BlocRepo) -> Result<(), Box<dyn std::error::Error>> {
-    let current_branch = repo.get_current_branch()?;
-    println!("{} {}", "On branch".bright_blue(), current_branch.bright_cyan().bold());
+/// Print a commit's hash, parents (for merges), author, date and message.
+fn print_commit_header(commit_hash: &str, commit: &Commit, signature_note: Option<ColoredString>) {
+    println!("{} {}", "commit".bright_yellow().bold(), commit_hash.bright_yellow());
+    if let Some(note) = signature_note {
+        println!("{}", note);
+    }
+    if !commit.merge_parents.is_empty() {
+        let parents: Vec<&str> = commit.parents().map(|parent| &parent[..8]).collect();
+        println!("{}: {}", "Merge".bright_blue(), parents.join(" ").white());
+    }
+    println!("{}: {}", "Author".bright_blue(), commit.author_signature().white());
+    println!("{}: {}", "Date".bright_blue(), commit.timestamp.format("%a %b %d %H:%M:%S %Y %z").to_string().white());
+    println!();
+    for line in commit.message.lines() {
+        println!("    {}", line.white());
+    }
+    println!();
+}
+
+/// Print the full patch for each change; `read` loads a blob by path and hash.
+fn print_patch<F>(
+    changes: &[diff::TreeChange],
+    old_tree: &BTreeMap<String, String>,
+    new_tree: &BTreeMap<String, String>,
+    read: F,
+    word_diff: Option<diff::WordDiff>,
+    colors: &diff::DiffColors,
+) -> io::Result<()>
+where
+    F: Fn(&str, &str) -> io::Result<Rc<Vec<u8>>>,
+{
+    for change in changes {
+        let (old_path, new_path) = change.sides();
+        let old = old_path.map(|path| read(path, &old_tree[path]).map(|content| (path, content))).transpose()?;
+        let new = new_path.map(|path| read(path, &new_tree[path]).map(|content| (path, content))).transpose()?;
+        let similarity = match change {
+            diff::TreeChange::Renamed { score, .. } => Some(*score),
+            _ => None,
+        };
+        diff::print_file_diff(
+            old.as_ref().map(|(path, content)| (*path, content.as_slice())),
+            new.as_ref().map(|(path, content)| (*path, content.as_slice())),
+            similarity,
+            word_diff,
+            colors,
+        );
+    }
+    Ok(())
+}
+
+/// Print one `<status>\t<path>` line per change, renames as
+/// `R<score>\t<old>\t<new>`.
+fn print_name_status(changes: &[diff::TreeChange]) {
+    for change in changes {
+        match change {
+            diff::TreeChange::Added(path) => println!("{}\t{}", "A".bright_green(), path),
+            diff::TreeChange::Deleted(path) => println!("{}\t{}", "D".bright_red(), path),
+            diff::TreeChange::Modified(path) => println!("{}\t{}", "M".bright_yellow(), path),
+            diff::TreeChange::Renamed { from, to, score } => {
+                println!("{}\t{}\t{}", format!("R{:03}", score).bright_cyan(), from, to)
+            }
+        }
+    }
+}
+
+/// Print a histogram of lines added and removed per file, then the totals.
+fn print_stat<F>(
+    changes: &[diff::TreeChange],
+    old_tree: &BTreeMap<String, String>,
+    new_tree: &BTreeMap<String, String>,
+    read: F,
+) -> io::Result<()>
+where
+    F: Fn(&str, &str) -> io::Result<Rc<Vec<u8>>>,
+{
+    const MAX_BAR_WIDTH: usize = 50;
+
+    // (name, Some((added, removed)) or None for binary files)
+    let mut rows = Vec::new();
+    for change in changes {
+        let (old_path, new_path) = change.sides();
+        let old = old_path.map(|path| read(path, &old_tree[path])).transpose()?.unwrap_or_default();
+        let new = new_path.map(|path| read(path, &new_tree[path])).transpose()?.unwrap_or_default();
+        let name = match change {
+            diff::TreeChange::Renamed { from, to, .. } => format!("{} => {}", from, to),
+            _ => change.path().to_string(),
+        };
+        let counts = if diff::is_binary(&old) || diff::is_binary(&new) {
+            None
+        } else {
+            Some(diff::line_stats(&old, &new))
+        };
+        rows.push((name, counts));
+    }
+
+    let name_width = rows.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+    let most_changed = rows.iter().filter_map(|(_, counts)| counts.map(|(a, r)| a + r)).max().unwrap_or(0);
+    let count_width = most_changed.to_string().len();
+    let (mut total_added, mut total_removed) = (0, 0);
+    for (name, counts) in &rows {
+        let Some((added, removed)) = *counts else {
+            println!(" {:<name_width$} | {:>count_width$}", name, "Bin");
+            continue;
+        };
+        total_added += added;
+        total_removed += removed;
+
+        let (mut plus, mut minus) = (added, removed);
+        if most_changed > MAX_BAR_WIDTH {
+            // Scale down, but keep at least one mark for any change
+            plus = if added > 0 { (added * MAX_BAR_WIDTH / most_changed).max(1) } else { 0 };
+            minus = if removed > 0 { (removed * MAX_BAR_WIDTH / most_changed).max(1) } else { 0 };
+        }
+        println!(" {:<name_width$} | {:>count_width$} {}{}",
+                name,
+                added + removed,
+                "+".repeat(plus).bright_green(),
+                "-".repeat(minus).bright_red());
+    }
+
+    println!(" {} {} changed, {} {}, {} {}",
+            rows.len(),
+            if rows.len() == 1 { "file" } else { "files" },
+            total_added,
+            if total_added == 1 { "insertion(+)" } else { "insertions(+)" },
+            total_removed,
+            if total_removed == 1 { "deletion(-)" } else { "deletions(-)" });
+    Ok(())
+}
+
+/// Check that the working tree matches the index and the index matches
+/// HEAD. Returns whether everything is in sync.
+pub fn verify(repo: &BlocRepo) -> Result<bool, Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        println!("{}", "Cannot verify a bare repository".bright_red().bold());
+        return Ok(false);
+    }
+
+    let head_tree = head_tree(repo)?;
+    let staged_tree = repo.staged_tree()?;
+
+    let sparse_patterns = repo.sparse_patterns();
+    let mut working_mismatches = Vec::new();
+    for (path, hash) in &staged_tree {
+        if !in_sparse_checkout(sparse_patterns.as_deref(), path) {
+            continue;
+        }
+        match repo.working_file_hash(path) {
+            Some(working_hash) if &working_hash == hash => {}
+            Some(_) => working_mismatches.push(("modified", path)),
+            None => working_mismatches.push(("deleted", path)),
+        }
+    }
+
+    let mut index_mismatches = Vec::new();
+    for (path, entry) in &repo.index.entries {
+        match (head_tree.get(path), entry.staged_hash()) {
+            (Some(head_hash), Some(hash)) if head_hash == hash => {}
+            (_, None) => index_mismatches.push(("deleted", path)),
+            (Some(_), _) => index_mismatches.push(("modified", path)),
+            (None, _) => index_mismatches.push(("new file", path)),
+        }
+    }
+    index_mismatches.sort();
+
+    if working_mismatches.is_empty() && index_mismatches.is_empty() {
+        println!("{}", "Working tree, index and HEAD are in sync".bright_green());
+        return Ok(true);
+    }
+
+    if !working_mismatches.is_empty() {
+        println!("{}", "Working tree differs from index:".bright_red().bold());
+        for (kind, path) in &working_mismatches {
+            println!("  {}: {}", kind.bright_red(), path.white());
+        }
+    }
+    if !index_mismatches.is_empty() {
+        println!("{}", "Index differs from HEAD:".bright_yellow().bold());
+        for (kind, path) in &index_mismatches {
+            println!("  {}: {}", kind.bright_yellow(), path.white());
+        }
+    }
+
+    Ok(false)
+}
+
+/// Human-readable signature verification result for `log --show-signature`.
+fn signature_note(repo: &BlocRepo, commit: &Commit) -> Result<Option<ColoredString>, Box<dyn std::error::Error>> {
+    if commit.signature.is_none() {
+        return Ok(None);
+    }
+    let Some(key) = &repo.config.user.signingkey else {
+        return Ok(Some("Cannot verify signature: no signing key configured".bright_yellow()));
+    };
+
+    Ok(match signing::verify_commit(commit, key)? {
+        SignatureStatus::Good => Some("Good signature".bright_green()),
+        SignatureStatus::Bad => Some("BAD signature".bright_red().bold()),
+        SignatureStatus::Unsigned => None,
+    })
+}
+
+/// Check a commit's signature. Returns whether the signature is valid.
+pub fn verify_commit(repo: &BlocRepo, revision: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let commit_hash = repo.resolve_revision(revision)?;
+    let commit = repo.read_commit(&commit_hash)?;
+    let key = repo.config.user.signingkey.as_ref().ok_or(
+        "No signing key configured (set one with 'bloc config set user.signingkey <secret>')"
+    )?;
+
+    match signing::verify_commit(&commit, key)? {
+        SignatureStatus::Good => {
+            println!("{} {} {}", 
+                    "Good signature".bright_green().bold(), 
+                    "on commit".bright_green(), 
+                    commit_hash[..8].bright_yellow());
+            Ok(true)
+        }
+        SignatureStatus::Bad => {
+            println!("{} {} {}", 
+                    "BAD signature".bright_red().bold(), 
+                    "on commit".bright_red(), 
+                    commit_hash[..8].bright_yellow());
+            Ok(false)
+        }
+        SignatureStatus::Unsigned => {
+            println!("{} {} {}", 
+                    "Commit".bright_yellow(), 
+                    commit_hash[..8].bright_yellow(), 
+                    "is not signed".bright_yellow());
+            Ok(false)
+        }
+    }
+}
+
+/// How `status` reports untracked files.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum UntrackedMode {
+    /// Don't look for untracked files
+    No,
+    /// Show untracked files, collapsing wholly untracked directories
+    Normal,
+    /// Show every untracked file individually
+    All,
+}
+
+/// Interrupted operations `status` reports, keyed by the marker file or
+/// directory under `.bloc` that records them, with hints on how to finish
+/// or abandon each one. Bloc has no commands for the ones other tools
+/// leave behind, so their hints say which marker to remove.
+const OPERATIONS_IN_PROGRESS: &[(&str, &str, &[&str])] = &[
+    ("rebase-merge", "You are currently rebasing.", &[
+        "use \"bloc rebase --continue\" to continue",
+        "use \"bloc rebase --abort\" to abort",
+    ]),
+    ("rebase-apply", "You are currently rebasing.", &[
+        "remove .bloc/rebase-apply to abandon it",
+    ]),
+    ("CHERRY_PICK_HEAD", "You are currently cherry-picking.", &[
+        "remove .bloc/CHERRY_PICK_HEAD once the pick is committed or abandoned",
+    ]),
+    ("BISECT_START", "You are currently bisecting.", &[
+        "remove the .bloc/BISECT_* files to end it",
+    ]),
+];
+
+/// Warn when the detached HEAD `hash` is reachable from no branch tip:
+/// its commits would be lost once HEAD moves elsewhere.
+fn warn_commits_off_branches(repo: &BlocRepo, hash: &str) -> io::Result<()> {
+    let heads_dir = repo.refs_dir().join("heads");
+    for entry in WalkDir::new(&heads_dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.path().is_file() {
+            let tip = fs::read_to_string(entry.path())?.trim().to_string();
+            if merge::ancestors(repo, &tip)?.contains(hash) {
+                return Ok(());
+            }
+        }
+    }
+
+    println!();
+    println!("{}", "Warning: the commits at HEAD are not on any branch and will be lost".bright_yellow().bold());
+    println!("{}", "on the next checkout unless a branch is created for them.".bright_yellow().bold());
+    println!("  {}", "(use \"bloc switch -c <name>\" to keep them on a new branch)".bright_black());
+    println!();
+    Ok(())
+}
+
+/// Tell the user about a merge, rebase, cherry-pick or bisect left in
+/// progress, and how to continue or abort it.
+fn print_operation_in_progress(repo: &BlocRepo) {
+    let mut reported = false;
+    if repo.bloc_dir.join("MERGE_HEAD").is_file() {
+        reported = true;
+        let conflicted = repo.index.entries.values().any(|entry| entry.conflicted);
+        println!();
+        if conflicted {
+            println!("{}", "You have unmerged paths.".bright_yellow().bold());
+            println!("  {}", "(fix conflicts, 'bloc add' them and run \"bloc commit\")".bright_black());
+        } else {
+            println!("{}", "All conflicts fixed but you are still merging.".bright_yellow().bold());
+            println!("  {}", "(use \"bloc commit\" to conclude merge)".bright_black());
+        }
+        println!("  {}", "(use \"bloc merge --abort\" to abort the merge)".bright_black());
+    }
+
+    for (marker, description, hints) in OPERATIONS_IN_PROGRESS {
+        if repo.bloc_dir.join(marker).exists() {
+            reported = true;
+            println!();
+            println!("{}", description.bright_yellow().bold());
+            for hint in *hints {
+                println!("  {}", format!("({})", hint).bright_black());
+            }
+            break;
+        }
+    }
+    if reported {
+        println!();
+    }
+}
+
+pub fn status(repo: &BlocRepo, untracked_mode: UntrackedMode) -> Result<(), Box<dyn std::error::Error>> {
+    match repo.detached_head()? {
+        Some(hash) => {
+            println!("{} {}", "HEAD detached at".bright_red(), hash[..8.min(hash.len())].bright_yellow().bold());
+            warn_commits_off_branches(repo, &hash)?;
+        }
+        None => {
+            let current_branch = repo.get_current_branch()?;
+            println!("{} {}", "On branch".bright_blue(), current_branch.bright_cyan().bold());
+        }
+    }
+    print_operation_in_progress(repo);
     
-    if repo.index.entries.is_empty() {
+    let staged_changes = diff::tree_changes(
+        &*head_tree(repo)?,
+        &repo.staged_tree()?,
+        Some(diff::DEFAULT_RENAME_THRESHOLD),
+        |_, hash| repo.read_object(hash),
+    )?;
+    if staged_changes.is_empty() {
         println!("{}", "No changes staged for commit".bright_green());
     } else {
         println!("{}", "Changes to be committed:".bright_green().bold());
-        for (path, _) in &repo.index.entries {
-            println!("  {}: {}", "new file".bright_green(), path.white());
+        for change in staged_changes {
+            let (kind, path) = match change {
+                diff::TreeChange::Added(path) => ("new file", path),
+                diff::TreeChange::Deleted(path) => ("deleted", path),
+                diff::TreeChange::Modified(path) => ("modified", path),
+                diff::TreeChange::Renamed { from, to, .. } => ("renamed", format!("{} -> {}", from, to)),
+            };
+            println!("  {}: {}", kind.bright_green(), path.white());
+        }
+    }
+
+    let mut unmerged: Vec<&String> = repo.index.entries
+        .iter()
+        .filter(|(_, entry)| entry.conflicted)
+        .map(|(path, _)| path)
+        .collect();
+    if !unmerged.is_empty() {
+        unmerged.sort();
+        println!();
+        println!("{}", "Unmerged paths:".bright_red().bold());
+        println!("  (use \"bloc add <file>...\" to mark resolution)");
+        for path in unmerged {
+            println!("  {}: {}", "both modified".bright_red(), path.bright_red());
         }
     }
     
     // Check for untracked files
-    let mut untracked = Vec::new();
+    let mut untracked = BTreeSet::new();
     
-    if !repo.is_bare {
-        for entry in WalkDir::new(".").into_iter().filter_map(|e| e.ok()) {
+    if !repo.is_bare && untracked_mode != UntrackedMode::No {
+        let tracked = repo.staged_tree()?;
+        let sparse_patterns = repo.sparse_patterns();
+
+        // Directories holding at least one tracked file
+        let tracked_dirs: BTreeSet<&str> = tracked
+            .keys()
+            .flat_map(|path| path.match_indices('/').map(move |(pos, _)| &path[..pos]))
+            .collect();
+
+        let mut walker = WalkDir::new(".").sort_by_file_name().into_iter();
+        while let Some(entry) = walker.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
             let path = entry.path();
-            if path.is_file() && !repo.should_ignore(path) {
-                let relative_path = if let Ok(rel_path) = path.strip_prefix(".") {
-                    rel_path.to_string_lossy().to_string()
-                } else {
-                    path.to_string_lossy().to_string()
-                };
-                
-                if !repo.index.entries.contains_key(&relative_path) {
-                    untracked.push(relative_path);
+            let relative_path = relative_path(path);
+
+            if entry.file_type().is_dir() {
+                if BlocRepo::is_internal_path(path) {
+                    walker.skip_current_dir();
+                } else if untracked_mode == UntrackedMode::Normal &&
+                          !relative_path.is_empty() &&
+                          !tracked_dirs.contains(relative_path.as_str()) {
+                    // Nothing below is tracked: summarize the directory instead of walking it
+                    if contains_untracked_file(repo, path, sparse_patterns.as_deref()) {
+                        untracked.insert(format!("{}/", relative_path));
+                    }
+                    walker.skip_current_dir();
                 }
+                continue;
+            }
+
+            if path.is_file() &&
+               !repo.should_ignore(path) &&
+               !tracked.contains_key(&relative_path) &&
+               in_sparse_checkout(sparse_patterns.as_deref(), &relative_path) {
+                untracked.insert(relative_path);
             }
         }
     }
@@ -259,3 +1713,83 @@ pub fn status(repo: &BlocRepo) -> Result<(), Box<dyn std::error::Error>> {
     
     Ok(())
 }
+
+/// Whether an untracked directory holds any file worth reporting.
+fn contains_untracked_file(repo: &BlocRepo, dir: &Path, sparse_patterns: Option<&[String]>) -> bool {
+    WalkDir::new(dir).into_iter().filter_map(|e| e.ok()).any(|entry| {
+        entry.path().is_file() &&
+            !repo.should_ignore(entry.path()) &&
+            in_sparse_checkout(sparse_patterns, &relative_path(entry.path()))
+    })
+}
+
+/// A walked path relative to the repository root, without the leading `./`.
+pub fn relative_path(path: &Path) -> String {
+    if let Ok(rel_path) = path.strip_prefix(".") {
+        rel_path.to_string_lossy().to_string()
+    } else {
+        path.to_string_lossy().to_string()
+    }
+}
+
+pub fn sparse_checkout_set(repo: &BlocRepo, patterns: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if patterns.is_empty() {
+        println!("{}", "No sparse-checkout patterns given".bright_yellow());
+        return Ok(());
+    }
+
+    let previous = repo.sparse_patterns();
+    fs::write(repo.bloc_dir.join("sparse-checkout"), patterns.join("\n") + "\n")?;
+    apply_sparse_checkout(repo, previous.as_deref(), Some(patterns))?;
+
+    println!("{} {}", 
+            "Sparse checkout set to".bright_green().bold(), 
+            patterns.join(", ").bright_cyan());
+    Ok(())
+}
+
+pub fn sparse_checkout_list(repo: &BlocRepo) {
+    match repo.sparse_patterns() {
+        Some(patterns) => {
+            for pattern in patterns {
+                println!("{}", pattern.white());
+            }
+        }
+        None => println!("{}", "Sparse checkout is not enabled".bright_yellow()),
+    }
+}
+
+pub fn sparse_checkout_disable(repo: &BlocRepo) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(previous) = repo.sparse_patterns() else {
+        println!("{}", "Sparse checkout is not enabled".bright_yellow());
+        return Ok(());
+    };
+
+    fs::remove_file(repo.bloc_dir.join("sparse-checkout"))?;
+    apply_sparse_checkout(repo, Some(&previous), None)?;
+
+    println!("{}", "Sparse checkout disabled".bright_green().bold());
+    Ok(())
+}
+
+/// Materialize files that entered the sparse checkout and remove clean
+/// files that left it. Locally modified files are kept with a warning.
+fn apply_sparse_checkout(repo: &BlocRepo, previous: Option<&[String]>, current: Option<&[String]>) -> Result<(), Box<dyn std::error::Error>> {
+    for (path, hash) in repo.staged_tree()? {
+        let (was_in, is_in) = (in_sparse_checkout(previous, &path), in_sparse_checkout(current, &path));
+        if is_in && (!was_in || !Path::new(&path).exists()) {
+            repo.checkout_file(&path, &hash)?;
+        } else if was_in && !is_in {
+            match repo.working_file_hash(&path) {
+                Some(working_hash) if working_hash != hash => {
+                    println!("{}: {} {}", 
+                            "Warning".bright_yellow().bold(), 
+                            path.bright_cyan(), 
+                            "has local changes and was left in place".bright_yellow());
+                }
+                _ => repo.remove_working_file(&path)?,
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,21 +1,64 @@
+use crate::editor;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use colored::*;
 
+/// Settings are resolved from the built-in defaults, then the global
+/// `~/.blocconfig`, then the repository's `.bloc/config`; each file only
+/// needs the keys it overrides.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BlocConfig {
     pub user: UserConfig,
     pub remotes: HashMap<String, RemoteConfig>,
     pub core: CoreConfig,
+    #[serde(default)]
+    pub gc: GcConfig,
+    #[serde(default)]
+    pub push: PushConfig,
+    #[serde(default)]
+    pub color: ColorConfig,
+    /// Upstream of each local branch, by branch name
+    #[serde(default)]
+    pub branches: HashMap<String, BranchConfig>,
+    /// Where each dotted key's value came from
+    #[serde(skip)]
+    origins: BTreeMap<String, ConfigOrigin>,
+    /// Defaults merged with the global config: what the repository file overrides
+    #[serde(skip)]
+    inherited: Value,
+    /// The repository config file as it was read
+    #[serde(skip)]
+    repo_layer: Option<Value>,
+}
+
+/// Where a configuration value was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigOrigin {
+    Default,
+    File(PathBuf),
+}
+
+impl fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigOrigin::Default => write!(f, "default"),
+            ConfigOrigin::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserConfig {
     pub name: String,
     pub email: String,
+    /// Shared secret used to sign and verify commits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signingkey: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,6 +72,76 @@ pub struct RemoteConfig {
 pub struct CoreConfig {
     pub bare: bool,
     pub default_branch: String,
+    /// Whether the filesystem treats paths differing only by case as the same file
+    #[serde(default)]
+    pub ignorecase: bool,
+    /// Editor for `config edit` and other interactive commands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub editor: Option<String>,
+    /// Hash function naming the repository's objects, recorded at init;
+    /// repositories without it use `sha256`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hashalgo: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PushConfig {
+    /// What `bloc push` without arguments pushes: `simple` (the current
+    /// branch to its upstream of the same name), `upstream` (the current
+    /// branch to its upstream), `current` (the current branch to the same
+    /// name) or `nothing`
+    pub default: String,
+}
+
+impl Default for PushConfig {
+    fn default() -> Self {
+        PushConfig { default: "simple".to_string() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ColorConfig {
+    #[serde(default)]
+    pub diff: DiffColorConfig,
+}
+
+/// Colors of `diff` and `show` output, each a color spec such as
+/// `bold red` or `brightgreen`; unset keys keep the built-in colors.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DiffColorConfig {
+    /// Added lines and words
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+    /// Removed lines and words
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    /// File headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<String>,
+}
+
+/// The remote branch a local branch pushes to and pulls from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BranchConfig {
+    pub remote: String,
+    pub merge: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GcConfig {
+    /// Approximate number of loose objects above which commits run
+    /// `gc --auto`; 0 disables automatic collection
+    pub auto: u64,
+    /// How old an unreachable object must be before `gc` prunes it
+    /// (e.g. 2.weeks.ago, 2024-01-31, now, never)
+    pub pruneexpire: String,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig { auto: 6700, pruneexpire: "2.weeks.ago".to_string() }
+    }
 }
 
 impl Default for BlocConfig {
@@ -37,38 +150,88 @@ impl Default for BlocConfig {
             user: UserConfig {
                 name: "Bloc User".to_string(),
                 email: "user@bloc.local".to_string(),
+                signingkey: None,
             },
             remotes: HashMap::new(),
             core: CoreConfig {
                 bare: false,
                 default_branch: "main".to_string(),
+                ignorecase: false,
+                editor: None,
+                hashalgo: None,
             },
+            gc: GcConfig::default(),
+            push: PushConfig::default(),
+            color: ColorConfig::default(),
+            branches: HashMap::new(),
+            origins: BTreeMap::new(),
+            inherited: Value::Null,
+            repo_layer: None,
         }
     }
 }
 
 impl BlocConfig {
     pub fn load() -> io::Result<Self> {
-        let config_path = if Path::new(".bloc").exists() {
-            ".bloc/config"
-        } else {
-            // Global config in user's home directory
-            return Ok(Self::default());
+        let mut merged = serde_json::to_value(Self::default())?;
+        let mut origins = BTreeMap::new();
+        for (key, _) in flatten(&merged) {
+            origins.insert(key, ConfigOrigin::Default);
+        }
+
+        if let Some(global_path) = global_config_path()
+            && let Some(layer) = read_layer(&global_path)? {
+            apply_layer(&mut merged, &layer, &ConfigOrigin::File(global_path), &mut origins);
+        }
+        let inherited = merged.clone();
+
+        let repo_path = repo_config_path();
+        let repo_layer = match &repo_path {
+            Some(path) => read_layer(path)?,
+            None => None,
         };
+        if let (Some(layer), Some(path)) = (&repo_layer, repo_path) {
+            apply_layer(&mut merged, layer, &ConfigOrigin::File(path), &mut origins);
+        }
 
-        if Path::new(config_path).exists() {
-            let content = fs::read_to_string(config_path)?;
-            let config: BlocConfig = serde_json::from_str(&content)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-            Ok(config)
-        } else {
-            Ok(Self::default())
+        let mut config: BlocConfig = serde_json::from_value(merged)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // A repository's hash algorithm is fixed when it is created: only its
+        // own file decides it, so changing the global setting never switches
+        // an existing repository to a different algorithm
+        config.core.hashalgo = repo_layer
+            .as_ref()
+            .and_then(|layer| layer.pointer("/core/hashalgo"))
+            .and_then(Value::as_str)
+            .map(String::from);
+        if config.core.hashalgo.is_none() {
+            origins.remove("core.hashalgo");
         }
+        config.origins = origins;
+        config.inherited = inherited;
+        config.repo_layer = repo_layer;
+        Ok(config)
     }
 
     pub fn save(&self) -> io::Result<()> {
-        let config_path = ".bloc/config";
-        let content = serde_json::to_string_pretty(self)?;
+        let path = repo_config_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Not a bloc repository"))?;
+        self.save_to(&path)
+    }
+
+    /// Write the repository's settings: every key the repository file
+    /// already set, plus any value that differs from what it would inherit.
+    /// `core.hashalgo` is never inherited, so it is always written.
+    pub fn save_to(&self, config_path: &Path) -> io::Result<()> {
+        let current = serde_json::to_value(self)?;
+        let mut inherited = self.inherited.clone();
+        if let Some(core) = inherited.get_mut("core").and_then(Value::as_object_mut) {
+            core.remove("hashalgo");
+        }
+        let layer = repo_overrides(&current, Some(&inherited), self.repo_layer.as_ref())
+            .unwrap_or_else(|| Value::Object(Map::new()));
+        let content = serde_json::to_string_pretty(&layer)?;
         fs::write(config_path, content)?;
         Ok(())
     }
@@ -124,15 +287,74 @@ impl BlocConfig {
         }
     }
 
+    /// The `core.hashalgo` new repositories are created with, from the
+    /// global config.
+    pub fn inherited_hashalgo(&self) -> Option<&str> {
+        self.inherited.pointer("/core/hashalgo").and_then(Value::as_str)
+    }
+
+    /// The value of a dotted key such as `user.name` or `remotes.origin.url`,
+    /// or `None` when it is not set.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let current = serde_json::to_value(self).ok()?;
+        flatten(&current).into_iter().find(|(name, _)| name == key).map(|(_, value)| value)
+    }
+
+    /// Print every setting as `key = value`, prefixed by where it came from.
+    pub fn show_config_origins(&self) {
+        let current = serde_json::to_value(self).unwrap_or_default();
+        for (key, value) in flatten(&current) {
+            let origin = self.origins.get(&key).cloned().unwrap_or(ConfigOrigin::Default);
+            let value = if is_secret(&key) { "(set)".to_string() } else { value };
+            println!("{}\t{} = {}", origin.to_string().bright_black(), key.bright_blue(), value.white());
+        }
+    }
+
     pub fn show_config(&self) {
         println!("{}:", "User Configuration".bright_green().bold());
         println!("  {}: {}", "name".bright_blue(), self.user.name.white());
         println!("  {}: {}", "email".bright_blue(), self.user.email.white());
+        if self.user.signingkey.is_some() {
+            println!("  {}: {}", "signingkey".bright_blue(), "(set)".white());
+        }
         
         println!("\n{}:", "Core Configuration".bright_green().bold());
         println!("  {}: {}", "bare".bright_blue(), self.core.bare.to_string().white());
         println!("  {}: {}", "default_branch".bright_blue(), self.core.default_branch.white());
+        println!("  {}: {}", "ignorecase".bright_blue(), self.core.ignorecase.to_string().white());
+        if let Some(editor) = &self.core.editor {
+            println!("  {}: {}", "editor".bright_blue(), editor.white());
+        }
+        if let Some(hashalgo) = &self.core.hashalgo {
+            println!("  {}: {}", "hashalgo".bright_blue(), hashalgo.white());
+        }
+
+        println!("\n{}:", "GC Configuration".bright_green().bold());
+        println!("  {}: {}", "auto".bright_blue(), self.gc.auto.to_string().white());
+        println!("  {}: {}", "pruneexpire".bright_blue(), self.gc.pruneexpire.white());
         
+        println!("\n{}:", "Push Configuration".bright_green().bold());
+        println!("  {}: {}", "default".bright_blue(), self.push.default.white());
+
+        let diff_colors = [("diff.new", &self.color.diff.new), ("diff.old", &self.color.diff.old), ("diff.meta", &self.color.diff.meta)];
+        if diff_colors.iter().any(|(_, spec)| spec.is_some()) {
+            println!("\n{}:", "Color Configuration".bright_green().bold());
+            for (name, spec) in diff_colors {
+                if let Some(spec) = spec {
+                    println!("  {}: {}", name.bright_blue(), spec.white());
+                }
+            }
+        }
+
+        if !self.branches.is_empty() {
+            println!("\n{}:", "Branch Upstreams".bright_green().bold());
+            let mut branches: Vec<_> = self.branches.iter().collect();
+            branches.sort_by_key(|(name, _)| *name);
+            for (name, upstream) in branches {
+                println!("  {}: {}/{}", name.bright_cyan(), upstream.remote.white(), upstream.merge.white());
+            }
+        }
+
         if !self.remotes.is_empty() {
             println!("\n{}:", "Remotes".bright_green().bold());
             for (name, remote) in &self.remotes {
@@ -141,3 +363,158 @@ impl BlocConfig {
         }
     }
 }
+
+/// Read a config value as a boolean: `true`/`yes`/`on`/`1` or
+/// `false`/`no`/`off`/`0`/empty, in any case.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// Read a config value as an integer, allowing a `k`, `m` or `g` suffix
+/// for multiples of 1024.
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, scale) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(scale)
+}
+
+/// Whether the setting `key` holds a secret that is never printed unasked.
+pub fn is_secret(key: &str) -> bool {
+    key == "user.signingkey"
+}
+
+/// The per-user config file, `~/.blocconfig`.
+pub fn global_config_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".blocconfig"))
+}
+
+/// The repository's own config file: `.bloc/config`, or `config` at the top
+/// of a bare repository. `None` outside a repository.
+pub fn repo_config_path() -> Option<PathBuf> {
+    if Path::new(".bloc").exists() {
+        Some(PathBuf::from(".bloc/config"))
+    } else if Path::new("HEAD").exists() && Path::new("config").exists() {
+        Some(PathBuf::from("config"))
+    } else {
+        None
+    }
+}
+
+/// Open a config file in the editor, keeping the edit only if the file
+/// still holds valid settings; otherwise the previous content is restored.
+pub fn edit_config_file(path: &Path, editor: Option<&str>) -> io::Result<()> {
+    let previous = if path.is_file() { Some(fs::read(path)?) } else { None };
+    if previous.is_none() {
+        fs::write(path, "{}\n")?;
+    }
+
+    // Only the edited file is checked, so a broken file in the other
+    // layer never blocks fixing this one
+    let result = editor::edit_file(editor, path)
+        .and_then(|_| match read_layer(path)? {
+            Some(layer) if !layer.is_object() => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: expected a JSON object", path.display()),
+            )),
+            Some(layer) => {
+                let mut merged = serde_json::to_value(BlocConfig::default())?;
+                merge(&mut merged, &layer);
+                serde_json::from_value::<BlocConfig>(merged)
+                    .map(|_| ())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+            }
+            None => Ok(()),
+        });
+
+    if let Err(e) = result {
+        match &previous {
+            Some(content) => fs::write(path, content)?,
+            None => fs::remove_file(path)?,
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn read_layer(path: &Path) -> io::Result<Option<Value>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)?;
+    let layer = serde_json::from_str(&content).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+    })?;
+    Ok(Some(layer))
+}
+
+/// Merge `layer` over `merged`, recording `origin` for every key it sets.
+fn apply_layer(merged: &mut Value, layer: &Value, origin: &ConfigOrigin, origins: &mut BTreeMap<String, ConfigOrigin>) {
+    merge(merged, layer);
+    for (key, _) in flatten(layer) {
+        origins.insert(key, origin.clone());
+    }
+}
+
+fn merge(base: &mut Value, layer: &Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                merge(base.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (base, layer) => *base = layer.clone(),
+    }
+}
+
+/// The parts of `current` the repository file must record.
+fn repo_overrides(current: &Value, inherited: Option<&Value>, stored: Option<&Value>) -> Option<Value> {
+    match current {
+        Value::Object(entries) => {
+            let layer: Map<String, Value> = entries
+                .iter()
+                .filter_map(|(key, value)| {
+                    let inherited = inherited.and_then(|inherited| inherited.get(key));
+                    let stored = stored.and_then(|stored| stored.get(key));
+                    repo_overrides(value, inherited, stored).map(|value| (key.clone(), value))
+                })
+                .collect();
+            (!layer.is_empty()).then_some(Value::Object(layer))
+        }
+        value if stored.is_some() || inherited != Some(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Dotted `section.key` names with their values, leaving out unset ones.
+fn flatten(value: &Value) -> Vec<(String, String)> {
+    fn walk(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+        match value {
+            Value::Object(entries) => {
+                let mut keys: Vec<&String> = entries.keys().collect();
+                keys.sort();
+                for key in keys {
+                    let name = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                    walk(&name, &entries[key], out);
+                }
+            }
+            Value::Null => {}
+            Value::String(s) => out.push((prefix.to_string(), s.clone())),
+            other => out.push((prefix.to_string(), other.to_string())),
+        }
+    }
+
+    let mut out = Vec::new();
+    walk("", value, &mut out);
+    out
+}
@@ -0,0 +1,41 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+/// Parse a date specification relative to now: `now`, `yesterday`,
+/// `<n>.<unit>.ago` (e.g. `2.weeks.ago`, `3.hours.ago`), an ISO date
+/// (`2024-01-31`), or an RFC 3339 timestamp.
+pub fn parse_date(spec: &str) -> Option<DateTime<Utc>> {
+    let spec = spec.trim();
+    let now = Utc::now();
+
+    match spec {
+        "now" => return Some(now),
+        "yesterday" => return now.checked_sub_signed(Duration::days(1)),
+        _ => {}
+    }
+
+    if let Some(relative) = spec.strip_suffix(".ago").or_else(|| spec.strip_suffix(" ago")) {
+        let (amount, unit) = relative.split_once(['.', ' '])?;
+        let amount: i64 = amount.parse().ok()?;
+        // Out-of-range amounts are an invalid date, not a panic
+        let duration = match unit.trim_end_matches('s') {
+            "second" => Duration::try_seconds(amount),
+            "minute" => Duration::try_minutes(amount),
+            "hour" => Duration::try_hours(amount),
+            "day" => Duration::try_days(amount),
+            "week" => Duration::try_weeks(amount),
+            "month" => amount.checked_mul(30).and_then(Duration::try_days),
+            "year" => amount.checked_mul(365).and_then(Duration::try_days),
+            _ => return None,
+        }?;
+        return now.checked_sub_signed(duration);
+    }
+
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(spec) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+
+    NaiveDate::parse_from_str(spec, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| datetime.and_utc())
+}
@@ -0,0 +1,726 @@
+use crate::config::DiffColorConfig;
+use colored::*;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// Lines of unchanged context shown around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// Past this many cells the LCS table is not built and the differing middle
+/// of the inputs is reported as replaced wholesale.
+const MAX_LCS_CELLS: usize = 16_000_000;
+
+/// Minimum similarity, in percent, for a deleted and an added file to be
+/// reported as a rename when no threshold is given.
+pub const DEFAULT_RENAME_THRESHOLD: u8 = 50;
+
+/// One step of an edit script, holding indices into the old and new input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// A run of edits with its surrounding context, as shown under one `@@` header.
+pub struct Hunk<'a> {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub edits: &'a [Edit],
+}
+
+/// The edit script turning `old` into `new`, from a longest common
+/// subsequence of the two after trimming their common prefix and suffix.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut edits: Vec<Edit> = (0..prefix).map(|i| Edit::Equal(i, i)).collect();
+
+    if (a.len() + 1).saturating_mul(b.len() + 1) > MAX_LCS_CELLS {
+        edits.extend((0..a.len()).map(|i| Edit::Delete(prefix + i)));
+        edits.extend((0..b.len()).map(|j| Edit::Insert(prefix + j)));
+    } else {
+        // lcs[i * width + j] is the LCS length of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                edits.push(Edit::Equal(prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if i < a.len() && (j == b.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                edits.push(Edit::Delete(prefix + i));
+                i += 1;
+            } else {
+                edits.push(Edit::Insert(prefix + j));
+                j += 1;
+            }
+        }
+    }
+
+    let old_tail = old.len() - suffix;
+    let new_tail = new.len() - suffix;
+    edits.extend((0..suffix).map(|k| Edit::Equal(old_tail + k, new_tail + k)));
+    edits
+}
+
+/// Group an edit script into hunks with `context` unchanged lines around
+/// each change; changes closer than twice the context share a hunk.
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk<'_>> {
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+        .map(|(pos, _)| pos)
+        .collect();
+
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for pos in changes {
+        let start = pos.saturating_sub(context);
+        let end = (pos + 1 + context).min(edits.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let (old_before, new_before) = line_counts(&edits[..start]);
+            let (old_len, new_len) = line_counts(&edits[start..end]);
+            Hunk {
+                old_start: if old_len == 0 { old_before } else { old_before + 1 },
+                old_len,
+                new_start: if new_len == 0 { new_before } else { new_before + 1 },
+                new_len,
+                edits: &edits[start..end],
+            }
+        })
+        .collect()
+}
+
+/// How many old and new lines a slice of the edit script covers.
+fn line_counts(edits: &[Edit]) -> (usize, usize) {
+    edits.iter().fold((0, 0), |(old, new), edit| match edit {
+        Edit::Equal(..) => (old + 1, new + 1),
+        Edit::Delete(_) => (old + 1, new),
+        Edit::Insert(_) => (old, new + 1),
+    })
+}
+
+/// How a path differs between two trees.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeChange {
+    Added(String),
+    Deleted(String),
+    Modified(String),
+    Renamed { from: String, to: String, score: u8 },
+}
+
+impl TreeChange {
+    /// The path the change is listed under: the new name for renames.
+    pub fn path(&self) -> &str {
+        match self {
+            TreeChange::Added(path) | TreeChange::Deleted(path) | TreeChange::Modified(path) => path,
+            TreeChange::Renamed { to, .. } => to,
+        }
+    }
+}
+
+impl TreeChange {
+    /// The path on the old and new side, `None` where the file is absent.
+    pub fn sides(&self) -> (Option<&str>, Option<&str>) {
+        match self {
+            TreeChange::Added(path) => (None, Some(path)),
+            TreeChange::Deleted(path) => (Some(path), None),
+            TreeChange::Modified(path) => (Some(path), Some(path)),
+            TreeChange::Renamed { from, to, .. } => (Some(from), Some(to)),
+        }
+    }
+}
+
+/// Compare two trees of path to blob hash. With a `rename_threshold`,
+/// deleted paths are paired with added ones: identical blobs first, then
+/// the most similar contents scoring at least the threshold. `read` loads
+/// a blob given its path and hash.
+pub fn tree_changes<F>(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+    rename_threshold: Option<u8>,
+    mut read: F,
+) -> io::Result<Vec<TreeChange>>
+where
+    F: FnMut(&str, &str) -> io::Result<Rc<Vec<u8>>>,
+{
+    let mut changes = Vec::new();
+    let mut deleted: Vec<&String> = Vec::new();
+    let mut added: Vec<&String> = Vec::new();
+    for (path, hash) in old {
+        match new.get(path) {
+            Some(new_hash) if new_hash == hash => {}
+            Some(_) => changes.push(TreeChange::Modified(path.clone())),
+            None => deleted.push(path),
+        }
+    }
+    added.extend(new.keys().filter(|path| !old.contains_key(*path)));
+
+    if let Some(threshold) = rename_threshold {
+        // Exact renames: the same blob under a new name
+        let mut by_hash: HashMap<&String, Vec<&String>> = HashMap::new();
+        for path in &deleted {
+            by_hash.entry(&old[*path]).or_default().push(path);
+        }
+        added.retain(|path| {
+            let Some(sources) = by_hash.get_mut(&new[*path]) else {
+                return true;
+            };
+            let Some(from) = sources.pop() else {
+                return true;
+            };
+            deleted.retain(|deleted_path| *deleted_path != from);
+            changes.push(TreeChange::Renamed { from: from.clone(), to: (*path).clone(), score: 100 });
+            false
+        });
+
+        // Inexact renames: best-scoring pairs first
+        if !deleted.is_empty() && !added.is_empty() {
+            let mut old_contents = Vec::new();
+            for path in &deleted {
+                old_contents.push(read(path, &old[*path])?);
+            }
+            let mut new_contents = Vec::new();
+            for path in &added {
+                new_contents.push(read(path, &new[*path])?);
+            }
+
+            let mut candidates = Vec::new();
+            for (i, old_content) in old_contents.iter().enumerate() {
+                for (j, new_content) in new_contents.iter().enumerate() {
+                    let score = similarity(old_content, new_content);
+                    if score >= threshold {
+                        candidates.push((score, i, j));
+                    }
+                }
+            }
+            candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+            let mut paired_old = vec![false; deleted.len()];
+            let mut paired_new = vec![false; added.len()];
+            for (score, i, j) in candidates {
+                if paired_old[i] || paired_new[j] {
+                    continue;
+                }
+                paired_old[i] = true;
+                paired_new[j] = true;
+                changes.push(TreeChange::Renamed { from: deleted[i].clone(), to: added[j].clone(), score });
+            }
+
+            let mut paired = paired_old.into_iter();
+            deleted.retain(|_| !paired.next().unwrap_or(false));
+            let mut paired = paired_new.into_iter();
+            added.retain(|_| !paired.next().unwrap_or(false));
+        }
+    }
+
+    changes.extend(deleted.into_iter().map(|path| TreeChange::Deleted(path.clone())));
+    changes.extend(added.into_iter().map(|path| TreeChange::Added(path.clone())));
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(changes)
+}
+
+/// How much of two contents is shared, in percent: the bytes of lines
+/// the two have in common relative to the larger of the two.
+pub fn similarity(old: &[u8], new: &[u8]) -> u8 {
+    let larger = old.len().max(new.len());
+    if larger == 0 {
+        return 100;
+    }
+
+    let mut old_lines: HashMap<&[u8], usize> = HashMap::new();
+    for line in old.split_inclusive(|&b| b == b'\n') {
+        *old_lines.entry(line).or_default() += 1;
+    }
+    let mut common = 0;
+    for line in new.split_inclusive(|&b| b == b'\n') {
+        if let Some(count) = old_lines.get_mut(line).filter(|count| **count > 0) {
+            *count -= 1;
+            common += line.len();
+        }
+    }
+    (common * 100 / larger) as u8
+}
+
+/// Lines added and removed going from `old` to `new`.
+pub fn line_stats(old: &[u8], new: &[u8]) -> (usize, usize) {
+    let old_text = String::from_utf8_lossy(old);
+    let new_text = String::from_utf8_lossy(new);
+    let old_lines: Vec<&str> = old_text.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new_text.split_inclusive('\n').collect();
+    diff(&old_lines, &new_lines).iter().fold((0, 0), |(added, removed), edit| match edit {
+        Edit::Equal(..) => (added, removed),
+        Edit::Delete(_) => (added, removed + 1),
+        Edit::Insert(_) => (added + 1, removed),
+    })
+}
+
+/// Whether content looks binary rather than text.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&b| b == 0)
+}
+
+/// How changed words are shown within lines: marked `[-old-]{+new+}`,
+/// or with color alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WordDiff {
+    Markers,
+    Color,
+}
+
+/// A color spec from the config, such as `bold red` or `brightgreen black`:
+/// the first color is the foreground, a second one the background, and
+/// `bold`, `dim`, `italic`, `ul` and `reverse` add attributes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DiffStyle {
+    foreground: Option<Color>,
+    background: Option<Color>,
+    bold: bool,
+    dimmed: bool,
+    italic: bool,
+    underline: bool,
+    reversed: bool,
+}
+
+impl FromStr for DiffStyle {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut style = DiffStyle::default();
+        let mut words = spec.split_whitespace().peekable();
+        while let Some(word) = words.next() {
+            let word = word.to_ascii_lowercase();
+            match word.as_str() {
+                "bold" => style.bold = true,
+                "dim" => style.dimmed = true,
+                "italic" => style.italic = true,
+                "ul" | "underline" => style.underline = true,
+                "reverse" => style.reversed = true,
+                "normal" | "default" => {}
+                _ => {
+                    // `bright red`, `bright_red` and `brightred` all name one color
+                    let name = match word.strip_prefix("bright") {
+                        Some("") => format!("bright {}", words.next().unwrap_or_default()),
+                        Some(color) => format!("bright {}", color.trim_start_matches('_')),
+                        None => word.clone(),
+                    };
+                    let color = name.parse::<Color>().map_err(|_| format!("unknown color '{}'", name))?;
+                    if style.foreground.is_none() {
+                        style.foreground = Some(color);
+                    } else if style.background.is_none() {
+                        style.background = Some(color);
+                    } else {
+                        return Err(format!("too many colors in '{}'", spec));
+                    }
+                }
+            }
+        }
+        Ok(style)
+    }
+}
+
+impl DiffStyle {
+    fn color(color: Color) -> Self {
+        DiffStyle { foreground: Some(color), ..DiffStyle::default() }
+    }
+
+    pub fn paint(&self, text: &str) -> ColoredString {
+        let mut painted = text.normal();
+        if let Some(color) = self.foreground {
+            painted = painted.color(color);
+        }
+        if let Some(color) = self.background {
+            painted = painted.on_color(color);
+        }
+        for (enabled, apply) in [
+            (self.bold, ColoredString::bold as fn(ColoredString) -> ColoredString),
+            (self.dimmed, ColoredString::dimmed),
+            (self.italic, ColoredString::italic),
+            (self.underline, ColoredString::underline),
+            (self.reversed, ColoredString::reversed),
+        ] {
+            if enabled {
+                painted = apply(painted);
+            }
+        }
+        painted
+    }
+}
+
+/// The styles a patch is printed with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiffColors {
+    pub old: DiffStyle,
+    pub new: DiffStyle,
+    pub meta: DiffStyle,
+}
+
+impl Default for DiffColors {
+    fn default() -> Self {
+        DiffColors {
+            old: DiffStyle::color(Color::BrightRed),
+            new: DiffStyle::color(Color::BrightGreen),
+            meta: DiffStyle { bold: true, ..DiffStyle::default() },
+        }
+    }
+}
+
+impl DiffColors {
+    /// The configured `color.diff.*` styles, keeping the default for any
+    /// key that is unset or can't be parsed.
+    pub fn from_config(config: &DiffColorConfig) -> Self {
+        let mut colors = DiffColors::default();
+        for (key, spec, style) in [
+            ("new", &config.new, &mut colors.new),
+            ("old", &config.old, &mut colors.old),
+            ("meta", &config.meta, &mut colors.meta),
+        ] {
+            let Some(spec) = spec else { continue };
+            match spec.parse() {
+                Ok(parsed) => *style = parsed,
+                Err(e) => println!("{}: {} {}: {}",
+                                   "Warning".bright_yellow().bold(),
+                                   "Ignoring".bright_yellow(),
+                                   format!("color.diff.{}", key).bright_cyan(),
+                                   e),
+            }
+        }
+        colors
+    }
+}
+
+/// Print the difference between two versions of a file, each given as
+/// its path and content; `None` stands for a side where the file does not
+/// exist. A `similarity` marks the pair as a rename. With `word_diff`,
+/// changed lines are shown once with the changed words highlighted.
+pub fn print_file_diff(
+    old: Option<(&str, &[u8])>,
+    new: Option<(&str, &[u8])>,
+    similarity: Option<u8>,
+    word_diff: Option<WordDiff>,
+    colors: &DiffColors,
+) {
+    let (old_path, old) = old.unzip();
+    let (new_path, new) = new.unzip();
+    let Some(path) = old_path.or(new_path) else {
+        return;
+    };
+    let new_path_or_old = new_path.unwrap_or(path);
+    println!("{}", colors.meta.paint(&format!("diff --bloc a/{} b/{}", path, new_path_or_old)));
+    match (old, new) {
+        (None, Some(_)) => println!("{}", colors.meta.paint("new file")),
+        (Some(_), None) => println!("{}", colors.meta.paint("deleted file")),
+        _ => {}
+    }
+    if let Some(score) = similarity {
+        println!("{}", colors.meta.paint(&format!("similarity index {}%", score)));
+        println!("{}", colors.meta.paint(&format!("rename from {}", path)));
+        println!("{}", colors.meta.paint(&format!("rename to {}", new_path_or_old)));
+        if old == new {
+            return;
+        }
+    }
+
+    let old_name = match old_path {
+        Some(path) => format!("a/{}", path),
+        None => "/dev/null".to_string(),
+    };
+    let new_name = match new_path {
+        Some(path) => format!("b/{}", path),
+        None => "/dev/null".to_string(),
+    };
+    let old = old.unwrap_or_default();
+    let new = new.unwrap_or_default();
+    if is_binary(old) || is_binary(new) {
+        println!("Binary files {} and {} differ", old_name, new_name);
+        return;
+    }
+    println!("{}", colors.meta.paint(&format!("--- {}", old_name)));
+    println!("{}", colors.meta.paint(&format!("+++ {}", new_name)));
+
+    let old_text = String::from_utf8_lossy(old);
+    let new_text = String::from_utf8_lossy(new);
+    let old_lines: Vec<&str> = old_text.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new_text.split_inclusive('\n').collect();
+
+    let edits = diff(&old_lines, &new_lines);
+    for hunk in hunks(&edits, CONTEXT_LINES) {
+        println!("{}", format!("@@ -{},{} +{},{} @@",
+                               hunk.old_start, hunk.old_len,
+                               hunk.new_start, hunk.new_len).bright_cyan());
+        match word_diff {
+            Some(mode) => print_word_hunk(&hunk, &old_lines, &new_lines, mode, colors),
+            None => print_line_hunk(&hunk, &old_lines, &new_lines, colors),
+        }
+    }
+}
+
+fn print_line_hunk(hunk: &Hunk, old_lines: &[&str], new_lines: &[&str], colors: &DiffColors) {
+    for edit in hunk.edits {
+        let (marker, line) = match *edit {
+            Edit::Equal(i, _) => (' ', old_lines[i]),
+            Edit::Delete(i) => ('-', old_lines[i]),
+            Edit::Insert(j) => ('+', new_lines[j]),
+        };
+        let text = format!("{}{}", marker, line.trim_end_matches('\n'));
+        match marker {
+            '-' => println!("{}", colors.old.paint(&text)),
+            '+' => println!("{}", colors.new.paint(&text)),
+            _ => println!("{}", text),
+        }
+        if !line.ends_with('\n') {
+            println!("\\ No newline at end of file");
+        }
+    }
+}
+
+/// Show a hunk as its new text, with each run of changed lines diffed
+/// word by word against the lines it replaced.
+fn print_word_hunk(hunk: &Hunk, old_lines: &[&str], new_lines: &[&str], mode: WordDiff, colors: &DiffColors) {
+    let mut pos = 0;
+    while pos < hunk.edits.len() {
+        if let Edit::Equal(i, _) = hunk.edits[pos] {
+            print!("{}", old_lines[i]);
+            if !old_lines[i].ends_with('\n') {
+                println!();
+            }
+            pos += 1;
+            continue;
+        }
+
+        let mut old_block = String::new();
+        let mut new_block = String::new();
+        while let Some(edit) = hunk.edits.get(pos) {
+            match *edit {
+                Edit::Delete(i) => old_block.push_str(old_lines[i]),
+                Edit::Insert(j) => new_block.push_str(new_lines[j]),
+                Edit::Equal(..) => break,
+            }
+            pos += 1;
+        }
+        print_word_changes(&old_block, &new_block, mode, colors);
+    }
+}
+
+fn print_word_changes(old: &str, new: &str, mode: WordDiff, colors: &DiffColors) {
+    let old_words = split_words(old);
+    let new_words = split_words(new);
+
+    let mut output = String::new();
+    let mut deleted = String::new();
+    let mut inserted = String::new();
+    for edit in diff(&old_words, &new_words) {
+        match edit {
+            Edit::Equal(i, _) => {
+                flush_word_changes(&mut output, &mut deleted, &mut inserted, mode, colors);
+                output.push_str(old_words[i]);
+            }
+            Edit::Delete(i) => deleted.push_str(old_words[i]),
+            Edit::Insert(j) => inserted.push_str(new_words[j]),
+        }
+    }
+    flush_word_changes(&mut output, &mut deleted, &mut inserted, mode, colors);
+
+    print!("{}", output);
+    if !output.ends_with('\n') {
+        println!();
+    }
+}
+
+/// Append pending removed and added words to `output` in their colors,
+/// marked `[-old-]{+new+}` unless `mode` is color alone. Markers and colors
+/// are closed at line ends so each output line stays balanced.
+fn flush_word_changes(output: &mut String, deleted: &mut String, inserted: &mut String, mode: WordDiff, colors: &DiffColors) {
+    let (open_old, close_old, open_new, close_new) = match mode {
+        WordDiff::Markers => ("[-", "-]", "{+", "+}"),
+        WordDiff::Color => ("", "", "", ""),
+    };
+    for (text, open, close, style) in [(&mut *deleted, open_old, close_old, &colors.old), (&mut *inserted, open_new, close_new, &colors.new)] {
+        for (n, segment) in text.split('\n').enumerate() {
+            if n > 0 {
+                output.push('\n');
+            }
+            if segment.is_empty() {
+                continue;
+            }
+            let marked = format!("{}{}{}", open, segment, close);
+            output.push_str(&style.paint(&marked).to_string());
+        }
+        text.clear();
+    }
+}
+
+/// Split text into alternating runs of whitespace and non-whitespace, so
+/// the pieces concatenate back to the original.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut in_space = None;
+    for (pos, c) in text.char_indices() {
+        let space = c.is_whitespace();
+        if in_space.is_some_and(|was_space| was_space != space) {
+            words.push(&text[start..pos]);
+            start = pos;
+        }
+        in_space = Some(space);
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+/// A replaced range of base lines and the lines one side put there.
+struct Chunk<'a> {
+    base_start: usize,
+    base_end: usize,
+    lines: Vec<&'a str>,
+}
+
+/// The runs of changes an edit script makes, in base order.
+fn chunks<'a>(edits: &[Edit], other: &[&'a str]) -> Vec<Chunk<'a>> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut base_pos = 0;
+    let mut in_chunk = false;
+    for edit in edits {
+        match *edit {
+            Edit::Equal(i, _) => {
+                base_pos = i + 1;
+                in_chunk = false;
+            }
+            Edit::Delete(i) => {
+                if !in_chunk {
+                    chunks.push(Chunk { base_start: i, base_end: i, lines: Vec::new() });
+                    in_chunk = true;
+                }
+                if let Some(chunk) = chunks.last_mut() {
+                    chunk.base_end = i + 1;
+                }
+                base_pos = i + 1;
+            }
+            Edit::Insert(j) => {
+                if !in_chunk {
+                    chunks.push(Chunk { base_start: base_pos, base_end: base_pos, lines: Vec::new() });
+                    in_chunk = true;
+                }
+                if let Some(chunk) = chunks.last_mut() {
+                    chunk.lines.push(other[j]);
+                }
+            }
+        }
+    }
+    chunks
+}
+
+/// One side's version of the base lines `start..end`, given its chunks
+/// falling inside that range.
+fn side_text(base: &[&str], start: usize, end: usize, chunks: &[Chunk]) -> String {
+    let mut text = String::new();
+    let mut pos = start;
+    for chunk in chunks {
+        text.extend(base[pos..chunk.base_start].iter().copied());
+        text.extend(chunk.lines.iter().copied());
+        pos = chunk.base_end;
+    }
+    text.extend(base[pos..end].iter().copied());
+    text
+}
+
+/// Three-way merge of text: changes either side made to `base` are
+/// combined, and where both sides changed the same or adjacent lines
+/// differently, both versions are kept between conflict markers. Returns
+/// the merged text and whether it contains conflicts.
+pub fn merge_text(base: &str, ours: &str, theirs: &str, ours_label: &str, theirs_label: &str) -> (String, bool) {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ours_chunks = chunks(&diff(&base_lines, &ours_lines), &ours_lines);
+    let theirs_chunks = chunks(&diff(&base_lines, &theirs_lines), &theirs_lines);
+
+    let mut merged = String::new();
+    let mut conflicts = false;
+    let mut base_pos = 0;
+    let (mut i, mut j) = (0, 0);
+    while i < ours_chunks.len() || j < theirs_chunks.len() {
+        // Start a region at the earliest chunk and grow it over every
+        // chunk from either side that overlaps or touches it
+        let (mut start, mut end) = match (ours_chunks.get(i), theirs_chunks.get(j)) {
+            (Some(a), Some(b)) if b.base_start < a.base_start => (b.base_start, b.base_end),
+            (Some(a), _) => (a.base_start, a.base_end),
+            (None, Some(b)) => (b.base_start, b.base_end),
+            (None, None) => break,
+        };
+        let (ours_from, theirs_from) = (i, j);
+        loop {
+            if let Some(a) = ours_chunks.get(i).filter(|a| a.base_start <= end) {
+                start = start.min(a.base_start);
+                end = end.max(a.base_end);
+                i += 1;
+            } else if let Some(b) = theirs_chunks.get(j).filter(|b| b.base_start <= end) {
+                start = start.min(b.base_start);
+                end = end.max(b.base_end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        merged.extend(base_lines[base_pos..start].iter().copied());
+        let ours_text = side_text(&base_lines, start, end, &ours_chunks[ours_from..i]);
+        let theirs_text = side_text(&base_lines, start, end, &theirs_chunks[theirs_from..j]);
+        if theirs_from == j || ours_text == theirs_text {
+            merged.push_str(&ours_text);
+        } else if ours_from == i {
+            merged.push_str(&theirs_text);
+        } else {
+            conflicts = true;
+            merged.push_str(&format!("<<<<<<< {}\n", ours_label));
+            push_line_block(&mut merged, &ours_text);
+            merged.push_str("=======\n");
+            push_line_block(&mut merged, &theirs_text);
+            merged.push_str(&format!(">>>>>>> {}\n", theirs_label));
+        }
+        base_pos = end;
+    }
+    merged.extend(base_lines[base_pos..].iter().copied());
+
+    (merged, conflicts)
+}
+
+/// Append text that must end on a line boundary, such as inside conflict markers.
+fn push_line_block(out: &mut String, text: &str) {
+    out.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        out.push('\n');
+    }
+}
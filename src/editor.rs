@@ -0,0 +1,45 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// The editor to launch: `core.editor` when set, then `$VISUAL`, then
+/// `$EDITOR`, falling back to `vi`.
+pub fn editor_command(configured: Option<&str>) -> String {
+    configured
+        .map(String::from)
+        .or_else(|| std::env::var("VISUAL").ok())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// Open `path` in the user's editor and wait for it to exit. The editor
+/// setting may carry arguments, e.g. `code --wait`.
+pub fn edit_file(configured: Option<&str>, path: &Path) -> io::Result<()> {
+    let editor = editor_command(configured);
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program).args(words).arg(path).status().map_err(|e| {
+        io::Error::new(e.kind(), format!("Could not launch editor '{}': {}", editor, e))
+    })?;
+    if !status.success() {
+        return Err(io::Error::other(format!("Editor '{}' exited with {}", editor, status)));
+    }
+    Ok(())
+}
+
+/// Let the user write a message in the file at `path`, starting from
+/// `initial`; lines starting with `#` are dropped and the file is removed
+/// afterwards. Returns the trimmed message, which may be empty.
+pub fn edit_message(configured: Option<&str>, path: &Path, initial: &str) -> io::Result<String> {
+    fs::write(path, format!("{}\n\n# Please enter the commit message. Lines starting with '#' will be ignored.\n", initial.trim_end()))?;
+    let edited = edit_file(configured, path).and_then(|_| fs::read_to_string(path));
+    let _ = fs::remove_file(path);
+    let edited = edited?;
+    let message: Vec<&str> = edited
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    Ok(message.join("\n").trim().to_string())
+}
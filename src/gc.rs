@@ -0,0 +1,417 @@
+use crate::dates;
+use crate::objects::{Index, ObjectHasher, ObjectType, PackFile, PackStore};
+use crate::repository::BlocRepo;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use colored::*;
+
+/// Remove loose objects that can no longer be reached from any ref,
+/// reflog entry, or the staging area. Packed objects are left alone, as
+/// `repack` only packs reachable ones. Only unreachable objects last
+/// modified before `prune_before` are removed, so objects written by an
+/// operation still in progress survive; `None` prunes nothing.
+pub fn gc(repo: &BlocRepo, prune_before: Option<DateTime<Utc>>) -> Result<(), Box<dyn std::error::Error>> {
+    let reachable = reachable_objects(repo)?;
+    let objects_dir = repo.objects_dir();
+
+    let mut pruned = 0;
+    let mut recent = 0;
+    let mut kept = 0;
+    for bucket in fs::read_dir(&objects_dir)? {
+        let bucket = bucket?;
+        let prefix = bucket.file_name().to_string_lossy().to_string();
+        if prefix.len() != 2 || !bucket.path().is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(bucket.path())? {
+            let entry = entry?;
+            let hash = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if reachable.contains(&hash) {
+                kept += 1;
+                continue;
+            }
+
+            let modified: DateTime<Utc> = entry.metadata()?.modified()?.into();
+            if prune_before.is_some_and(|cutoff| modified < cutoff) {
+                fs::remove_file(entry.path())?;
+                pruned += 1;
+            } else {
+                recent += 1;
+            }
+        }
+
+        // Leave no empty buckets behind
+        let _ = fs::remove_dir(bucket.path());
+    }
+
+    // `auto_gc` collects in the middle of other commands, which must not
+    // find the pruned objects in the prefix index or object cache
+    repo.rebuild_object_index()?;
+    fs::write(repo.bloc_dir.join("gc.baseline"), format!("{}\n", sample_loose_objects(repo)))?;
+
+    println!("{} {} {}, {} {}", 
+            "Pruned".bright_green().bold(), 
+            pruned.to_string().bright_yellow(), 
+            "unreachable objects".bright_green(),
+            kept.to_string().bright_cyan(),
+            "kept".bright_green());
+    if recent > 0 {
+        let reason = if prune_before.is_some() {
+            "unreachable objects are newer than the prune cutoff and were kept"
+        } else {
+            "unreachable objects were kept (pruning disabled)"
+        };
+        println!("{} {}", recent.to_string().bright_yellow(), reason.bright_black());
+    }
+    Ok(())
+}
+
+/// Resolve a prune expiry such as `gc.pruneexpire` to the cutoff `gc`
+/// takes; `never` disables pruning.
+pub fn prune_cutoff(expire: &str) -> Result<Option<DateTime<Utc>>, String> {
+    if expire == "never" {
+        return Ok(None);
+    }
+    dates::parse_date(expire).map(Some).ok_or_else(|| format!("Invalid prune date '{}'", expire))
+}
+
+/// Run `gc` if more loose objects than `gc.auto` allows were written since
+/// the last collection. Returns whether a collection ran.
+pub fn auto_gc(repo: &BlocRepo, prune_before: Option<DateTime<Utc>>) -> Result<bool, Box<dyn std::error::Error>> {
+    let threshold = repo.config.gc.auto;
+    if threshold == 0 {
+        return Ok(false);
+    }
+    // A collection leaves unreachable objects inside the prune window
+    // loose, so only growth since the last one counts
+    let baseline = fs::read_to_string(repo.bloc_dir.join("gc.baseline"))
+        .ok()
+        .and_then(|content| content.trim().parse().ok())
+        .unwrap_or(0);
+    if sample_loose_objects(repo).saturating_sub(baseline) <= threshold.div_ceil(256) {
+        return Ok(false);
+    }
+
+    println!("{}", "Too many loose objects; running 'bloc gc' to tidy up the repository".bright_yellow());
+    gc(repo, prune_before)?;
+    repack(repo)?;
+    Ok(true)
+}
+
+/// Loose objects in a single bucket. Hashes spread evenly over the 256
+/// buckets, so this estimates the total while staying cheap enough to
+/// check after every commit.
+fn sample_loose_objects(repo: &BlocRepo) -> u64 {
+    fs::read_dir(repo.objects_dir().join("17"))
+        .map(|entries| entries.count() as u64)
+        .unwrap_or(0)
+}
+
+/// Move every reachable loose object into a single pack, merging any
+/// existing packs into it. Nothing is pruned: unreachable objects stay
+/// loose, and unreachable objects found in old packs are written back out
+/// as loose objects dated like their pack, so `gc` can prune them once they
+/// expire rather than each repack restarting their clock. Loose objects and
+/// old packs are only deleted once the new pack and its index are on disk,
+/// so this is safe to run at any time. With nothing reachable loose,
+/// nothing unreachable packed and at most one pack, there is nothing to do.
+pub fn repack(repo: &BlocRepo) -> Result<(), Box<dyn std::error::Error>> {
+    let reachable = reachable_objects(repo)?;
+    let mut loose = loose_objects(repo)?;
+    loose.retain(|hash, _| reachable.contains(hash));
+    let existing = PackStore::load(&repo.objects_dir())?;
+    let unreachable_packed: Vec<&String> = existing.hashes().filter(|hash| !reachable.contains(*hash)).collect();
+    if loose.is_empty() && unreachable_packed.is_empty() && existing.packs().len() <= 1 {
+        println!("{}", "Nothing to repack".bright_green());
+        return Ok(());
+    }
+
+    // Loosen unreachable packed objects before their packs go away
+    for hash in &unreachable_packed {
+        let object_path = repo.object_path(hash);
+        if object_path.exists() {
+            continue;
+        }
+        if let Some(data) = existing.read(hash)? {
+            if let Some(object_dir) = object_path.parent() {
+                fs::create_dir_all(object_dir)?;
+            }
+            fs::write(&object_path, data)?;
+            if let Some(pack) = existing.pack_of(hash) {
+                let packed_at = fs::metadata(pack)?.modified()?;
+                fs::File::options().write(true).open(&object_path)?.set_modified(packed_at)?;
+            }
+        }
+    }
+
+    let mut contents = BTreeMap::new();
+    for hash in existing.hashes().filter(|hash| reachable.contains(*hash)) {
+        if let Some(data) = existing.read(hash)? {
+            contents.insert(hash.clone(), data);
+        }
+    }
+    let packed_before = contents.len();
+    for (hash, path) in &loose {
+        if !contents.contains_key(hash) {
+            contents.insert(hash.clone(), fs::read(path)?);
+        }
+    }
+
+    let mut pack = PackFile::new();
+    for (hash, data) in contents {
+        let object_type = ObjectType::of(&data);
+        pack.add_object(hash, object_type, data);
+    }
+    pack.finalize();
+    let pack_path = pack.write_to(&repo.objects_dir().join("pack"))?;
+
+    for path in loose.values() {
+        fs::remove_file(path)?;
+        if let Some(bucket) = path.parent() {
+            let _ = fs::remove_dir(bucket);
+        }
+    }
+    for old_pack in existing.packs() {
+        if *old_pack != pack_path {
+            fs::remove_file(old_pack.with_extension("idx"))?;
+            fs::remove_file(old_pack)?;
+        }
+    }
+    repo.rebuild_object_index()?;
+    fs::write(repo.bloc_dir.join("gc.baseline"), format!("{}\n", sample_loose_objects(repo)))?;
+
+    println!("{} {} {} ({} {}, {} {}) {} {}",
+            "Packed".bright_green().bold(),
+            pack.objects.len().to_string().bright_yellow(),
+            "objects".bright_green(),
+            loose.len().to_string().bright_yellow(),
+            "loose".bright_green(),
+            packed_before.to_string().bright_yellow(),
+            "already packed".bright_green(),
+            "into".bright_green(),
+            pack_path.file_name().unwrap_or_default().to_string_lossy().bright_cyan());
+    if !unreachable_packed.is_empty() {
+        println!("{} {}",
+                unreachable_packed.len().to_string().bright_yellow(),
+                "unreachable objects were unpacked and left loose for gc to prune".bright_black());
+    }
+    Ok(())
+}
+
+/// Loose objects by hash, with their paths.
+fn loose_objects(repo: &BlocRepo) -> io::Result<BTreeMap<String, PathBuf>> {
+    let mut objects = BTreeMap::new();
+    for bucket in fs::read_dir(repo.objects_dir())? {
+        let bucket = bucket?;
+        let prefix = bucket.file_name().to_string_lossy().to_string();
+        if prefix.len() != 2 || !bucket.path().is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(bucket.path())? {
+            let entry = entry?;
+            objects.insert(format!("{}{}", prefix, entry.file_name().to_string_lossy()), entry.path());
+        }
+    }
+    Ok(objects)
+}
+
+/// Report how many loose objects are stored and how much space they take.
+/// With `verbose`, also show the packed objects and packs, and how content
+/// addressing pays off: the files tracked in the staged snapshot (HEAD plus
+/// the index) against the unique blobs storing them, and the bytes saved by
+/// storing identical content once.
+pub fn count_objects(repo: &BlocRepo, verbose: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut count = 0;
+    let mut size = 0;
+    for path in loose_objects(repo)?.values() {
+        count += 1;
+        size += fs::metadata(path)?.len();
+    }
+
+    if !verbose {
+        println!("{} objects, {} kilobytes", count, size / 1024);
+        return Ok(());
+    }
+
+    let tracked = repo.staged_tree()?;
+    let mut copies: HashMap<&String, u64> = HashMap::new();
+    for hash in tracked.values() {
+        *copies.entry(hash).or_default() += 1;
+    }
+    let mut saved = 0;
+    for (hash, count) in &copies {
+        if let Ok(object_size) = repo.object_size(hash) {
+            saved += (count - 1) * object_size;
+        }
+    }
+    let packs = PackStore::load(&repo.objects_dir())?;
+    let mut pack_size = 0;
+    for pack in packs.packs() {
+        pack_size += fs::metadata(pack)?.len() + fs::metadata(pack.with_extension("idx"))?.len();
+    }
+
+    println!("{}: {}", "count".bright_blue(), count);
+    println!("{}: {}", "size".bright_blue(), format_size(size));
+    println!("{}: {}", "in-pack".bright_blue(), packs.hashes().count());
+    println!("{}: {}", "packs".bright_blue(), packs.packs().len());
+    println!("{}: {}", "size-pack".bright_blue(), format_size(pack_size));
+    println!("{}: {}", "tracked-files".bright_blue(), tracked.len());
+    println!("{}: {}", "unique-blobs".bright_blue(), copies.len());
+    println!("{}: {}", "dedup-saved".bright_blue(), format_size(saved));
+    Ok(())
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} bytes", bytes),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// Every object reachable from the repository's roots: all refs under
+/// `refs/` (branches, tags, remote-tracking branches and the stash),
+/// a detached HEAD, every hash recorded in the reflogs, and staged blobs.
+/// Each linked worktree (see `BlocRepo::worktree_admin_dirs`) adds its own
+/// HEAD, reflogs and index, so its in-progress work is never pruned.
+pub fn reachable_objects(repo: &BlocRepo) -> io::Result<HashSet<String>> {
+    let mut roots = Vec::new();
+
+    for entry in WalkDir::new(repo.refs_dir()).into_iter().filter_map(|e| e.ok()) {
+        if entry.path().is_file() {
+            roots.push(fs::read_to_string(entry.path())?.trim().to_string());
+        }
+    }
+
+    let mut reachable: HashSet<String> = repo.index.entries.values().map(|entry| entry.hash.clone()).collect();
+    add_head_roots(&repo.bloc_dir, &mut roots)?;
+
+    for worktree_dir in repo.worktree_admin_dirs()? {
+        add_head_roots(&worktree_dir, &mut roots)?;
+        let index = Index::load_from(&worktree_dir.join("index"))?;
+        reachable.extend(index.entries.into_values().map(|entry| entry.hash));
+    }
+
+    let hasher = repo.object_hasher();
+    let mut pending: Vec<String> = roots.into_iter().filter(|hash| is_object_hash(hash, hasher)).collect();
+
+    while let Some(hash) = pending.pop() {
+        if !reachable.insert(hash.clone()) {
+            continue;
+        }
+        if let Ok(tag) = repo.read_tag(&hash) {
+            pending.push(tag.object.clone());
+            continue;
+        }
+        // Roots may name blobs, and history may be cut short by missing objects
+        let Ok(commit) = repo.read_commit(&hash) else {
+            continue;
+        };
+        reachable.extend(commit.tree_entries().into_values());
+        pending.extend(commit.parents().cloned());
+    }
+
+    Ok(reachable)
+}
+
+/// Add the roots kept by one checkout's administrative directory: its HEAD
+/// when detached, and every hash recorded in its reflogs.
+fn add_head_roots(admin_dir: &Path, roots: &mut Vec<String>) -> io::Result<()> {
+    if let Ok(head_content) = fs::read_to_string(admin_dir.join("HEAD"))
+        && !head_content.starts_with("ref: ") {
+        roots.push(head_content.trim().to_string());
+    }
+
+    // Reflog lines look like `<old> <new> <identity> <time>\t<message>`
+    for entry in WalkDir::new(admin_dir.join("logs")).into_iter().filter_map(|e| e.ok()) {
+        if entry.path().is_file() {
+            for line in fs::read_to_string(entry.path())?.lines() {
+                roots.extend(line.split_whitespace().take(2).map(String::from));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_object_hash(value: &str, hasher: ObjectHasher) -> bool {
+    hasher.is_object_name(value) && !value.chars().all(|c| c == '0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::serialize_tree;
+    use crate::objects::{Commit, IndexEntry};
+    use crate::repository::test_support::temp_repo;
+    use chrono::Duration;
+
+    fn write_commit(repo: &BlocRepo, path: &str, content: &[u8]) -> String {
+        let blob = repo.write_object(content).unwrap();
+        let commit = Commit {
+            parent: None,
+            merge_parents: Vec::new(),
+            author: repo.get_author_signature(),
+            committer: repo.get_author_signature(),
+            timestamp: Utc::now(),
+            message: format!("add {}", path),
+            tree: serialize_tree(&BTreeMap::from([(path.to_string(), blob)])),
+            signature: None,
+        };
+        repo.write_object(serde_json::to_string_pretty(&commit).unwrap().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn tagged_commit_off_every_branch_survives_prune() {
+        let (_guard, repo, dir) = temp_repo("gc-tagged-commit");
+        let tagged = write_commit(&repo, "released.txt", b"released\n");
+        fs::write(repo.refs_dir().join("tags").join("v1.0"), &tagged).unwrap();
+        let stray = write_commit(&repo, "stray.txt", b"stray\n");
+
+        gc(&repo, Some(Utc::now() + Duration::days(1))).unwrap();
+
+        let tagged_blob = repo.read_tree(&tagged).unwrap()["released.txt"].clone();
+        assert!(repo.object_path(&tagged).exists());
+        assert!(repo.object_path(&tagged_blob).exists());
+        assert!(!repo.object_path(&stray).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn work_in_a_linked_worktree_survives_prune() {
+        let (_guard, repo, dir) = temp_repo("gc-worktrees");
+        let worktrees_dir = repo.bloc_dir.join("worktrees");
+        let first = worktrees_dir.join("first");
+        let second = worktrees_dir.join("second");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        fs::write(first.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        // Only the second worktree knows about this commit and staged blob
+        let detached = write_commit(&repo, "wip.txt", b"work in progress\n");
+        fs::write(second.join("HEAD"), format!("{}\n", detached)).unwrap();
+        let staged = repo.write_object(b"staged in second\n").unwrap();
+        let mut index = Index::new(second.join("index"));
+        index.entries.insert("staged.txt".to_string(), IndexEntry {
+            hash: staged.clone(),
+            mode: "100644".to_string(),
+            size: 17,
+            mtime: Utc::now(),
+            deleted: false,
+            conflicted: false,
+        });
+        index.save().unwrap();
+        let stray = write_commit(&repo, "stray.txt", b"stray\n");
+
+        gc(&repo, Some(Utc::now() + Duration::days(1))).unwrap();
+
+        assert!(repo.object_path(&detached).exists());
+        assert!(repo.object_path(&staged).exists());
+        assert!(!repo.object_path(&stray).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -3,11 +3,25 @@ mod repository;
 mod objects;
 mod commands;
 mod branches;
+mod signing;
+mod gc;
+mod dates;
+mod tags;
+mod diff;
+mod merge;
+mod blame;
+mod remote;
+mod editor;
+mod rebase;
+mod refsort;
+mod stash;
 
 use clap::{Parser, Subcommand};
 use repository::BlocRepo;
-use config::BlocConfig;
+use config::{BlocConfig, BranchConfig};
+#[cfg(windows)]
 use std::io;
+use std::path::PathBuf;
 use colored::*;
 
 #[cfg(windows)]
@@ -29,12 +43,6 @@ fn hide_directory(path: &str) -> io::Result<()> {
     Ok(())
 }
 
-#[cfg(not(windows))]
-fn hide_directory(_path: &str) -> io::Result<()> {
-    // On Unix-like systems, directories starting with . are hidden by default
-    Ok(())
-}
-
 #[derive(Parser)]
 #[command(name = "bloc")]
 #[command(about = "A powerful git-like version control tool", long_about = None)]
@@ -59,32 +67,143 @@ enum Commands {
         url: String,
         /// Destination directory (optional)
         directory: Option<String>,
+        /// Check out this branch instead of the one the source's HEAD points at
+        #[arg(short, long)]
+        branch: Option<String>,
+        /// Copy only the most recent N commits of history
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        depth: Option<u64>,
     },
     /// Add file(s) to the staging area
     Add {
         files: Vec<String>,
+        /// Add files even if they are ignored
+        #[arg(short, long)]
+        force: bool,
     },
-    /// Remove files from the staging area
+    /// Remove files from the staging area, or reset them to a commit
     Reset {
+        /// Files to unstage, or the commit to reset paths from when used with `--`
         files: Vec<String>,
+        /// Paths to reset to the given commit (default: HEAD)
+        #[arg(last = true)]
+        paths: Vec<String>,
+        /// Move the branch to the given commit, updating the files that differ,
+        /// but refuse if that would overwrite local changes
+        #[arg(long)]
+        keep: bool,
+    },
+    /// Restore working tree files, or staged content with --staged
+    Restore {
+        files: Vec<String>,
+        /// Restore the staging area instead of the working tree
+        #[arg(long)]
+        staged: bool,
+        /// Commit to restore from (default: the index, or HEAD with --staged)
+        #[arg(long, short)]
+        source: Option<String>,
     },
     /// Commit staged changes
     Commit {
+        /// Commit message (defaults to the prepared message while merging,
+        /// otherwise opens the editor)
         #[arg(short, long)]
-        message: String,
+        message: Option<String>,
+        /// Override the commit author, as "Name <email>"
+        #[arg(long)]
+        author: Option<String>,
+        /// Sign the commit with user.signingkey
+        #[arg(short = 'S', long)]
+        sign: bool,
+        /// Mark the commit to be folded into COMMIT by rebase --autosquash
+        #[arg(long, value_name = "COMMIT", conflicts_with_all = ["message", "squash"])]
+        fixup: Option<String>,
+        /// Like --fixup, but keep this commit's message (-m) when squashing
+        #[arg(long, value_name = "COMMIT")]
+        squash: Option<String>,
+    },
+    /// Check the signature of a commit
+    VerifyCommit {
+        revision: String,
+    },
+    /// Show where HEAD or a branch has pointed, most recent first
+    Reflog {
+        /// HEAD, a branch name or a full ref name
+        #[arg(default_value = "HEAD")]
+        reference: String,
     },
     /// Show commit log
     Log {
         #[arg(short, long)]
         oneline: bool,
+        /// Verify and show commit signatures
+        #[arg(long)]
+        show_signature: bool,
+        /// Show commits reachable from any ref
+        #[arg(long)]
+        all: bool,
+        /// Show commits reachable from any branch
+        #[arg(long)]
+        branches: bool,
+        /// Show commits reachable from any tag
+        #[arg(long)]
+        tags: bool,
+        /// Limit the number of commits shown
+        #[arg(short = 'n', long, value_name = "N")]
+        max_count: Option<usize>,
+        /// Show commits oldest first
+        #[arg(long)]
+        reverse: bool,
     },
     /// Show repository status
-    Status,
+    Status {
+        /// How to show untracked files (no, normal, all)
+        #[arg(short = 'u', long = "untracked-files", value_name = "MODE", value_enum,
+              default_value = "normal", num_args = 0..=1, default_missing_value = "all")]
+        untracked_files: commands::UntrackedMode,
+    },
+    /// Check that the working tree, index and HEAD are consistent
+    Verify,
     /// Show differences
     Diff {
+        /// Commit to compare with, or two commits to compare with each other
+        #[arg(num_args = 0..=2, value_name = "COMMIT")]
+        commits: Vec<String>,
         /// Show staged changes
         #[arg(long)]
         staged: bool,
+        /// Detect renames of files at least N% similar (default 50)
+        #[arg(short = 'M', long = "find-renames", value_name = "N", num_args = 0..=1,
+              default_missing_value = "50",
+              value_parser = clap::value_parser!(u8).range(0..=100))]
+        find_renames: Option<u8>,
+        /// Mark changed words within lines as [-old-]{+new+}
+        #[arg(long)]
+        word_diff: bool,
+        /// Show changed words within lines by color alone
+        #[arg(long, conflicts_with = "word_diff")]
+        color_words: bool,
+        /// Exit with 1 if there are differences and 0 otherwise
+        #[arg(long)]
+        exit_code: bool,
+        /// Print nothing; implies --exit-code
+        #[arg(long)]
+        quiet: bool,
+    },
+    /// List the files changed by a commit, or between two commits
+    DiffTree {
+        /// Commit to compare with its parent
+        revision: String,
+        /// Compare `revision` with this commit instead
+        other: Option<String>,
+        /// Detect renames of files at least N% similar (default 50)
+        #[arg(short = 'M', long = "find-renames", value_name = "N", num_args = 0..=1,
+              default_missing_value = "50",
+              value_parser = clap::value_parser!(u8).range(0..=100))]
+        find_renames: Option<u8>,
+        /// Print the changes as a JSON array of records
+        #[arg(long)]
+        json: bool,
     },
     /// Branch operations
     Branch {
@@ -93,28 +212,175 @@ enum Commands {
         /// List all branches
         #[arg(short, long)]
         list: bool,
-        /// Delete a branch
-        #[arg(short, long)]
-        delete: Option<String>,
+        /// Delete branches; each must be merged into HEAD unless --force is given
+        #[arg(short, long, num_args = 1.., value_name = "BRANCH")]
+        delete: Option<Vec<String>>,
         /// Force delete
         #[arg(long)]
         force: bool,
+        /// With --delete, only report which branches would be deleted or refused
+        #[arg(long, requires = "delete")]
+        dry_run: bool,
         /// Rename a branch
-        #[arg(short, long)]
+        #[arg(short, short_alias = 'm', long, num_args = 2, value_names = ["OLD", "NEW"])]
         rename: Option<Vec<String>>,
+        /// List remote-tracking branches
+        #[arg(long)]
+        remotes: bool,
+        /// List both local and remote-tracking branches
+        #[arg(short, long)]
+        all: bool,
+        /// Order the listing by name, creatordate or version:refname
+        /// (prefix with - to reverse)
+        #[arg(long, value_name = "KEY", allow_hyphen_values = true)]
+        sort: Option<refsort::RefSort>,
+    },
+    /// Create, list, delete or verify tags
+    Tag {
+        /// Tag name to create, delete or verify
+        name: Option<String>,
+        /// Commit to tag (defaults to HEAD)
+        revision: Option<String>,
+        /// Create an annotated tag
+        #[arg(short, long)]
+        annotate: bool,
+        /// Annotation message (implies -a)
+        #[arg(short, long)]
+        message: Option<String>,
+        /// Create a signed annotated tag
+        #[arg(short = 's', long)]
+        sign: bool,
+        /// Delete the tag
+        #[arg(short, long)]
+        delete: bool,
+        /// List tags
+        #[arg(short, long)]
+        list: bool,
+        /// Show up to N lines of each tag's annotation when listing
+        #[arg(short = 'n', value_name = "N", num_args = 0..=1, default_missing_value = "1")]
+        lines: Option<usize>,
+        /// Check the tag's signature
+        #[arg(long)]
+        verify: bool,
+        /// Replace an existing tag
+        #[arg(short, long)]
+        force: bool,
+        /// Order the listing by name, creatordate or version:refname
+        /// (prefix with - to reverse)
+        #[arg(long, value_name = "KEY", allow_hyphen_values = true)]
+        sort: Option<refsort::RefSort>,
     },
     /// Switch to a different branch
     Checkout {
+        /// Branch to switch to, or with paths, the commit to take them from
+        branch: Option<String>,
+        /// Paths to restore instead of switching branches (after `--`)
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Switch to a branch without touching individual paths
+    Switch {
         branch: String,
+        /// Create the branch at the current commit before switching
+        #[arg(short = 'c', long)]
+        create: bool,
     },
     /// Merge a branch into current branch
     Merge {
-        branch: String,
+        #[arg(required_unless_present = "abort")]
+        branch: Option<String>,
+        /// Create a merge commit even when a fast-forward is possible
+        #[arg(long)]
+        no_ff: bool,
+        /// Give up on a conflicted merge and restore the files it changed
+        #[arg(long, conflicts_with_all = ["branch", "no_ff"])]
+        abort: bool,
+    },
+    /// Replay the current branch's commits on top of another commit
+    Rebase {
+        /// Commit to rebase onto
+        upstream: Option<String>,
+        /// Edit the list of commits to replay before starting
+        #[arg(short, long)]
+        interactive: bool,
+        /// Move fixup!/squash! commits next to the commits they amend
+        #[arg(long)]
+        autosquash: bool,
+        /// Resume after resolving conflicts or editing a commit
+        #[arg(long = "continue", conflicts_with_all = ["upstream", "abort"])]
+        continue_rebase: bool,
+        /// Undo the rebase in progress
+        #[arg(long, conflicts_with = "upstream")]
+        abort: bool,
+    },
+    /// Show which commit last changed each line of a file
+    #[command(visible_alias = "annotate")]
+    Blame {
+        /// File to blame
+        file: String,
+        /// Commit to blame the file as of (defaults to HEAD)
+        revision: Option<String>,
+        /// Only blame lines <start>,<end> (or <start>,+<count>)
+        #[arg(short = 'L', value_name = "RANGE")]
+        lines: Option<String>,
+        /// Print a machine-readable header block per line for editors and tools
+        #[arg(long)]
+        porcelain: bool,
     },
     /// Show file contents at specific commit
     Show {
-        #[arg(help = "commit-hash:path or just commit-hash")]
+        #[arg(help = "commit-hash:path or just commit-hash", default_value = "HEAD")]
         target: String,
+        /// Show a histogram of changed lines per file instead of the patch
+        #[arg(long)]
+        stat: bool,
+        /// Show the status and path of each changed file instead of the patch
+        #[arg(long)]
+        name_status: bool,
+        /// Print the commit and its changes as JSON
+        #[arg(long, conflicts_with_all = ["stat", "name_status"])]
+        json: bool,
+    },
+    /// Remove unreachable objects
+    Gc {
+        /// Only prune unreachable objects older than this date
+        /// (e.g. 2.weeks.ago, 2024-01-31, now, never); defaults to gc.pruneexpire
+        #[arg(long, value_name = "DATE")]
+        prune: Option<String>,
+        /// Only collect when there are more loose objects than gc.auto allows
+        #[arg(long)]
+        auto: bool,
+    },
+    /// Pack all loose objects into a single pack without pruning anything
+    Repack,
+    /// Count stored objects and the disk space they use
+    CountObjects {
+        /// Also report deduplication of identical content
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Resolve a revision to its full commit hash
+    RevParse {
+        revision: String,
+    },
+    /// List refs with the commits they point to
+    ShowRef {
+        /// Only show branches (refs/heads)
+        #[arg(long)]
+        heads: bool,
+        /// Only show tags (refs/tags)
+        #[arg(long)]
+        tags: bool,
+    },
+    /// Print or change the ref HEAD points to
+    SymbolicRef {
+        /// The symbolic ref to read or update (only HEAD)
+        name: String,
+        /// Ref to point it at, e.g. refs/heads/main
+        target: Option<String>,
+        /// Print only the branch name
+        #[arg(long)]
+        short: bool,
     },
     /// Remove files from working directory and index
     Rm {
@@ -122,12 +388,69 @@ enum Commands {
         /// Remove directories recursively
         #[arg(short)]
         recursive: bool,
+        /// Only remove from the index, keeping the working tree file
+        #[arg(long)]
+        cached: bool,
+        /// Remove files even if they have local modifications
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Move or rename tracked files and directories
+    Mv {
+        /// Sources followed by the destination
+        #[arg(required = true, num_args = 2..)]
+        paths: Vec<String>,
+        /// Skip moves that would fail instead of aborting, and report them
+        #[arg(short = 'k')]
+        keep_going: bool,
+    },
+    /// Show the type, size or contents of stored objects
+    CatFile {
+        /// Object hash (possibly abbreviated) or revision
+        #[arg(required_unless_present = "batch")]
+        object: Option<String>,
+        /// Show the object's type
+        #[arg(short = 't', conflicts_with_all = ["size", "pretty"])]
+        show_type: bool,
+        /// Show the object's size in bytes
+        #[arg(short = 's', conflicts_with = "pretty")]
+        size: bool,
+        /// Show the object's contents
+        #[arg(short = 'p')]
+        pretty: bool,
+        /// Read object ids from stdin, one per line, and print the type, size
+        /// and contents of each
+        #[arg(long, conflicts_with_all = ["object", "show_type", "size", "pretty"])]
+        batch: bool,
+    },
+    /// List the files recorded in a commit
+    LsTree {
+        revision: String,
     },
     /// Configuration operations
     Config {
         #[command(subcommand)]
         action: ConfigCommands,
     },
+    /// Limit the working tree to a subset of paths
+    SparseCheckout {
+        #[command(subcommand)]
+        action: SparseCheckoutCommands,
+    },
+    /// Save local changes away and restore them later
+    Stash {
+        #[command(subcommand)]
+        action: Option<StashCommands>,
+        /// Describe the stashed changes (when no subcommand is given)
+        #[arg(short, long)]
+        message: Option<String>,
+        /// Also stash untracked files (when no subcommand is given)
+        #[arg(short = 'u', long)]
+        include_untracked: bool,
+        /// Also stash untracked and ignored files (when no subcommand is given)
+        #[arg(short, long)]
+        all: bool,
+    },
     /// Remote repository operations
     Remote {
         #[command(subcommand)]
@@ -135,22 +458,28 @@ enum Commands {
     },
     /// Push changes to remote repository
     Push {
-        /// Remote name (default: origin)
+        /// Remote name (default: the branch's upstream, see push.default)
         remote: Option<String>,
         /// Branch name (default: current branch)
         branch: Option<String>,
         /// Force push
         #[arg(long)]
         force: bool,
+        /// Remember the remote branch as the current branch's upstream
+        #[arg(short = 'u', long)]
+        set_upstream: bool,
     },
     /// Fetch changes from remote repository
     Fetch {
         /// Remote name (default: origin)
         remote: Option<String>,
+        /// Delete remote-tracking branches that no longer exist on the remote
+        #[arg(short, long)]
+        prune: bool,
     },
     /// Pull changes from remote repository
     Pull {
-        /// Remote name (default: origin)
+        /// Remote name (default: the branch's upstream)
         remote: Option<String>,
         /// Branch name (default: current branch)
         branch: Option<String>,
@@ -166,13 +495,81 @@ enum ConfigCommands {
         /// Configuration value
         value: String,
     },
-    /// Get configuration value
+    /// Get configuration value; exits with 1 if the key is not set and 2
+    /// if it doesn't fit the requested type
     Get {
-        /// Configuration key
+        /// Configuration key (e.g., user.name, core.editor, remotes.origin.url)
         key: String,
+        /// Print the value as true or false (accepts yes/no, on/off, 1/0)
+        #[arg(long, conflicts_with = "int")]
+        bool: bool,
+        /// Print the value as a plain integer (accepts k, m and g suffixes)
+        #[arg(long)]
+        int: bool,
+        /// Print secrets such as user.signingkey instead of masking them
+        #[arg(long)]
+        show_secret: bool,
     },
     /// List all configuration
+    List {
+        /// Prefix each setting with the file it came from
+        #[arg(long)]
+        show_origin: bool,
+    },
+    /// Open the config file in an editor, rejecting edits that don't parse
+    Edit {
+        /// Edit the global config (~/.blocconfig) instead of the repository's
+        #[arg(long)]
+        global: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SparseCheckoutCommands {
+    /// Set the patterns of paths to keep in the working tree
+    Set {
+        /// Path patterns (e.g., src/, docs/*.md)
+        patterns: Vec<String>,
+    },
+    /// List the current sparse-checkout patterns
+    List,
+    /// Disable sparse checkout and restore the full working tree
+    Disable,
+}
+
+#[derive(Subcommand)]
+enum StashCommands {
+    /// Save local changes and reset the working tree to HEAD (the default)
+    Push {
+        /// Describe the stashed changes
+        #[arg(short, long)]
+        message: Option<String>,
+        /// Also stash untracked files, removing them from the working tree
+        #[arg(short = 'u', long)]
+        include_untracked: bool,
+        /// Also stash untracked files, ignored ones included
+        #[arg(short, long)]
+        all: bool,
+    },
+    /// List stash entries, newest first
     List,
+    /// Apply a stash entry and drop it if it applied cleanly
+    Pop {
+        /// Entry to apply, as stash@{n} or n (default: the newest)
+        stash: Option<String>,
+    },
+    /// Apply a stash entry, keeping it on the stack
+    Apply {
+        /// Entry to apply, as stash@{n} or n (default: the newest)
+        stash: Option<String>,
+    },
+    /// Remove a stash entry without applying it
+    Drop {
+        /// Entry to remove, as stash@{n} or n (default: the newest)
+        stash: Option<String>,
+    },
+    /// Remove all stash entries
+    Clear,
 }
 
 #[derive(Subcommand)]
@@ -216,13 +613,10 @@ fn main() {
             }
         }
         
-        Commands::Clone { url, directory } => {
-            println!("{}: {}", 
-                    "Clone functionality".bright_yellow().bold(), 
-                    "not yet implemented".bright_yellow());
-            println!("URL: {}", url.bright_cyan());
-            if let Some(dir) = directory {
-                println!("Directory: {}", dir.bright_cyan());
+        Commands::Clone { url, directory, branch, depth } => {
+            let depth = depth.map(|depth| depth as usize);
+            if let Err(e) = remote::clone(url, directory.as_deref(), branch.as_deref(), depth) {
+                println!("{}: {}", "Error cloning".bright_red().bold(), e);
             }
         }
 
@@ -230,11 +624,19 @@ fn main() {
             handle_config_command(action);
         }
 
+        Commands::SparseCheckout { action } => {
+            handle_sparse_checkout_command(action);
+        }
+
+        Commands::Stash { action, message, include_untracked, all } => {
+            handle_stash_command(action.as_ref(), message.as_deref(), stash_untracked(*include_untracked, *all));
+        }
+
         Commands::Remote { action } => {
             handle_remote_command(action);
         }
 
-        Commands::Add { files } => {
+        Commands::Add { files, force } => {
             if !BlocRepo::is_repo() {
                 println!("{}: {}. {}", 
                         "Error".bright_red().bold(),
@@ -245,7 +647,7 @@ fn main() {
             
             match BlocRepo::new() {
                 Ok(mut repo) => {
-                    if let Err(e) = commands::add_files(&mut repo, files) {
+                    if let Err(e) = commands::add_files(&mut repo, files, *force) {
                         println!("{}: {}", "Error adding files".bright_red().bold(), e);
                     }
                 }
@@ -253,7 +655,7 @@ fn main() {
             }
         }
         
-        Commands::Reset { files } => {
+        Commands::Reset { files, paths, keep } => {
             if !BlocRepo::is_repo() {
                 println!("{}: {}. {}", 
                         "Error".bright_red().bold(),
@@ -264,7 +666,27 @@ fn main() {
             
             match BlocRepo::new() {
                 Ok(mut repo) => {
-                    if let Err(e) = commands::reset_files(&mut repo, files) {
+                    let result = if *keep {
+                        if files.len() > 1 || !paths.is_empty() {
+                            println!("{}: {}", 
+                                    "Error".bright_red().bold(), 
+                                    "--keep takes a single commit and no paths".bright_red());
+                            return;
+                        }
+                        commands::reset_keep(&mut repo, files.first().map(String::as_str).unwrap_or("HEAD"))
+                    } else if !paths.is_empty() {
+                        if files.len() > 1 {
+                            println!("{}: {}", 
+                                    "Error".bright_red().bold(), 
+                                    "Only one commit may be given before '--'".bright_red());
+                            return;
+                        }
+                        let revision = files.first().map(String::as_str).unwrap_or("HEAD");
+                        commands::reset_paths_to_commit(&mut repo, revision, paths)
+                    } else {
+                        commands::reset_files(&mut repo, files)
+                    };
+                    if let Err(e) = result {
                         println!("{}: {}", "Error resetting files".bright_red().bold(), e);
                     }
                 }
@@ -272,7 +694,26 @@ fn main() {
             }
         }
         
-        Commands::Commit { message } => {
+        Commands::Restore { files, staged, source } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            match BlocRepo::new() {
+                Ok(mut repo) => {
+                    if let Err(e) = commands::restore(&mut repo, files, *staged, source.as_deref()) {
+                        println!("{}: {}", "Error restoring files".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+        
+        Commands::Commit { message, author, sign, fixup, squash } => {
             if !BlocRepo::is_repo() {
                 println!("{}: {}. {}", 
                         "Error".bright_red().bold(),
@@ -283,15 +724,77 @@ fn main() {
             
             match BlocRepo::new() {
                 Ok(mut repo) => {
-                    if let Err(e) = commands::commit(&mut repo, message) {
-                        println!("{}: {}", "Error committing".bright_red().bold(), e);
+                    let message = match (fixup, squash) {
+                        (Some(target), _) => commands::autosquash_message(&repo, target, false, None).map(Some),
+                        (_, Some(target)) => commands::autosquash_message(&repo, target, true, message.as_deref()).map(Some),
+                        _ => Ok(message.clone()),
+                    };
+                    let result = message.and_then(|message| {
+                        commands::commit(&mut repo, message.as_deref(), author.as_deref(), *sign)
+                    });
+                    match result {
+                        Ok(()) => {
+                            let result = gc::prune_cutoff(&repo.config.gc.pruneexpire)
+                                .map_err(|e| e.into())
+                                .and_then(|prune_before| gc::auto_gc(&repo, prune_before));
+                            if let Err(e) = result {
+                                println!("{}: {}", "Error running gc".bright_red().bold(), e);
+                            }
+                        }
+                        Err(e) => println!("{}: {}", "Error committing".bright_red().bold(), e),
                     }
                 }
                 Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
             }
         }
         
-        Commands::Log { oneline } => {
+        Commands::VerifyCommit { revision } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                std::process::exit(1);
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    match commands::verify_commit(&repo, revision) {
+                        Ok(true) => {}
+                        Ok(false) => std::process::exit(1),
+                        Err(e) => {
+                            println!("{}: {}", "Error verifying commit".bright_red().bold(), e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => {
+                    println!("{}: {}", "Error".bright_red().bold(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        
+        Commands::Reflog { reference } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    if let Err(e) = commands::reflog(&repo, reference) {
+                        println!("{}: {}", "Error reading reflog".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Log { oneline, show_signature, all, branches, tags, max_count, reverse } => {
             if !BlocRepo::is_repo() {
                 println!("{}: {}. {}", 
                         "Error".bright_red().bold(),
@@ -302,15 +805,28 @@ fn main() {
             
             match BlocRepo::new() {
                 Ok(repo) => {
-                    if let Err(e) = commands::log(&repo, *oneline) {
-                        println!("{}: {}", "Error showing log".bright_red().bold(), e);
+                    match commands::log(&repo, &commands::LogOptions {
+                        oneline: *oneline,
+                        show_signature: *show_signature,
+                        all: *all,
+                        branches: *branches,
+                        tags: *tags,
+                        max_count: *max_count,
+                        reverse: *reverse,
+                    }) {
+                        Ok(true) => {}
+                        Ok(false) => std::process::exit(1),
+                        Err(e) => {
+                            println!("{}: {}", "Error showing log".bright_red().bold(), e);
+                            std::process::exit(1);
+                        }
                     }
                 }
                 Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
             }
         }
         
-        Commands::Status => {
+        Commands::Status { untracked_files } => {
             if !BlocRepo::is_repo() {
                 println!("{}: {}. {}", 
                         "Error".bright_red().bold(),
@@ -321,7 +837,7 @@ fn main() {
             
             match BlocRepo::new() {
                 Ok(repo) => {
-                    if let Err(e) = commands::status(&repo) {
+                    if let Err(e) = commands::status(&repo, *untracked_files) {
                         println!("{}: {}", "Error showing status".bright_red().bold(), e);
                     }
                 }
@@ -329,7 +845,34 @@ fn main() {
             }
         }
         
-        Commands::Branch { name, list, delete, force, rename } => {
+        Commands::Verify => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                std::process::exit(1);
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    match commands::verify(&repo) {
+                        Ok(true) => {}
+                        Ok(false) => std::process::exit(1),
+                        Err(e) => {
+                            println!("{}: {}", "Error verifying repository".bright_red().bold(), e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => {
+                    println!("{}: {}", "Error".bright_red().bold(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        
+        Commands::Branch { name, list, delete, force, dry_run, rename, remotes, all, sort } => {
             if !BlocRepo::is_repo() {
                 println!("{}: {}. {}", 
                         "Error".bright_red().bold(),
@@ -340,8 +883,8 @@ fn main() {
             
             match BlocRepo::new() {
                 Ok(mut repo) => {
-                    if let Some(branch_to_delete) = delete {
-                        if let Err(e) = branches::delete_branch(&mut repo, branch_to_delete, *force) {
+                    if let Some(branches_to_delete) = delete {
+                        if let Err(e) = branches::delete_branches(&mut repo, branches_to_delete, *force, *dry_run) {
                             println!("{}: {}", "Error deleting branch".bright_red().bold(), e);
                         }
                     } else if let Some(rename_args) = rename {
@@ -354,21 +897,59 @@ fn main() {
                                     "Error".bright_red().bold(), 
                                     "Rename requires old and new branch names".bright_red());
                         }
-                    } else if *list || name.is_none() {
-                        if let Err(e) = branches::list_branches(&repo) {
+                    } else if *list || *remotes || *all || sort.is_some() || name.is_none() {
+                        if let Err(e) = branches::list_branches(&repo, !*remotes || *all, *remotes || *all, sort.unwrap_or_default()) {
                             println!("{}: {}", "Error listing branches".bright_red().bold(), e);
                         }
-                    } else if let Some(branch_name) = name {
-                        if let Err(e) = branches::create_branch(&mut repo, branch_name) {
-                            println!("{}: {}", "Error creating branch".bright_red().bold(), e);
-                        }
+                    } else if let Some(branch_name) = name
+                        && let Err(e) = branches::create_branch(&mut repo, branch_name) {
+                        println!("{}: {}", "Error creating branch".bright_red().bold(), e);
                     }
                 }
                 Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
             }
         }
         
-        Commands::Checkout { branch } => {
+        Commands::Tag { name, revision, annotate, message, sign, delete, list, lines, verify, force, sort } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                std::process::exit(1);
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    let result = match name {
+                        Some(name) if *verify => match tags::verify_tag(&repo, name) {
+                            Ok(true) => Ok(()),
+                            Ok(false) => std::process::exit(1),
+                            Err(e) => Err(e),
+                        },
+                        Some(name) if *delete => tags::delete_tag(&repo, name).map_err(Into::into),
+                        Some(name) if !*list && sort.is_none() => {
+                            if (*annotate || *sign) && message.is_none() {
+                                Err("Annotated tags need a message (use -m <message>)".into())
+                            } else {
+                                tags::create_tag(&repo, name, revision.as_deref(), message.as_deref(), *sign, *force)
+                            }
+                        }
+                        _ => tags::list_tags(&repo, *lines, sort.unwrap_or_default()),
+                    };
+                    if let Err(e) = result {
+                        println!("{}: {}", "Error".bright_red().bold(), e);
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    println!("{}: {}", "Error".bright_red().bold(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Checkout { branch, paths } => {
             if !BlocRepo::is_repo() {
                 println!("{}: {}. {}", 
                         "Error".bright_red().bold(),
@@ -379,82 +960,483 @@ fn main() {
             
             match BlocRepo::new() {
                 Ok(mut repo) => {
-                    if let Err(e) = branches::checkout(&mut repo, branch) {
-                        println!("{}: {}", "Error checking out branch".bright_red().bold(), e);
+                    if !paths.is_empty() {
+                        if let Err(e) = commands::checkout_paths(&mut repo, branch.as_deref(), paths) {
+                            println!("{}: {}", "Error checking out paths".bright_red().bold(), e);
+                        }
+                    } else if let Some(branch) = branch {
+                        if let Err(e) = branches::checkout(&mut repo, branch) {
+                            println!("{}: {}", "Error checking out branch".bright_red().bold(), e);
+                        }
+                    } else {
+                        println!("{}: {}", 
+                                "Error".bright_red().bold(), 
+                                "Specify a branch, or paths after '--'".bright_red());
                     }
                 }
                 Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
             }
         }
 
-        Commands::Push { remote, branch, force } => {
-            println!("{}: {}", 
-                    "Push functionality".bright_yellow().bold(), 
-                    "not yet implemented".bright_yellow());
-            println!("Remote: {}", remote.as_deref().unwrap_or("origin").bright_cyan());
-            if let Some(b) = branch {
-                println!("Branch: {}", b.bright_cyan());
+        Commands::Switch { branch, create } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
             }
-            if *force {
-                println!("Force: {}", "true".bright_red());
+            
+            match BlocRepo::new() {
+                Ok(mut repo) => {
+                    if let Err(e) = branches::switch(&mut repo, branch, *create) {
+                        println!("{}: {}", "Error switching branch".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
             }
         }
 
-        Commands::Fetch { remote } => {
-            println!("{}: {}", 
-                    "Fetch functionality".bright_yellow().bold(), 
-                    "not yet implemented".bright_yellow());
-            println!("Remote: {}", remote.as_deref().unwrap_or("origin").bright_cyan());
-        }
-
-        Commands::Pull { remote, branch } => {
-            println!("{}: {}", 
-                    "Pull functionality".bright_yellow().bold(), 
-                    "not yet implemented".bright_yellow());
-            println!("Remote: {}", remote.as_deref().unwrap_or("origin").bright_cyan());
-            if let Some(b) = branch {
-                println!("Branch: {}", b.bright_cyan());
+        Commands::Push { remote, branch, force, set_upstream } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
             }
-        }
 
-        Commands::Diff { staged } => {
-            println!("{}: {}", 
-                    "Diff functionality".bright_yellow().bold(), 
-                    "not yet implemented".bright_yellow());
-            if *staged {
-                println!("Mode: {}", "staged changes".bright_cyan());
-            } else {
-                println!("Mode: {}", "working directory changes".bright_cyan());
+            match BlocRepo::new() {
+                Ok(mut repo) => {
+                    if let Err(e) = remote::push(&mut repo, remote.as_deref(), branch.as_deref(), *force, *set_upstream) {
+                        println!("{}: {}", "Error pushing".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
             }
         }
 
-        Commands::Merge { branch } => {
-            println!("{}: {}", 
-                    "Merge functionality".bright_yellow().bold(), 
-                    "not yet implemented".bright_yellow());
-            println!("Branch: {}", branch.bright_cyan());
-        }
+        Commands::Fetch { remote, prune } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
 
-        Commands::Show { target } => {
-            println!("{}: {}", 
-                    "Show functionality".bright_yellow().bold(), 
-                    "not yet implemented".bright_yellow());
-            println!("Target: {}", target.bright_cyan());
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    if let Err(e) = remote::fetch(&repo, remote.as_deref().unwrap_or("origin"), *prune) {
+                        println!("{}: {}", "Error fetching".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
         }
 
-        Commands::Rm { files, recursive } => {
-            println!("{}: {}", 
-                    "Remove functionality".bright_yellow().bold(), 
-                    "not yet implemented".bright_yellow());
-            println!("Files: {}", files.join(", ").bright_cyan());
-            if *recursive {
-                println!("Recursive: {}", "true".bright_red());
+        Commands::Pull { remote, branch } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
             }
-        }
-    }
-}
 
-fn handle_config_command(action: &ConfigCommands) {
+            match BlocRepo::new() {
+                Ok(mut repo) => {
+                    if let Err(e) = remote::pull(&mut repo, remote.as_deref(), branch.as_deref()) {
+                        println!("{}: {}", "Error pulling".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Diff { commits, staged, find_renames, word_diff, color_words, exit_code, quiet } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    match commands::diff(&repo, commits, &commands::DiffOptions {
+                        staged: *staged,
+                        find_renames: *find_renames,
+                        word_diff: if *color_words {
+                            Some(diff::WordDiff::Color)
+                        } else if *word_diff {
+                            Some(diff::WordDiff::Markers)
+                        } else {
+                            None
+                        },
+                        quiet: *quiet,
+                    }) {
+                        Ok(true) if *exit_code || *quiet => std::process::exit(1),
+                        Ok(_) => {}
+                        Err(e) => {
+                            println!("{}: {}", "Error showing diff".bright_red().bold(), e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::DiffTree { revision, other, find_renames, json } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    if let Err(e) = commands::diff_tree(&repo, revision, other.as_deref(), *find_renames, *json) {
+                        println!("{}: {}", "Error comparing trees".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Merge { branch, no_ff, abort } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            match BlocRepo::new() {
+                Ok(mut repo) => {
+                    let result = match branch {
+                        Some(branch) if !*abort => merge::merge(&mut repo, branch, *no_ff),
+                        _ => merge::abort_merge(&mut repo),
+                    };
+                    if let Err(e) = result {
+                        println!("{}: {}", "Error merging".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Rebase { upstream, interactive, autosquash, continue_rebase, abort } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+
+            match BlocRepo::new() {
+                Ok(mut repo) => {
+                    let result = if *abort {
+                        rebase::abort_rebase(&mut repo)
+                    } else if *continue_rebase {
+                        rebase::continue_rebase(&mut repo)
+                    } else if let Some(upstream) = upstream {
+                        rebase::rebase(&mut repo, upstream, *interactive, *autosquash)
+                    } else {
+                        Err("Specify the commit to rebase onto".into())
+                    };
+                    if let Err(e) = result {
+                        println!("{}: {}", "Error rebasing".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Blame { file, revision, lines, porcelain } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    if let Err(e) = blame::blame(&repo, file, revision.as_deref(), lines.as_deref(), *porcelain) {
+                        println!("{}: {}", "Error blaming file".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Show { target, stat, name_status, json } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            let format = if *json {
+                commands::ShowFormat::Json
+            } else if *name_status {
+                commands::ShowFormat::NameStatus
+            } else if *stat {
+                commands::ShowFormat::Stat
+            } else {
+                commands::ShowFormat::Patch
+            };
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    if let Err(e) = commands::show(&repo, target, format) {
+                        println!("{}: {}", "Error showing object".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Repack => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    if let Err(e) = gc::repack(&repo) {
+                        println!("{}: {}", "Error repacking".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::CountObjects { verbose } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    if let Err(e) = gc::count_objects(&repo, *verbose) {
+                        println!("{}: {}", "Error counting objects".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Gc { prune, auto } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    let expire = prune.as_deref().unwrap_or(&repo.config.gc.pruneexpire);
+                    let prune_before = match gc::prune_cutoff(expire) {
+                        Ok(prune_before) => prune_before,
+                        Err(e) => {
+                            println!("{}: {}", "Error".bright_red().bold(), e.bright_red());
+                            return;
+                        }
+                    };
+                    let result = if *auto {
+                        gc::auto_gc(&repo, prune_before).map(|_| ())
+                    } else {
+                        gc::gc(&repo, prune_before)
+                    };
+                    if let Err(e) = result {
+                        println!("{}: {}", "Error running gc".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::RevParse { revision } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    match repo.resolve_revision(revision) {
+                        Ok(hash) => println!("{}", hash),
+                        Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::ShowRef { heads, tags } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                std::process::exit(1);
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    match commands::show_ref(&repo, *heads, *tags) {
+                        Ok(true) => {}
+                        Ok(false) => std::process::exit(1),
+                        Err(e) => println!("{}: {}", "Error listing refs".bright_red().bold(), e),
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::SymbolicRef { name, target, short } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                std::process::exit(1);
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    if let Err(e) = commands::symbolic_ref(&repo, name, target.as_deref(), *short) {
+                        println!("{}: {}", "Error".bright_red().bold(), e);
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Rm { files, recursive, cached, force } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            match BlocRepo::new() {
+                Ok(mut repo) => {
+                    if let Err(e) = commands::remove_files(&mut repo, files, *recursive, *cached, *force) {
+                        println!("{}: {}", "Error removing files".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::Mv { paths, keep_going } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            match BlocRepo::new() {
+                Ok(mut repo) => {
+                    let (destination, sources) = paths.split_last().expect("clap requires two paths");
+                    if let Err(e) = commands::move_paths(&mut repo, sources, destination, *keep_going) {
+                        println!("{}: {}", "Error moving files".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::CatFile { object, show_type, size, pretty, batch } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    let mode = if *show_type {
+                        Some(commands::CatFileMode::Type)
+                    } else if *size {
+                        Some(commands::CatFileMode::Size)
+                    } else if *pretty {
+                        Some(commands::CatFileMode::Contents)
+                    } else {
+                        None
+                    };
+                    let result = match (object, mode) {
+                        _ if *batch => commands::cat_file_batch(&repo).map_err(Into::into),
+                        (Some(object), Some(mode)) => commands::cat_file(&repo, object, mode),
+                        _ => Err("Specify one of -t, -s, -p or --batch".into()),
+                    };
+                    if let Err(e) = result {
+                        println!("{}: {}", "Error reading object".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+
+        Commands::LsTree { revision } => {
+            if !BlocRepo::is_repo() {
+                println!("{}: {}. {}", 
+                        "Error".bright_red().bold(),
+                        "Not a bloc repository".bright_red(), 
+                        "Run 'bloc init' first".bright_yellow());
+                return;
+            }
+            
+            match BlocRepo::new() {
+                Ok(repo) => {
+                    if let Err(e) = commands::ls_tree(&repo, revision) {
+                        println!("{}: {}", "Error listing tree".bright_red().bold(), e);
+                    }
+                }
+                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+            }
+        }
+    }
+}
+
+fn handle_config_command(action: &ConfigCommands) {
+    // A broken config must still be editable, so this comes before loading it
+    if let ConfigCommands::Edit { global } = action {
+        handle_config_edit(*global);
+        return;
+    }
+
     match BlocConfig::load() {
         Ok(mut config) => {
             match action {
@@ -480,6 +1462,152 @@ fn handle_config_command(action: &ConfigCommands) {
                                         value.white());
                             }
                         }
+                        "user.signingkey" => {
+                            config.user.signingkey = Some(value.clone());
+                            if let Err(e) = config.save() {
+                                println!("{}: {}", "Error".bright_red().bold(), e);
+                            } else {
+                                println!("{} {}", 
+                                        "Set".bright_green().bold(), 
+                                        key.bright_blue());
+                            }
+                        }
+                        "core.editor" => {
+                            config.core.editor = Some(value.clone());
+                            if let Err(e) = config.save() {
+                                println!("{}: {}", "Error".bright_red().bold(), e);
+                            } else {
+                                println!("{} {} = {}", 
+                                        "Set".bright_green().bold(), 
+                                        key.bright_blue(), 
+                                        value.white());
+                            }
+                        }
+                        "core.hashalgo" => {
+                            println!("{}: {} {}", 
+                                    "Error".bright_red().bold(), 
+                                    "The hash algorithm is fixed when the repository is created; cannot set".bright_red(), 
+                                    key.bright_cyan());
+                        }
+                        "core.ignorecase" => {
+                            match value.parse::<bool>() {
+                                Ok(ignorecase) => {
+                                    config.core.ignorecase = ignorecase;
+                                    if let Err(e) = config.save() {
+                                        println!("{}: {}", "Error".bright_red().bold(), e);
+                                    } else {
+                                        println!("{} {} = {}", 
+                                                "Set".bright_green().bold(), 
+                                                key.bright_blue(), 
+                                                value.white());
+                                    }
+                                }
+                                Err(_) => println!("{}: {} {}", 
+                                            "Error".bright_red().bold(), 
+                                            "Expected true or false for".bright_red(), 
+                                            key.bright_cyan()),
+                            }
+                        }
+                        "push.default" => {
+                            if ["simple", "upstream", "current", "nothing"].contains(&value.as_str()) {
+                                config.push.default = value.clone();
+                                if let Err(e) = config.save() {
+                                    println!("{}: {}", "Error".bright_red().bold(), e);
+                                } else {
+                                    println!("{} {} = {}", 
+                                            "Set".bright_green().bold(), 
+                                            key.bright_blue(), 
+                                            value.white());
+                                }
+                            } else {
+                                println!("{}: {} {}", 
+                                        "Error".bright_red().bold(), 
+                                        "Expected simple, upstream, current or nothing for".bright_red(), 
+                                        key.bright_cyan());
+                            }
+                        }
+                        upstream_key if upstream_key.starts_with("branches.")
+                            && (upstream_key.ends_with(".remote") || upstream_key.ends_with(".merge")) => {
+                            let (branch, field) = upstream_key["branches.".len()..].rsplit_once('.').unwrap_or_default();
+                            let upstream = config.branches.entry(branch.to_string()).or_insert_with(|| BranchConfig {
+                                remote: "origin".to_string(),
+                                merge: branch.to_string(),
+                            });
+                            if field == "remote" {
+                                upstream.remote = value.clone();
+                            } else {
+                                upstream.merge = value.clone();
+                            }
+                            if let Err(e) = config.save() {
+                                println!("{}: {}", "Error".bright_red().bold(), e);
+                            } else {
+                                println!("{} {} = {}", 
+                                        "Set".bright_green().bold(), 
+                                        key.bright_blue(), 
+                                        value.white());
+                            }
+                        }
+                        "color.diff.new" | "color.diff.old" | "color.diff.meta" => {
+                            match value.parse::<diff::DiffStyle>() {
+                                Ok(_) => {
+                                    let diff_colors = &mut config.color.diff;
+                                    let spec = match key.as_str() {
+                                        "color.diff.new" => &mut diff_colors.new,
+                                        "color.diff.old" => &mut diff_colors.old,
+                                        _ => &mut diff_colors.meta,
+                                    };
+                                    *spec = Some(value.clone());
+                                    if let Err(e) = config.save() {
+                                        println!("{}: {}", "Error".bright_red().bold(), e);
+                                    } else {
+                                        println!("{} {} = {}", 
+                                                "Set".bright_green().bold(), 
+                                                key.bright_blue(), 
+                                                value.white());
+                                    }
+                                }
+                                Err(e) => println!("{}: {} {}: {}", 
+                                            "Error".bright_red().bold(), 
+                                            "Invalid color for".bright_red(), 
+                                            key.bright_cyan(), 
+                                            e),
+                            }
+                        }
+                        "gc.auto" => {
+                            match value.parse::<u64>() {
+                                Ok(auto) => {
+                                    config.gc.auto = auto;
+                                    if let Err(e) = config.save() {
+                                        println!("{}: {}", "Error".bright_red().bold(), e);
+                                    } else {
+                                        println!("{} {} = {}", 
+                                                "Set".bright_green().bold(), 
+                                                key.bright_blue(), 
+                                                value.white());
+                                    }
+                                }
+                                Err(_) => println!("{}: {} {}", 
+                                            "Error".bright_red().bold(), 
+                                            "Expected a number of objects for".bright_red(), 
+                                            key.bright_cyan()),
+                            }
+                        }
+                        "gc.pruneexpire" => {
+                            match gc::prune_cutoff(value) {
+                                Ok(_) => {
+                                    config.gc.pruneexpire = value.clone();
+                                    if let Err(e) = config.save() {
+                                        println!("{}: {}", "Error".bright_red().bold(), e);
+                                    } else {
+                                        println!("{} {} = {}", 
+                                                "Set".bright_green().bold(), 
+                                                key.bright_blue(), 
+                                                value.white());
+                                    }
+                                }
+                                Err(e) => println!("{}: {}", "Error".bright_red().bold(), e.bright_red()),
+                            }
+                        }
                         _ => {
                             println!("{}: {} {}", 
                                     "Error".bright_red().bold(), 
@@ -488,24 +1616,145 @@ fn handle_config_command(action: &ConfigCommands) {
                         }
                     }
                 }
-                ConfigCommands::Get { key } => {
-                    match key.as_str() {
-                        "user.name" => println!("{}", config.user.name.white()),
-                        "user.email" => println!("{}", config.user.email.white()),
-                        _ => println!("{}: {}", 
+                ConfigCommands::Get { key, bool, int, show_secret } => {
+                    let Some(value) = config.get(key) else {
+                        println!("{}: {} {}", 
+                                "Error".bright_red().bold(), 
+                                "Configuration key is not set:".bright_red(), 
+                                key.bright_cyan());
+                        std::process::exit(1);
+                    };
+                    let value = if config::is_secret(key) && !*show_secret {
+                        Some("(set)".to_string())
+                    } else if *bool {
+                        config::parse_bool(&value).map(|value| value.to_string())
+                    } else if *int {
+                        config::parse_int(&value).map(|value| value.to_string())
+                    } else {
+                        Some(value)
+                    };
+                    match value {
+                        Some(value) => println!("{}", value.white()),
+                        None => {
+                            println!("{}: {} {} {}", 
                                     "Error".bright_red().bold(), 
-                                    "Unknown configuration key".bright_red()),
+                                    "Invalid".bright_red(), 
+                                    if *bool { "boolean" } else { "integer" }.bright_red(), 
+                                    format!("value for {}", key).bright_red());
+                            std::process::exit(2);
+                        }
                     }
                 }
-                ConfigCommands::List => {
-                    config.show_config();
+                ConfigCommands::List { show_origin } => {
+                    if *show_origin {
+                        config.show_config_origins();
+                    } else {
+                        config.show_config();
+                    }
                 }
+                ConfigCommands::Edit { .. } => unreachable!("handled before the config is loaded"),
             }
         }
         Err(e) => println!("{}: {}", "Error loading config".bright_red().bold(), e),
     }
 }
 
+fn handle_config_edit(global: bool) {
+    let path = if global {
+        match config::global_config_path() {
+            Some(path) => path,
+            None => {
+                println!("{}: {}", "Error".bright_red().bold(), "Cannot locate the home directory".bright_red());
+                return;
+            }
+        }
+    } else {
+        if !BlocRepo::is_repo() {
+            println!("{}: {}. {}", 
+                    "Error".bright_red().bold(),
+                    "Not a bloc repository".bright_red(), 
+                    "Run 'bloc init' first".bright_yellow());
+            return;
+        }
+        config::repo_config_path().unwrap_or_else(|| PathBuf::from(".bloc/config"))
+    };
+
+    // Fall back to $VISUAL/$EDITOR when the current config cannot be read
+    let editor = BlocConfig::load().ok().and_then(|config| config.core.editor);
+    match config::edit_config_file(&path, editor.as_deref()) {
+        Ok(()) => println!("{} {}", "Updated".bright_green().bold(), path.display().to_string().bright_cyan()),
+        Err(e) => println!("{}: {}", "Edit rejected, previous config restored".bright_red().bold(), e),
+    }
+}
+
+fn handle_sparse_checkout_command(action: &SparseCheckoutCommands) {
+    if !BlocRepo::is_repo() {
+        println!("{}: {}. {}", 
+                "Error".bright_red().bold(),
+                "Not a bloc repository".bright_red(), 
+                "Run 'bloc init' first".bright_yellow());
+        return;
+    }
+
+    match BlocRepo::new() {
+        Ok(repo) => {
+            let result = match action {
+                SparseCheckoutCommands::Set { patterns } => commands::sparse_checkout_set(&repo, patterns),
+                SparseCheckoutCommands::List => {
+                    commands::sparse_checkout_list(&repo);
+                    Ok(())
+                }
+                SparseCheckoutCommands::Disable => commands::sparse_checkout_disable(&repo),
+            };
+            if let Err(e) = result {
+                println!("{}: {}", "Error".bright_red().bold(), e);
+            }
+        }
+        Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+    }
+}
+
+/// What `stash push` saves besides tracked changes, from its `-u` and `-a` flags.
+fn stash_untracked(include_untracked: bool, all: bool) -> stash::UntrackedFiles {
+    if all {
+        stash::UntrackedFiles::IncludeIgnored
+    } else if include_untracked {
+        stash::UntrackedFiles::Include
+    } else {
+        stash::UntrackedFiles::Leave
+    }
+}
+
+fn handle_stash_command(action: Option<&StashCommands>, message: Option<&str>, untracked: stash::UntrackedFiles) {
+    if !BlocRepo::is_repo() {
+        println!("{}: {}. {}", 
+                "Error".bright_red().bold(),
+                "Not a bloc repository".bright_red(), 
+                "Run 'bloc init' first".bright_yellow());
+        return;
+    }
+
+    match BlocRepo::new() {
+        Ok(mut repo) => {
+            let result = match action {
+                None => stash::push(&mut repo, message, untracked),
+                Some(StashCommands::Push { message, include_untracked, all }) => {
+                    stash::push(&mut repo, message.as_deref(), stash_untracked(*include_untracked, *all))
+                }
+                Some(StashCommands::List) => stash::list(&repo).map_err(Into::into),
+                Some(StashCommands::Pop { stash }) => stash::pop(&mut repo, stash.as_deref()),
+                Some(StashCommands::Apply { stash }) => stash::apply(&mut repo, stash.as_deref()).map(|_| ()),
+                Some(StashCommands::Drop { stash }) => stash::drop(&repo, stash.as_deref()),
+                Some(StashCommands::Clear) => stash::clear(&repo).map_err(Into::into),
+            };
+            if let Err(e) = result {
+                println!("{}: {}", "Error".bright_red().bold(), e);
+            }
+        }
+        Err(e) => println!("{}: {}", "Error".bright_red().bold(), e),
+    }
+}
+
 fn handle_remote_command(action: &RemoteCommands) {
     if !BlocRepo::is_repo() {
         println!("{}: {}. {}", 
@@ -0,0 +1,346 @@
+use crate::commands;
+use crate::diff;
+use crate::objects::IndexEntry;
+use crate::repository::BlocRepo;
+use chrono::Utc;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashSet};
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use colored::*;
+
+/// How one path comes out of a three-way merge.
+pub enum Outcome {
+    /// The merged blob, or `None` when the path is deleted
+    Resolved(Option<String>),
+    /// Both sides changed the path incompatibly; `content` is what is left
+    /// in the working tree for the user to fix
+    Conflict { content: Vec<u8>, stage_hash: String },
+}
+
+/// Merge `target` into the current branch. When HEAD is an ancestor of the
+/// target the branch is fast-forwarded unless `no_ff` is set; otherwise the
+/// changes of both sides since their merge base are combined and recorded
+/// in a merge commit with both tips as parents.
+pub fn merge(repo: &mut BlocRepo, target: &str, no_ff: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        println!("{}", "Cannot merge in a bare repository".bright_red().bold());
+        return Ok(());
+    }
+    if repo.merge_head()?.is_some() {
+        println!("{}: {}",
+                "Cannot merge".bright_red().bold(),
+                "a merge is already in progress (commit it first)".bright_red());
+        return Ok(());
+    }
+    if !repo.index.entries.is_empty() {
+        println!("{}: {}",
+                "Cannot merge".bright_red().bold(),
+                "you have staged changes (commit or reset them first)".bright_red());
+        return Ok(());
+    }
+
+    let Some(ours) = repo.head_commit()? else {
+        return Err("Cannot merge into a branch with no commits".into());
+    };
+    let theirs = repo.resolve_revision(target)?;
+    let source = if repo.refs_dir().join("heads").join(target).is_file() {
+        format!("branch '{}'", target)
+    } else if repo.refs_dir().join("remotes").join(target).is_file() {
+        format!("remote-tracking branch '{}'", target)
+    } else {
+        format!("commit '{}'", target)
+    };
+    let mut message = format!("Merge {} into '{}'", source, repo.get_current_branch()?);
+
+    if ancestors(repo, &ours)?.contains(&theirs) {
+        println!("{}", "Already up to date.".bright_green());
+        return Ok(());
+    }
+
+    let ours_tree = repo.read_tree(&ours)?;
+    let theirs_tree = repo.read_tree(&theirs)?;
+    let base = merge_base(repo, &ours, &theirs)?;
+
+    if base.as_deref() == Some(ours.as_str()) && !no_ff {
+        if !check_overwrites(repo, &ours_tree, &theirs_tree) {
+            return Ok(());
+        }
+        repo.update_working_tree(&ours_tree, &theirs_tree)?;
+        repo.index.save()?;
+        repo.update_head(Some(&ours), &theirs, &format!("merge {}: Fast-forward", target))?;
+        println!("{} {}..{}",
+                "Fast-forward".bright_green().bold(),
+                ours[..8].bright_yellow(),
+                theirs[..8].bright_yellow());
+        return Ok(());
+    }
+
+    let base_tree = match &base {
+        Some(base) => repo.read_tree(base)?,
+        None => Rc::default(),
+    };
+
+    let outcomes = merge_trees(repo, &base_tree, &ours_tree, &theirs_tree, target)?;
+
+    // What the working tree will hold afterwards, to check nothing local is lost
+    if !check_overwrites(repo, &ours_tree, &result_tree(repo, &ours_tree, &outcomes)) {
+        return Ok(());
+    }
+
+    let conflicts = apply_outcomes(repo, &ours_tree, outcomes)?;
+    repo.index.save()?;
+
+    if !conflicts.is_empty() {
+        message.push_str("\n\nConflicts:\n");
+        for path in &conflicts {
+            message.push_str(&format!("\t{}\n", path));
+        }
+    }
+    // The message waits in MERGE_MSG until the merge is committed, so it
+    // can be edited while conflicts are resolved
+    fs::write(repo.bloc_dir.join("MERGE_MSG"), format!("{}\n", message.trim_end()))?;
+    fs::write(repo.bloc_dir.join("MERGE_HEAD"), &theirs)?;
+
+    if conflicts.is_empty() {
+        return commands::commit_as(repo, None, None, false, Some(&format!("merge {}", target)));
+    }
+
+    for path in &conflicts {
+        println!("{}: {}", "CONFLICT".bright_red().bold(), path.bright_cyan());
+    }
+    println!("{}", "Automatic merge failed; fix conflicts, 'bloc add' them and then 'bloc commit' the result.".bright_yellow());
+    println!("{}", "The merge message is prepared in .bloc/MERGE_MSG".bright_black());
+    Ok(())
+}
+
+/// Give up on a merge stopped by conflicts: every path the merge staged is
+/// put back as HEAD has it, and the merge state is forgotten. A merge only
+/// starts with nothing staged, so other local changes are left alone.
+pub fn abort_merge(repo: &mut BlocRepo) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.merge_head()?.is_none() {
+        return Err("There is no merge to abort".into());
+    }
+    let head_tree = match repo.head_commit()? {
+        Some(head) => repo.read_tree(&head)?,
+        None => Rc::default(),
+    };
+
+    let merged: Vec<String> = repo.index.entries.keys().cloned().collect();
+    for path in &merged {
+        match head_tree.get(path) {
+            Some(hash) => repo.checkout_file(path, hash)?,
+            None => repo.remove_working_file(path)?,
+        }
+    }
+    repo.index.entries.clear();
+    repo.index.save()?;
+    repo.clear_merge_state()?;
+    println!("{}", "Merge aborted".bright_yellow().bold());
+    Ok(())
+}
+
+/// Three-way merge of whole snapshots: the outcome for every path whose
+/// merged result differs from `ours_tree`.
+pub fn merge_trees(
+    repo: &BlocRepo,
+    base_tree: &BTreeMap<String, String>,
+    ours_tree: &BTreeMap<String, String>,
+    theirs_tree: &BTreeMap<String, String>,
+    theirs_label: &str,
+) -> io::Result<BTreeMap<String, Outcome>> {
+    let paths: BTreeSet<&String> = base_tree.keys().chain(ours_tree.keys()).chain(theirs_tree.keys()).collect();
+    let mut outcomes = BTreeMap::new();
+    for path in paths {
+        let outcome = merge_path(
+            repo,
+            path,
+            base_tree.get(path),
+            ours_tree.get(path),
+            theirs_tree.get(path),
+            theirs_label,
+        )?;
+        match &outcome {
+            Outcome::Resolved(hash) if hash.as_ref() == ours_tree.get(path) => {}
+            _ => {
+                outcomes.insert(path.clone(), outcome);
+            }
+        }
+    }
+    Ok(outcomes)
+}
+
+/// The files the working tree holds once `outcomes` are applied on top of
+/// `ours_tree`, with conflicted paths holding their marked-up content.
+pub fn result_tree(repo: &BlocRepo, ours_tree: &BTreeMap<String, String>, outcomes: &BTreeMap<String, Outcome>) -> BTreeMap<String, String> {
+    let mut result = ours_tree.clone();
+    for (path, outcome) in outcomes {
+        match outcome {
+            Outcome::Resolved(Some(hash)) => result.insert(path.clone(), hash.clone()),
+            Outcome::Resolved(None) => result.remove(path),
+            Outcome::Conflict { content, .. } => result.insert(path.clone(), repo.hash_object(content)),
+        };
+    }
+    result
+}
+
+/// Write the merged files to the working tree and stage them, leaving
+/// conflicted paths marked up and flagged in the index. Returns the
+/// conflicted paths; the index is not saved.
+pub fn apply_outcomes(repo: &mut BlocRepo, ours_tree: &BTreeMap<String, String>, outcomes: BTreeMap<String, Outcome>) -> io::Result<Vec<String>> {
+    let mut conflicts = Vec::new();
+    for (path, outcome) in outcomes {
+        let entry = match outcome {
+            Outcome::Resolved(Some(hash)) => {
+                repo.checkout_file(&path, &hash)?;
+                IndexEntry {
+                    size: repo.read_object(&hash)?.len() as u64,
+                    hash,
+                    mode: "100644".to_string(),
+                    mtime: Utc::now(),
+                    deleted: false,
+                    conflicted: false,
+                }
+            }
+            Outcome::Resolved(None) => {
+                repo.remove_working_file(&path)?;
+                IndexEntry {
+                    hash: ours_tree.get(&path).cloned().unwrap_or_default(),
+                    mode: "100644".to_string(),
+                    size: 0,
+                    mtime: Utc::now(),
+                    deleted: true,
+                    conflicted: false,
+                }
+            }
+            Outcome::Conflict { content, stage_hash } => {
+                if let Some(parent) = std::path::Path::new(&path).parent()
+                    && !parent.as_os_str().is_empty() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, &content)?;
+                conflicts.push(path.clone());
+                IndexEntry {
+                    hash: stage_hash,
+                    mode: "100644".to_string(),
+                    size: content.len() as u64,
+                    mtime: Utc::now(),
+                    deleted: false,
+                    conflicted: true,
+                }
+            }
+        };
+        repo.index.entries.insert(path, entry);
+    }
+    Ok(conflicts)
+}
+
+/// Combine the two sides' versions of one path against the base version.
+fn merge_path(
+    repo: &BlocRepo,
+    path: &str,
+    base: Option<&String>,
+    ours: Option<&String>,
+    theirs: Option<&String>,
+    theirs_label: &str,
+) -> io::Result<Outcome> {
+    if ours == theirs || base == theirs {
+        return Ok(Outcome::Resolved(ours.cloned()));
+    }
+    if base == ours {
+        return Ok(Outcome::Resolved(theirs.cloned()));
+    }
+
+    match (ours, theirs) {
+        (Some(ours), Some(theirs)) => {
+            let base_content = match base {
+                Some(base) => repo.read_object(base)?,
+                None => Rc::default(),
+            };
+            let ours_content = repo.read_object(ours)?;
+            let theirs_content = repo.read_object(theirs)?;
+            if [&base_content, &ours_content, &theirs_content].iter().any(|content| diff::is_binary(content)) {
+                return Ok(Outcome::Conflict { content: Rc::unwrap_or_clone(ours_content), stage_hash: ours.clone() });
+            }
+
+            let (merged, conflicted) = diff::merge_text(
+                &String::from_utf8_lossy(&base_content),
+                &String::from_utf8_lossy(&ours_content),
+                &String::from_utf8_lossy(&theirs_content),
+                "HEAD",
+                theirs_label,
+            );
+            if conflicted {
+                Ok(Outcome::Conflict { content: merged.into_bytes(), stage_hash: ours.clone() })
+            } else {
+                Ok(Outcome::Resolved(Some(repo.write_object(merged.as_bytes())?)))
+            }
+        }
+        // Modified on one side, deleted on the other: keep the surviving version
+        (Some(kept), None) | (None, Some(kept)) => {
+            println!("{}: {} {}",
+                    "Warning".bright_yellow().bold(),
+                    path.bright_cyan(),
+                    "was deleted on one side and modified on the other".bright_yellow());
+            Ok(Outcome::Conflict { content: Rc::unwrap_or_clone(repo.read_object(kept)?), stage_hash: kept.clone() })
+        }
+        (None, None) => Ok(Outcome::Resolved(None)),
+    }
+}
+
+/// Report and refuse a merge that would overwrite local changes.
+fn check_overwrites(repo: &BlocRepo, from: &BTreeMap<String, String>, to: &BTreeMap<String, String>) -> bool {
+    let blocked = repo.overwritten_paths(from, to);
+    if blocked.is_empty() {
+        return true;
+    }
+    println!("{}: {}",
+            "Cannot merge".bright_red().bold(),
+            "local changes to the following files would be overwritten".bright_red());
+    for path in &blocked {
+        println!("  {}", path.bright_red());
+    }
+    println!("{}", "Commit or restore your changes before merging".bright_yellow());
+    false
+}
+
+/// `commit` and every commit reachable from it.
+pub fn ancestors(repo: &BlocRepo, commit: &str) -> io::Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![commit.to_string()];
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        // History may be cut short by missing objects
+        if let Ok(commit) = repo.read_commit(&hash) {
+            pending.extend(commit.parents().cloned());
+        }
+    }
+    Ok(seen)
+}
+
+/// The most recent commit reachable from both `a` and `b`, if any.
+pub fn merge_base(repo: &BlocRepo, a: &str, b: &str) -> io::Result<Option<String>> {
+    let reachable_from_a = ancestors(repo, a)?;
+
+    let mut visited = HashSet::new();
+    let mut pending = BinaryHeap::new();
+    pending.push((repo.read_commit(b)?.timestamp, b.to_string()));
+    while let Some((_, hash)) = pending.pop() {
+        if reachable_from_a.contains(&hash) {
+            return Ok(Some(hash));
+        }
+        if !visited.insert(hash.clone()) {
+            continue;
+        }
+        if let Ok(commit) = repo.read_commit(&hash) {
+            for parent in commit.parents() {
+                if let Ok(parent_commit) = repo.read_commit(parent) {
+                    pending.push((parent_commit.timestamp, parent.clone()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
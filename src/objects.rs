@@ -1,53 +1,158 @@
+use crate::config::CoreConfig;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs;
-use std::io;
-use std::path::Path;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
 use chrono::{DateTime, Utc};
 
+/// The hash function that names a repository's objects. It is chosen when
+/// the repository is created and recorded as `core.hashalgo`, so every
+/// object in one repository is named the same way.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ObjectHasher {
+    #[default]
+    Sha256,
+}
+
+impl FromStr for ObjectHasher {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "sha256" => Ok(ObjectHasher::Sha256),
+            _ => Err(format!("unsupported hash algorithm '{}'", name)),
+        }
+    }
+}
+
+impl ObjectHasher {
+    /// The algorithm `core` records; repositories created before it was
+    /// recorded use SHA-256. Fails for an algorithm this build can't compute.
+    pub fn for_config(core: &CoreConfig) -> io::Result<Self> {
+        Self::named(core.hashalgo.as_deref())
+    }
+
+    /// The algorithm called `name`, or SHA-256 when none is named.
+    pub fn named(name: Option<&str>) -> io::Result<Self> {
+        match name {
+            Some(name) => name.parse().map_err(|e| {
+                io::Error::new(io::ErrorKind::Unsupported, format!("Repository uses an {}", e))
+            }),
+            None => Ok(ObjectHasher::default()),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ObjectHasher::Sha256 => "sha256",
+        }
+    }
+
+    /// The length of an object name in hex digits.
+    pub fn hex_len(self) -> usize {
+        match self {
+            ObjectHasher::Sha256 => 64,
+        }
+    }
+
+    /// Whether `value` has the shape of an object name: hex digits of the
+    /// right length.
+    pub fn is_object_name(self, value: &str) -> bool {
+        value.len() == self.hex_len() && is_hex(value)
+    }
+
+    /// The object name of `content`, in lowercase hex.
+    pub fn hash(self, content: &[u8]) -> String {
+        match self {
+            ObjectHasher::Sha256 => format!("{:x}", Sha256::digest(content)),
+        }
+    }
+}
+
+/// Path of the loose object `hash` under `objects_dir`, fanned out by its
+/// first two characters. A name too short to fan out maps straight under
+/// `objects_dir`, where no object is ever stored.
+pub fn loose_object_path(objects_dir: &Path, hash: &str) -> PathBuf {
+    match hash.split_at_checked(2) {
+        Some((dir, file)) if !file.is_empty() => objects_dir.join(dir).join(file),
+        _ => objects_dir.join(hash),
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Commit {
     pub parent: Option<String>,
+    /// Parents after the first, for merge commits
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merge_parents: Vec<String>,
+    /// `Name <email>` of whoever wrote the change
     pub author: String,
+    /// `Name <email>` of whoever recorded the commit
     pub committer: String,
     pub timestamp: DateTime<Utc>,
     pub message: String,
     pub tree: String,
+    /// HMAC-SHA256 over the rest of the commit, keyed by `user.signingkey`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
+/// An annotated tag: a named, dated message pointing at a commit.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TreeEntry {
-    pub name: String,
-    pub hash: String,
-    pub is_file: bool,
-    pub mode: String, // File permissions/type
+pub struct Tag {
+    /// The tagged commit
+    pub object: String,
+    /// Name of the tag
+    pub tag: String,
+    /// `Name <email>` of whoever created the tag
+    pub tagger: String,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+    /// HMAC-SHA256 over the rest of the tag, keyed by `user.signingkey`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Index {
     pub entries: HashMap<String, IndexEntry>,
+    /// Size and mtime of working files as they were when last written or
+    /// hashed by bloc, so unchanged files need not be read again
+    #[serde(default)]
+    pub stat_cache: RefCell<HashMap<String, FileStat>>,
+    /// Where the index is stored; set when the index is created or loaded
+    #[serde(skip)]
+    path: PathBuf,
+    /// Modification time of the index file when it was loaded
+    #[serde(skip)]
+    written_at: Option<DateTime<Utc>>,
 }
 
+/// What a working file looked like when its content last matched `hash`.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct IndexEntry {
+pub struct FileStat {
     pub hash: String,
-    pub mode: String,
     pub size: u64,
     pub mtime: DateTime<Utc>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Ref {
-    pub name: String,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexEntry {
     pub hash: String,
-    pub ref_type: RefType,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub enum RefType {
-    Branch,
-    Tag,
-    Remote,
+    pub mode: String,
+    pub size: u64,
+    pub mtime: DateTime<Utc>,
+    /// The path is staged for removal in the next commit
+    #[serde(default)]
+    pub deleted: bool,
+    /// The path has merge conflicts that must be resolved before committing
+    #[serde(default)]
+    pub conflicted: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -63,7 +168,7 @@ pub struct PackedObject {
     pub data: Vec<u8>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum ObjectType {
     Commit,
     Tree,
@@ -72,95 +177,138 @@ pub enum ObjectType {
 }
 
 impl Index {
-    pub fn new() -> Self {
+    pub fn new(path: PathBuf) -> Self {
         Index {
             entries: HashMap::new(),
+            stat_cache: RefCell::new(HashMap::new()),
+            path,
+            written_at: None,
         }
     }
 
-    pub fn load() -> io::Result<Self> {
-        let index_path = if Path::new(".bloc").exists() {
-            ".bloc/index"
-        } else {
-            "index" // For bare repositories
-        };
-        
-        if Path::new(index_path).exists() {
-            let content = fs::read_to_string(index_path)?;
-            serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    pub fn load_from(path: &Path) -> io::Result<Self> {
+        if path.exists() {
+            let content = fs::read_to_string(path)?;
+            let mut index: Index = serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            index.path = path.to_path_buf();
+            index.written_at = fs::metadata(path)?.modified().ok().map(DateTime::from);
+            Ok(index)
         } else {
-            Ok(Index::new())
+            Ok(Index::new(path.to_path_buf()))
         }
     }
 
     pub fn save(&self) -> io::Result<()> {
-        let index_path = if Path::new(".bloc").exists() {
-            ".bloc/index"
-        } else {
-            "index"
-        };
-        
         let content = serde_json::to_string_pretty(self)?;
-        fs::write(index_path, content)
+        fs::write(&self.path, content)
     }
 
-    pub fn add_entry(&mut self, path: String, hash: String, size: u64) {
-        let entry = IndexEntry {
-            hash,
-            mode: "100644".to_string(), // Regular file
-            size,
-            mtime: Utc::now(),
-        };
-        self.entries.insert(path, entry);
+    /// The hash recorded for a working file whose size and mtime are
+    /// unchanged since bloc last wrote or hashed it. A file modified no
+    /// earlier than the index was saved may have changed within the mtime
+    /// granularity, so it is never trusted and must be re-hashed.
+    pub fn cached_hash(&self, path: &str, metadata: &fs::Metadata) -> Option<String> {
+        let mtime: DateTime<Utc> = metadata.modified().ok()?.into();
+        if mtime >= self.written_at? {
+            return None;
+        }
+        let cache = self.stat_cache.borrow();
+        let stat = cache.get(path)?;
+        (stat.size == metadata.len() && stat.mtime == mtime).then(|| stat.hash.clone())
     }
 
-    pub fn remove_entry(&mut self, path: &str) -> bool {
-        self.entries.remove(path).is_some()
+    /// Remember that the working file at `path` currently holds `hash`.
+    pub fn record_stat(&self, path: &str, hash: &str, metadata: &fs::Metadata) {
+        let Ok(modified) = metadata.modified() else {
+            return;
+        };
+        self.stat_cache.borrow_mut().insert(path.to_string(), FileStat {
+            hash: hash.to_string(),
+            size: metadata.len(),
+            mtime: modified.into(),
+        });
     }
 
-    pub fn is_staged(&self, path: &str) -> bool {
-        self.entries.contains_key(path)
+    pub fn forget_stat(&self, path: &str) {
+        self.stat_cache.borrow_mut().remove(path);
     }
+}
 
-    pub fn get_staged_files(&self) -> Vec<&String> {
-        self.entries.keys().collect()
+impl IndexEntry {
+    /// The blob this entry stages, or `None` for a staged deletion.
+    pub fn staged_hash(&self) -> Option<&String> {
+        if self.deleted { None } else { Some(&self.hash) }
     }
 }
 
 impl Commit {
-    pub fn new(
-        parent: Option<String>,
-        author: String,
-        message: String,
-        tree: String,
-    ) -> Self {
-        Commit {
-            parent,
-            author: author.clone(),
-            committer: author, // For now, author and committer are the same
-            timestamp: Utc::now(),
-            message,
-            tree,
-        }
-    }
-}
-
-impl TreeEntry {
-    pub fn new_file(name: String, hash: String) -> Self {
-        TreeEntry {
-            name,
-            hash,
-            is_file: true,
-            mode: "100644".to_string(),
+    /// The author as `Name <email>`. Older commits stored the author's name
+    /// and email in the `author` and `committer` fields respectively.
+    pub fn author_signature(&self) -> String {
+        if self.author.contains('<') {
+            self.author.clone()
+        } else {
+            format!("{} <{}>", self.author, self.committer)
         }
     }
 
-    pub fn new_directory(name: String, hash: String) -> Self {
-        TreeEntry {
-            name,
-            hash,
-            is_file: false,
-            mode: "040000".to_string(),
+    /// Every parent, first parent first.
+    pub fn parents(&self) -> impl Iterator<Item = &String> {
+        self.parent.iter().chain(&self.merge_parents)
+    }
+
+    /// Bytes the commit's fields hold on the heap.
+    pub fn heap_size(&self) -> usize {
+        self.parent.as_ref().map_or(0, String::len)
+            + self.merge_parents.iter().map(|parent| size_of::<String>() + parent.len()).sum::<usize>()
+            + self.author.len()
+            + self.committer.len()
+            + self.message.len()
+            + self.tree.len()
+            + self.signature.as_ref().map_or(0, String::len)
+    }
+
+    /// Parse the serialized `path:hash` tree into a map of path to blob hash.
+    pub fn tree_entries(&self) -> BTreeMap<String, String> {
+        self.tree
+            .lines()
+            .filter_map(|line| line.rsplit_once(':'))
+            .map(|(path, hash)| (path.to_string(), hash.to_string()))
+            .collect()
+    }
+}
+
+impl Tag {
+    /// Bytes the tag's fields hold on the heap.
+    pub fn heap_size(&self) -> usize {
+        self.object.len()
+            + self.tag.len()
+            + self.tagger.len()
+            + self.message.len()
+            + self.signature.as_ref().map_or(0, String::len)
+    }
+}
+
+impl ObjectType {
+    /// The type of a stored object. Loose objects carry no type, so commits
+    /// and tags are told apart by their JSON and anything else is a blob.
+    pub fn of(data: &[u8]) -> Self {
+        if serde_json::from_slice::<Commit>(data).is_ok() {
+            ObjectType::Commit
+        } else if serde_json::from_slice::<Tag>(data).is_ok() {
+            ObjectType::Tag
+        } else {
+            ObjectType::Blob
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ObjectType::Commit => "commit",
+            ObjectType::Tree => "tree",
+            ObjectType::Blob => "blob",
+            ObjectType::Tag => "tag",
         }
     }
 }
@@ -184,11 +332,344 @@ impl PackFile {
 
     pub fn finalize(&mut self) {
         // Calculate checksum of all objects
-        use sha2::{Digest, Sha256};
         let mut hasher = Sha256::new();
         for obj in &self.objects {
             hasher.update(&obj.data);
         }
         self.checksum = format!("{:x}", hasher.finalize());
     }
+
+    /// Write the finalized pack as `pack-<checksum>.pack` in `pack_dir`,
+    /// followed by its `.idx`. The pack holds one `<hash> <type> <length>`
+    /// line per object, each followed by the object's bytes; the index
+    /// lists `<hash> <offset> <length>` for every object's data. Both are
+    /// synced to disk, and since readers only use packs that have an index,
+    /// a half-written pack is never read. Returns the pack's path.
+    pub fn write_to(&self, pack_dir: &Path) -> io::Result<PathBuf> {
+        fs::create_dir_all(pack_dir)?;
+        let pack_path = pack_dir.join(format!("pack-{}.pack", self.checksum));
+
+        let mut pack = Vec::new();
+        let mut index = String::new();
+        for object in &self.objects {
+            pack.extend_from_slice(format!("{} {} {}\n", object.hash, object.object_type.name(), object.data.len()).as_bytes());
+            index.push_str(&format!("{} {} {}\n", object.hash, pack.len(), object.data.len()));
+            pack.extend_from_slice(&object.data);
+        }
+
+        let mut file = fs::File::create(&pack_path)?;
+        file.write_all(&pack)?;
+        file.sync_all()?;
+        let mut file = fs::File::create(pack_path.with_extension("idx"))?;
+        file.write_all(index.as_bytes())?;
+        file.sync_all()?;
+        Ok(pack_path)
+    }
+}
+
+/// Where a packed object's data sits.
+struct PackLocation {
+    pack: PathBuf,
+    offset: u64,
+    length: u64,
+}
+
+/// The objects stored in the packs of an objects directory, found through
+/// each pack's `.idx` file.
+#[derive(Default)]
+pub struct PackStore {
+    packs: Vec<PathBuf>,
+    locations: HashMap<String, PackLocation>,
+}
+
+impl PackStore {
+    pub fn load(objects_dir: &Path) -> io::Result<Self> {
+        let mut store = PackStore::default();
+        let pack_dir = objects_dir.join("pack");
+        if !pack_dir.is_dir() {
+            return Ok(store);
+        }
+
+        for entry in fs::read_dir(&pack_dir)? {
+            let idx_path = entry?.path();
+            let pack = idx_path.with_extension("pack");
+            if idx_path.extension().is_none_or(|extension| extension != "idx") || !pack.is_file() {
+                continue;
+            }
+            for line in fs::read_to_string(&idx_path)?.lines() {
+                let mut fields = line.split(' ');
+                if let (Some(hash), Some(Ok(offset)), Some(Ok(length))) =
+                    (fields.next(), fields.next().map(str::parse), fields.next().map(str::parse)) {
+                    store.locations.insert(hash.to_string(), PackLocation { pack: pack.clone(), offset, length });
+                }
+            }
+            store.packs.push(pack);
+        }
+        store.packs.sort();
+        Ok(store)
+    }
+
+    /// The content of a packed object, or `None` if no pack has it.
+    pub fn read(&self, hash: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(location) = self.locations.get(hash) else {
+            return Ok(None);
+        };
+        let mut file = fs::File::open(&location.pack)?;
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut data = vec![0; location.length as usize];
+        file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.locations.contains_key(hash)
+    }
+
+    /// The `.pack` file holding object `hash`.
+    pub fn pack_of(&self, hash: &str) -> Option<&Path> {
+        self.locations.get(hash).map(|location| location.pack.as_path())
+    }
+
+    pub fn size(&self, hash: &str) -> Option<u64> {
+        self.locations.get(hash).map(|location| location.length)
+    }
+
+    pub fn hashes(&self) -> impl Iterator<Item = &String> {
+        self.locations.keys()
+    }
+
+    /// Paths of the `.pack` files, sorted.
+    pub fn packs(&self) -> &[PathBuf] {
+        &self.packs
+    }
+}
+
+/// Objects read so far, with whatever has been decoded from them, so a
+/// command that reads the same commit or blob many times parses it once.
+/// Objects never change once written, so entries only go stale when
+/// objects are removed. Content and decoded values are shared as `Rc`s and
+/// both count toward `capacity`; beyond it the oldest entries are evicted.
+pub struct ObjectCache {
+    entries: HashMap<String, CachedObject>,
+    order: VecDeque<String>,
+    size: usize,
+    capacity: usize,
+}
+
+/// A cached object's content and the values decoded from it so far.
+pub struct CachedObject {
+    pub data: Rc<Vec<u8>>,
+    pub object_type: Option<ObjectType>,
+    pub commit: Option<Rc<Commit>>,
+    pub tag: Option<Rc<Tag>>,
+    pub tree: Option<Rc<BTreeMap<String, String>>>,
+    /// Approximate bytes held by this entry
+    size: usize,
+}
+
+impl ObjectCache {
+    /// The default limit on cached object data, in bytes.
+    pub const DEFAULT_CAPACITY: usize = 64 * 1024 * 1024;
+
+    pub fn new(capacity: usize) -> Self {
+        ObjectCache { entries: HashMap::new(), order: VecDeque::new(), size: 0, capacity }
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&CachedObject> {
+        self.entries.get(hash)
+    }
+
+    /// Cache the content of object `hash`. Objects bigger than the whole
+    /// cache are not kept.
+    pub fn insert(&mut self, hash: &str, data: Rc<Vec<u8>>) {
+        if data.len() > self.capacity || self.entries.contains_key(hash) {
+            return;
+        }
+        let size = data.len();
+        self.order.push_back(hash.to_string());
+        self.entries.insert(hash.to_string(), CachedObject { data, object_type: None, commit: None, tag: None, tree: None, size: 0 });
+        self.charge(hash, size);
+    }
+
+    pub fn set_object_type(&mut self, hash: &str, object_type: ObjectType) {
+        if let Some(object) = self.entries.get_mut(hash) {
+            object.object_type = Some(object_type);
+        }
+    }
+
+    pub fn set_commit(&mut self, hash: &str, commit: Rc<Commit>) {
+        let size = size_of::<Commit>() + commit.heap_size();
+        if let Some(object) = self.entries.get_mut(hash) {
+            object.commit = Some(commit);
+            self.charge(hash, size);
+        }
+    }
+
+    pub fn set_tag(&mut self, hash: &str, tag: Rc<Tag>) {
+        let size = size_of::<Tag>() + tag.heap_size();
+        if let Some(object) = self.entries.get_mut(hash) {
+            object.tag = Some(tag);
+            self.charge(hash, size);
+        }
+    }
+
+    pub fn set_tree(&mut self, hash: &str, tree: Rc<BTreeMap<String, String>>) {
+        let size = tree.iter().map(|(path, hash)| 2 * size_of::<String>() + path.len() + hash.len()).sum();
+        if let Some(object) = self.entries.get_mut(hash) {
+            object.tree = Some(tree);
+            self.charge(hash, size);
+        }
+    }
+
+    /// Count `size` more bytes against entry `hash`, then evict the oldest
+    /// entries until the cache fits again.
+    fn charge(&mut self, hash: &str, size: usize) {
+        if let Some(object) = self.entries.get_mut(hash) {
+            object.size += size;
+            self.size += size;
+        }
+        while self.size > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.size -= evicted.size;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.size = 0;
+    }
+}
+
+/// Sorted object hashes with a fan-out table over the first byte, so an
+/// abbreviated hash is resolved with a binary search inside its bucket
+/// instead of a directory scan. It is kept in `objects/info/object-index`,
+/// one hash per line: new loose objects are appended as they are written,
+/// and `gc` and `repack` rewrite it from what is on disk.
+pub struct ObjectIndex {
+    /// `fanout[b]` is the number of hashes whose first byte is `<= b`
+    fanout: [usize; 256],
+    hashes: Vec<String>,
+}
+
+impl ObjectIndex {
+    pub fn build(objects_dir: &Path) -> io::Result<Self> {
+        let mut hashes = Vec::new();
+        if objects_dir.is_dir() {
+            for bucket in fs::read_dir(objects_dir)? {
+                let bucket = bucket?;
+                let prefix = bucket.file_name().to_string_lossy().to_string();
+                if prefix.len() != 2 || !bucket.path().is_dir() || u8::from_str_radix(&prefix, 16).is_err() {
+                    continue;
+                }
+                for entry in fs::read_dir(bucket.path())? {
+                    let hash = format!("{}{}", prefix, entry?.file_name().to_string_lossy());
+                    if is_hex(&hash) {
+                        hashes.push(hash);
+                    }
+                }
+            }
+        }
+        hashes.extend(PackStore::load(objects_dir)?.hashes().cloned());
+        hashes.sort();
+        hashes.dedup();
+
+        let mut index = ObjectIndex { fanout: [0; 256], hashes };
+        index.rebuild_fanout();
+        Ok(index)
+    }
+
+    fn file(objects_dir: &Path) -> PathBuf {
+        objects_dir.join("info").join("object-index")
+    }
+
+    /// The index stored in `objects_dir`, or `None` if it was never written.
+    pub fn load(objects_dir: &Path) -> io::Result<Option<Self>> {
+        let content = match fs::read_to_string(Self::file(objects_dir)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // Appended hashes arrive unsorted; a damaged line is skipped, as the
+        // index only ever narrows down which objects to look for
+        let mut hashes: Vec<String> = content.lines().filter(|line| line.len() > 2 && is_hex(line)).map(String::from).collect();
+        hashes.sort();
+        hashes.dedup();
+
+        let mut index = ObjectIndex { fanout: [0; 256], hashes };
+        index.rebuild_fanout();
+        Ok(Some(index))
+    }
+
+    pub fn save(&self, objects_dir: &Path) -> io::Result<()> {
+        let path = Self::file(objects_dir);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = String::new();
+        for hash in &self.hashes {
+            content.push_str(hash);
+            content.push('\n');
+        }
+        fs::write(path, content)
+    }
+
+    /// Add a newly written object to the stored index, if there is one.
+    pub fn record(objects_dir: &Path, hash: &str) -> io::Result<()> {
+        match fs::OpenOptions::new().append(true).open(Self::file(objects_dir)) {
+            Ok(mut file) => writeln!(file, "{}", hash),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn insert(&mut self, hash: String) {
+        let Some(first_byte) = first_byte(&hash) else {
+            return;
+        };
+        if let Err(pos) = self.hashes.binary_search(&hash) {
+            self.hashes.insert(pos, hash);
+            for count in &mut self.fanout[first_byte as usize..] {
+                *count += 1;
+            }
+        }
+    }
+
+    /// All indexed hashes starting with `prefix` (at least two hex digits).
+    pub fn find_prefix(&self, prefix: &str) -> Vec<&String> {
+        let Some(first_byte) = first_byte(prefix) else {
+            return Vec::new();
+        };
+        let start = if first_byte == 0 { 0 } else { self.fanout[first_byte as usize - 1] };
+        let bucket = &self.hashes[start..self.fanout[first_byte as usize]];
+
+        let from = bucket.partition_point(|hash| hash.as_str() < prefix);
+        bucket[from..].iter().take_while(|hash| hash.starts_with(prefix)).collect()
+    }
+
+    fn rebuild_fanout(&mut self) {
+        self.fanout = [0; 256];
+        for hash in &self.hashes {
+            if let Some(first_byte) = first_byte(hash) {
+                self.fanout[first_byte as usize] += 1;
+            }
+        }
+        for byte in 1..256 {
+            self.fanout[byte] += self.fanout[byte - 1];
+        }
+    }
+}
+
+fn is_hex(value: &str) -> bool {
+    value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The byte the first two hex digits of `hash` spell, which picks its
+/// fan-out bucket.
+fn first_byte(hash: &str) -> Option<u8> {
+    hash.get(..2).filter(|digits| is_hex(digits)).and_then(|digits| u8::from_str_radix(digits, 16).ok())
 }
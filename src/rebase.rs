@@ -0,0 +1,512 @@
+use crate::commands;
+use crate::editor;
+use crate::merge;
+use crate::objects::Commit;
+use crate::repository::{in_sparse_checkout, BlocRepo};
+use chrono::Utc;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::path::PathBuf;
+use colored::*;
+
+/// What to do with one commit of a rebase todo list.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Action {
+    Pick,
+    Reword,
+    Edit,
+    Squash,
+    Fixup,
+    Drop,
+}
+
+impl Action {
+    fn parse(word: &str) -> Option<Action> {
+        match word {
+            "p" | "pick" => Some(Action::Pick),
+            "r" | "reword" => Some(Action::Reword),
+            "e" | "edit" => Some(Action::Edit),
+            "s" | "squash" => Some(Action::Squash),
+            "f" | "fixup" => Some(Action::Fixup),
+            "d" | "drop" => Some(Action::Drop),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Reword => "reword",
+            Action::Edit => "edit",
+            Action::Squash => "squash",
+            Action::Fixup => "fixup",
+            Action::Drop => "drop",
+        }
+    }
+
+    /// Whether the commit is melded into the one before it.
+    fn melds(self) -> bool {
+        matches!(self, Action::Squash | Action::Fixup)
+    }
+}
+
+struct Step {
+    action: Action,
+    commit: String,
+}
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\", but discard this commit's log message
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+# If you remove everything, the rebase will be aborted.
+";
+
+/// Replay the current branch's commits that are not in `upstream` on top
+/// of it. With `interactive`, the list of commits is opened in the editor
+/// first so it can be reordered and each commit picked, reworded, edited,
+/// squashed, fixed up or dropped; with `autosquash`, `fixup!` and `squash!`
+/// commits are moved next to the commit they name beforehand.
+///
+/// Progress is kept in `.bloc/rebase-merge`, so a rebase stopped by a
+/// conflict or an `edit` can be resumed with `--continue` or undone with
+/// `--abort`. The branch itself moves as commits are replayed.
+pub fn rebase(repo: &mut BlocRepo, upstream: &str, interactive: bool, autosquash: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        println!("{}", "Cannot rebase in a bare repository".bright_red().bold());
+        return Ok(());
+    }
+    if state_dir(repo).exists() {
+        return Err("A rebase is already in progress (use 'bloc rebase --continue' or 'bloc rebase --abort')".into());
+    }
+    if repo.merge_head()?.is_some() {
+        return Err("A merge is in progress (commit it first)".into());
+    }
+    if !repo.index.entries.is_empty() {
+        return Err("You have staged changes (commit or reset them first)".into());
+    }
+    // Finishing moves the branch recorded here, which a detached HEAD lacks
+    if repo.detached_head()?.is_some() {
+        return Err("Cannot rebase a detached HEAD (create a branch for it first)".into());
+    }
+
+    let branch = repo.get_current_branch()?;
+    let head = repo.head_commit()?.ok_or("Cannot rebase a branch with no commits")?;
+    let head_tree = repo.read_tree(&head)?;
+    let onto = repo.resolve_revision(upstream)?;
+    let onto_tree = repo.read_tree(&onto)?;
+
+    let sparse_patterns = repo.sparse_patterns();
+    let dirty = head_tree.iter().any(|(path, hash)| {
+        in_sparse_checkout(sparse_patterns.as_deref(), path) && repo.working_file_hash(path).as_ref() != Some(hash)
+    });
+    if dirty {
+        return Err("You have unstaged changes (commit or restore them first)".into());
+    }
+    let blocked = repo.overwritten_paths(&head_tree, &onto_tree);
+    if !blocked.is_empty() {
+        return Err(format!("Untracked files would be overwritten: {}", blocked.join(", ")).into());
+    }
+
+    // The branch's own commits, oldest first; merge commits are not replayed
+    let upstream_history = merge::ancestors(repo, &onto)?;
+    let mut commits = Vec::new();
+    let mut cursor = Some(head.clone());
+    while let Some(hash) = cursor {
+        if upstream_history.contains(&hash) {
+            break;
+        }
+        let commit = repo.read_commit(&hash)?;
+        if commit.merge_parents.is_empty() {
+            commits.push(hash);
+        }
+        cursor = commit.parent.clone();
+    }
+    commits.reverse();
+
+    if !interactive && merge::merge_base(repo, &head, &onto)?.as_deref() == Some(onto.as_str()) {
+        println!("{} '{}' {}", "Current branch".bright_green(), branch.bright_cyan(), "is up to date.".bright_green());
+        return Ok(());
+    }
+
+    let mut steps: Vec<Step> = commits.into_iter().map(|commit| Step { action: Action::Pick, commit }).collect();
+    if autosquash {
+        steps = autosquash_steps(repo, steps)?;
+    }
+    if interactive {
+        steps = edit_todo(repo, &steps, &head, &onto)?;
+        if steps.is_empty() {
+            println!("{}", "Nothing to do".bright_yellow());
+            return Ok(());
+        }
+    }
+    if steps.first().is_some_and(|step| step.action.melds()) {
+        return Err(format!("Cannot '{}' without a previous commit", steps[0].action.name()).into());
+    }
+
+    fs::create_dir_all(state_dir(repo))?;
+    write_state(repo, "head-name", &branch)?;
+    write_state(repo, "orig-head", &head)?;
+    write_state(repo, "onto", &onto)?;
+    write_todo(repo, &steps)?;
+
+    repo.update_working_tree(&head_tree, &onto_tree)?;
+    repo.index.save()?;
+    move_branch(repo, &onto, &format!("rebase (start): checkout {}", upstream))?;
+    println!("{} {} {} {}",
+            "Rebasing".bright_green().bold(),
+            steps.len().to_string().bright_yellow(),
+            if steps.len() == 1 { "commit onto" } else { "commits onto" }.bright_green(),
+            onto[..8].bright_yellow());
+
+    run(repo)
+}
+
+/// Resume a rebase stopped by a conflict or an `edit`. Resolved conflicts
+/// are committed; after an `edit`, anything staged is amended into the
+/// stopped commit.
+pub fn continue_rebase(repo: &mut BlocRepo) -> Result<(), Box<dyn std::error::Error>> {
+    if !state_dir(repo).exists() {
+        return Err("No rebase in progress".into());
+    }
+
+    let mut conflicted: Vec<&String> = repo.index.entries
+        .iter()
+        .filter(|(_, entry)| entry.conflicted)
+        .map(|(path, _)| path)
+        .collect();
+    if !conflicted.is_empty() {
+        conflicted.sort();
+        println!("{}: {}",
+                "Cannot continue".bright_red().bold(),
+                "the following files have unresolved conflicts".bright_red());
+        for path in conflicted {
+            println!("  {}", path.bright_red());
+        }
+        println!("{}", "Fix them and mark them resolved with 'bloc add <file>'".bright_yellow());
+        return Ok(());
+    }
+
+    if state_dir(repo).join("stopped").exists() {
+        if finish_step(repo)? {
+            return Ok(());
+        }
+    } else if state_dir(repo).join("amend").exists() {
+        if !repo.index.entries.is_empty() {
+            let head = repo.head_commit()?.ok_or("The rebased branch has no commits")?;
+            let commit = repo.read_commit(&head)?;
+            write_commit(repo, commit.parent.clone(), &commit.message, &commit.author, "rebase (amend)")?;
+        }
+        fs::remove_file(state_dir(repo).join("amend"))?;
+    }
+
+    run(repo)
+}
+
+/// Give up on the rebase in progress: the branch, index and working tree
+/// go back to where they were before it started.
+pub fn abort_rebase(repo: &mut BlocRepo) -> Result<(), Box<dyn std::error::Error>> {
+    if !state_dir(repo).exists() {
+        return Err("No rebase in progress".into());
+    }
+    let branch = read_state(repo, "head-name")?;
+    let orig_head = read_state(repo, "orig-head")?;
+    let orig_tree = repo.read_tree(&orig_head)?;
+    let current_tree = repo.staged_tree()?;
+
+    // Conflicted files hold markers the index doesn't know about, so every
+    // path is compared with what is actually on disk
+    let sparse_patterns = repo.sparse_patterns();
+    for (path, hash) in orig_tree.iter() {
+        if in_sparse_checkout(sparse_patterns.as_deref(), path) && repo.working_file_hash(path).as_ref() != Some(hash) {
+            repo.checkout_file(path, hash)?;
+        }
+    }
+    for path in current_tree.keys() {
+        if !orig_tree.contains_key(path) {
+            repo.remove_working_file(path)?;
+        }
+    }
+
+    repo.index.entries.clear();
+    repo.index.save()?;
+    move_branch(repo, &orig_head, &format!("rebase (abort): returning to refs/heads/{}", branch))?;
+    fs::remove_dir_all(state_dir(repo))?;
+    println!("{} '{}' {} {}",
+            "Rebase aborted;".bright_yellow().bold(),
+            branch.bright_cyan(),
+            "is back at".bright_yellow(),
+            orig_head[..8].bright_yellow());
+    Ok(())
+}
+
+/// Work through the todo list until it is done or a step stops.
+fn run(repo: &mut BlocRepo) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let mut todo = read_todo(repo)?;
+        if todo.is_empty() {
+            let branch = read_state(repo, "head-name")?;
+            let onto = read_state(repo, "onto")?;
+            let head = repo.head_commit()?.ok_or("The rebased branch has no commits")?;
+            move_branch(repo, &head, &format!("rebase (finish): refs/heads/{} onto {}", branch, onto))?;
+            fs::remove_dir_all(state_dir(repo))?;
+            println!("{} refs/heads/{}.",
+                    "Successfully rebased and updated".bright_green().bold(),
+                    branch.bright_cyan());
+            return Ok(());
+        }
+        let step = todo.remove(0);
+        write_todo(repo, &todo)?;
+        if apply_step(repo, &step)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Replay one commit onto the branch. Returns whether the rebase stopped,
+/// for a conflict or an `edit`.
+fn apply_step(repo: &mut BlocRepo, step: &Step) -> Result<bool, Box<dyn std::error::Error>> {
+    if step.action == Action::Drop {
+        return Ok(false);
+    }
+
+    let commit = repo.read_commit(&step.commit)?;
+    let head = repo.head_commit()?.ok_or("The rebased branch has no commits")?;
+    let head_tree = repo.read_tree(&head)?;
+
+    // A commit already sitting on the new base is reused as it is
+    if step.action == Action::Pick && commit.parent.as_deref() == Some(head.as_str()) {
+        repo.update_working_tree(&head_tree, &commit.tree_entries())?;
+        repo.index.save()?;
+        move_branch(repo, &step.commit, &format!("rebase (pick): {}", subject(&commit)))?;
+        return Ok(false);
+    }
+
+    let base_tree = match &commit.parent {
+        Some(parent) => repo.read_tree(parent)?,
+        None => Rc::default(),
+    };
+    let outcomes = merge::merge_trees(repo, &base_tree, &head_tree, &commit.tree_entries(), &step.commit[..8])?;
+    let conflicts = merge::apply_outcomes(repo, &head_tree, outcomes)?;
+    repo.index.save()?;
+
+    let message = match step.action {
+        Action::Squash => format!("{}\n\n{}", repo.read_commit(&head)?.message.trim_end(), commit.message),
+        Action::Fixup => repo.read_commit(&head)?.message.clone(),
+        _ => commit.message.clone(),
+    };
+    write_state(repo, "stopped", &format!("{} {}", step.action.name(), step.commit))?;
+    write_state(repo, "message", &message)?;
+
+    if !conflicts.is_empty() {
+        for path in &conflicts {
+            println!("{}: {}", "CONFLICT".bright_red().bold(), path.bright_cyan());
+        }
+        println!("{} {} {}",
+                "Could not apply".bright_red().bold(),
+                step.commit[..8].bright_yellow(),
+                subject(&commit).white());
+        println!("{}", "Resolve the conflicts, 'bloc add' them and run 'bloc rebase --continue',".bright_yellow());
+        println!("{}", "or run 'bloc rebase --abort' to undo the rebase.".bright_yellow());
+        return Ok(true);
+    }
+    finish_step(repo)
+}
+
+/// Commit the changes of the stopped step with its recorded message.
+/// Returns whether the rebase stops here for an `edit`.
+fn finish_step(repo: &mut BlocRepo) -> Result<bool, Box<dyn std::error::Error>> {
+    let stopped = read_state(repo, "stopped")?;
+    let (action, hash) = stopped.split_once(' ').ok_or("Corrupt rebase state")?;
+    let action = Action::parse(action).ok_or("Corrupt rebase state")?;
+    let commit = repo.read_commit(hash)?;
+    let mut message = read_state(repo, "message")?;
+
+    if matches!(action, Action::Reword | Action::Squash) {
+        message = edit_message(repo, &message)?;
+    }
+
+    let head = repo.head_commit()?.ok_or("The rebased branch has no commits")?;
+    if action.melds() {
+        let head_commit = repo.read_commit(&head)?;
+        write_commit(repo, head_commit.parent.clone(), &message, &head_commit.author, &format!("rebase ({})", action.name()))?;
+    } else if repo.index.entries.is_empty() {
+        println!("{} {} {}",
+                "Dropping".bright_yellow(),
+                hash[..8].bright_yellow(),
+                format!("{} -- patch contents already upstream", subject(&commit)).bright_black());
+    } else {
+        write_commit(repo, Some(head), &message, &commit.author, &format!("rebase ({})", action.name()))?;
+    }
+    fs::remove_file(state_dir(repo).join("stopped"))?;
+    fs::remove_file(state_dir(repo).join("message"))?;
+
+    if action == Action::Edit {
+        write_state(repo, "amend", hash)?;
+        println!("{} {} {}", "Stopped at".bright_yellow().bold(), hash[..8].bright_yellow(), subject(&commit).white());
+        println!("{}", "Stage changes to amend the commit, then run 'bloc rebase --continue'".bright_yellow());
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Record the staged snapshot (HEAD plus the index) as a new commit on the
+/// branch, logged as `<reflog_action>: <subject>`, and clear the index.
+fn write_commit(repo: &mut BlocRepo, parent: Option<String>, message: &str, author: &str, reflog_action: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let tree = repo.staged_tree()?;
+    let commit = Commit {
+        parent,
+        merge_parents: Vec::new(),
+        author: author.to_string(),
+        committer: repo.get_author_signature(),
+        timestamp: Utc::now(),
+        message: message.to_string(),
+        tree: commands::serialize_tree(&tree),
+        signature: None,
+    };
+    let hash = repo.write_object(serde_json::to_string_pretty(&commit)?.as_bytes())?;
+    move_branch(repo, &hash, &format!("{}: {}", reflog_action, subject(&commit)))?;
+    repo.index.entries.clear();
+    repo.index.save()?;
+    Ok(hash)
+}
+
+/// Move each `fixup!`/`squash!` commit right after the commit it names,
+/// by subject or hash prefix, turning it into a fixup or squash step.
+fn autosquash_steps(repo: &BlocRepo, steps: Vec<Step>) -> io::Result<Vec<Step>> {
+    let mut subjects = Vec::new();
+    for step in &steps {
+        subjects.push(subject(&*repo.read_commit(&step.commit)?).to_string());
+    }
+
+    let mut targets = vec![None; steps.len()];
+    for (i, subject) in subjects.iter().enumerate() {
+        let action = if subject.starts_with("fixup! ") {
+            Action::Fixup
+        } else if subject.starts_with("squash! ") {
+            Action::Squash
+        } else {
+            continue;
+        };
+        let mut name = subject.as_str();
+        while let Some(rest) = name.strip_prefix("fixup! ").or_else(|| name.strip_prefix("squash! ")) {
+            name = rest;
+        }
+        let target = (0..i).find(|&j| {
+            targets[j].is_none() && (subjects[j] == name || (name.len() >= 4 && steps[j].commit.starts_with(name)))
+        });
+        if let Some(target) = target {
+            targets[i] = Some((target, action));
+        }
+    }
+
+    let mut ordered = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        if targets[i].is_some() {
+            continue;
+        }
+        ordered.push(Step { action: step.action, commit: step.commit.clone() });
+        for (j, target) in targets.iter().enumerate() {
+            if let Some((target, action)) = target
+                && *target == i {
+                ordered.push(Step { action: *action, commit: steps[j].commit.clone() });
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+/// Let the user edit the todo list and parse the result.
+fn edit_todo(repo: &BlocRepo, steps: &[Step], head: &str, onto: &str) -> Result<Vec<Step>, Box<dyn std::error::Error>> {
+    let mut text = String::new();
+    for step in steps {
+        let commit = repo.read_commit(&step.commit)?;
+        text.push_str(&format!("{} {} {}\n", step.action.name(), &step.commit[..8], subject(&commit)));
+    }
+    text.push_str(&format!("\n# Rebase {}..{} onto {} ({} commands)\n#", &onto[..8], &head[..8], &onto[..8], steps.len()));
+    text.push_str(TODO_HELP);
+
+    let todo_path = repo.bloc_dir.join("rebase-todo");
+    fs::write(&todo_path, text)?;
+    let edited = editor::edit_file(repo.config.core.editor.as_deref(), &todo_path)
+        .and_then(|_| fs::read_to_string(&todo_path));
+    let _ = fs::remove_file(&todo_path);
+
+    let mut edited_steps = Vec::new();
+    for line in edited?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let (Some(action), Some(commit)) = (words.next(), words.next()) else {
+            return Err(format!("Invalid todo line: '{}'", line).into());
+        };
+        let action = Action::parse(action).ok_or_else(|| format!("Unknown command '{}' in todo line: '{}'", action, line))?;
+        let commit = repo.resolve_revision(commit)?;
+        edited_steps.push(Step { action, commit });
+    }
+    Ok(edited_steps)
+}
+
+/// Let the user edit a commit message.
+fn edit_message(repo: &BlocRepo, message: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let path = state_dir(repo).join("COMMIT_EDITMSG");
+    let edited = editor::edit_message(repo.config.core.editor.as_deref(), &path, message)?;
+    if edited.is_empty() {
+        return Err("Empty commit message (fix it and run 'bloc rebase --continue')".into());
+    }
+    Ok(edited)
+}
+
+fn subject(commit: &Commit) -> &str {
+    commit.message.lines().next().unwrap_or_default()
+}
+
+fn state_dir(repo: &BlocRepo) -> PathBuf {
+    repo.bloc_dir.join("rebase-merge")
+}
+
+fn branch_ref_path(repo: &BlocRepo) -> io::Result<PathBuf> {
+    Ok(repo.refs_dir().join("heads").join(read_state(repo, "head-name")?))
+}
+
+/// Point the rebased branch at `hash`, logging `message` in the reflogs.
+fn move_branch(repo: &BlocRepo, hash: &str, message: &str) -> io::Result<()> {
+    let path = branch_ref_path(repo)?;
+    let old = fs::read_to_string(&path).ok().map(|old| old.trim().to_string());
+    fs::write(&path, hash)?;
+    repo.record_ref_update(&read_state(repo, "head-name")?, old.as_deref(), hash, message)
+}
+
+fn read_state(repo: &BlocRepo, name: &str) -> io::Result<String> {
+    Ok(fs::read_to_string(state_dir(repo).join(name))?.trim_end().to_string())
+}
+
+fn write_state(repo: &BlocRepo, name: &str, value: &str) -> io::Result<()> {
+    fs::write(state_dir(repo).join(name), format!("{}\n", value))
+}
+
+/// The steps still to run, stored one `<action> <commit>` per line.
+fn read_todo(repo: &BlocRepo) -> io::Result<Vec<Step>> {
+    let content = fs::read_to_string(state_dir(repo).join("todo"))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let (action, commit) = line.split_once(' ')?;
+            Some(Step { action: Action::parse(action)?, commit: commit.to_string() })
+        })
+        .collect())
+}
+
+fn write_todo(repo: &BlocRepo, steps: &[Step]) -> io::Result<()> {
+    let content: String = steps.iter().map(|step| format!("{} {}\n", step.action.name(), step.commit)).collect();
+    fs::write(state_dir(repo).join("todo"), content)
+}
@@ -0,0 +1,188 @@
+use crate::repository::BlocRepo;
+use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use walkdir::WalkDir;
+
+/// The order of a `branch` or `tag` listing, as given to `--sort`: `name`,
+/// `creatordate` or `version:refname` (`v:refname`), each reversed by a
+/// leading `-`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RefSort {
+    key: SortKey,
+    reverse: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortKey {
+    Name,
+    CreatorDate,
+    Version,
+}
+
+impl Default for RefSort {
+    fn default() -> Self {
+        RefSort { key: SortKey::Name, reverse: false }
+    }
+}
+
+impl FromStr for RefSort {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (reverse, key) = match spec.strip_prefix('-') {
+            Some(key) => (true, key),
+            None => (false, spec),
+        };
+        let key = match key {
+            "name" | "refname" => SortKey::Name,
+            "creatordate" => SortKey::CreatorDate,
+            "version:refname" | "v:refname" => SortKey::Version,
+            _ => return Err(format!("unsupported sort key '{}' (use name, creatordate or version:refname)", key)),
+        };
+        Ok(RefSort { key, reverse })
+    }
+}
+
+impl RefSort {
+    /// Sort the ref `names` stored under `dir`.
+    pub fn sort(&self, repo: &BlocRepo, dir: &Path, names: &mut [String]) {
+        match self.key {
+            SortKey::Name => names.sort(),
+            SortKey::Version => names.sort_by(|a, b| compare_versions(a, b)),
+            SortKey::CreatorDate => {
+                // Refs whose date can't be read sort first, by name
+                names.sort_by_cached_key(|name| (creator_date(repo, &dir.join(name.as_str())), name.clone()))
+            }
+        }
+        if self.reverse {
+            names.reverse();
+        }
+    }
+}
+
+/// Names of the refs stored under `dir`, including nested ones like `feature/x`.
+pub fn ref_names(dir: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    if !dir.is_dir() {
+        return Ok(names);
+    }
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.path().is_file()
+            && let Ok(name) = entry.path().strip_prefix(dir) {
+            names.push(name.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// When the object a ref points at was created: the tagging time for
+/// annotated tags, the commit time otherwise.
+fn creator_date(repo: &BlocRepo, ref_path: &Path) -> Option<DateTime<Utc>> {
+    let hash = fs::read_to_string(ref_path).ok()?.trim().to_string();
+    match repo.read_tag(&hash) {
+        Ok(tag) => Some(tag.timestamp),
+        Err(_) => repo.read_commit(&hash).ok().map(|commit| commit.timestamp),
+    }
+}
+
+/// Compare names as versions: runs of digits compare by numeric value, so
+/// `v1.10` sorts after `v1.9`, and everything else compares as text. A name
+/// that is a prefix of another (`1.0` and `1.0.1`) sorts first, except
+/// that a `-` suffix marks a pre-release, which sorts before the release
+/// (`1.0-rc1` before `1.0`).
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (mut a_parts, mut b_parts) = (VersionParts(a), VersionParts(b));
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(b_part)) if b_part.starts_with('-') => return Ordering::Greater,
+            (Some(a_part), None) if a_part.starts_with('-') => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_part), Some(b_part)) => {
+                let a_numeric = a_part.starts_with(|c: char| c.is_ascii_digit());
+                let b_numeric = b_part.starts_with(|c: char| c.is_ascii_digit());
+                let ordering = match (a_numeric, b_numeric) {
+                    (true, true) => compare_numbers(a_part, b_part),
+                    // At the same position, numbers sort before text
+                    (true, false) => Ordering::Less,
+                    (false, true) => Ordering::Greater,
+                    (false, false) => a_part.cmp(b_part),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+/// Compare two digit strings by value, without overflowing on long ones.
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Splits a name into alternating runs of digits and non-digits.
+struct VersionParts<'a>(&'a str);
+
+impl<'a> Iterator for VersionParts<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let first = self.0.chars().next()?;
+        let numeric = first.is_ascii_digit();
+        let end = self.0.find(|c: char| c.is_ascii_digit() != numeric).unwrap_or(self.0.len());
+        let (part, rest) = self.0.split_at(end);
+        self.0 = rest;
+        Some(part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numeric_components_compare_by_value() {
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.9", "1.10"), Ordering::Less);
+        assert_eq!(compare_versions("2.0", "1.99"), Ordering::Greater);
+        assert_eq!(compare_versions("1.2.3", "1.2.3"), Ordering::Equal);
+    }
+
+    #[test]
+    fn v_prefixes_compare_like_the_bare_version() {
+        assert_eq!(compare_versions("v1.10", "v1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("v2.0.0", "v10.0.0"), Ordering::Less);
+    }
+
+    #[test]
+    fn fewer_components_sort_first() {
+        assert_eq!(compare_versions("1.0", "1.0.1"), Ordering::Less);
+        assert_eq!(compare_versions("1.2", "1.10.0"), Ordering::Less);
+        assert_eq!(compare_versions("2", "1.9.9"), Ordering::Greater);
+    }
+
+    #[test]
+    fn pre_releases_sort_before_their_release() {
+        assert_eq!(compare_versions("1.0-rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("v1.0", "v1.0-beta"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0-rc2", "1.0-rc10"), Ordering::Less);
+        assert_eq!(compare_versions("1.0-alpha", "1.0-beta"), Ordering::Less);
+        assert_eq!(compare_versions("1.0-rc1", "1.0.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn sorts_a_release_history() {
+        let mut tags = vec!["v1.10.0", "v1.2.0", "v1.10.0-rc1", "v1.9.1", "v1.9", "v2.0.0"];
+        tags.sort_by(|a, b| compare_versions(a, b));
+        assert_eq!(tags, ["v1.2.0", "v1.9", "v1.9.1", "v1.10.0-rc1", "v1.10.0", "v2.0.0"]);
+    }
+}
@@ -0,0 +1,485 @@
+use crate::config::{BlocConfig, BranchConfig, RemoteConfig};
+use crate::merge;
+use crate::objects::{self, ObjectHasher, PackStore};
+use crate::refsort;
+use crate::repository::BlocRepo;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use colored::*;
+
+/// Fetch every branch of the remote `remote_name` into
+/// `refs/remotes/<remote_name>/`, copying the objects they need. With
+/// `prune`, remote-tracking branches whose branch no longer exists on the
+/// remote are deleted.
+pub fn fetch(repo: &BlocRepo, remote_name: &str, prune: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let remote = repo.config.remotes.get(remote_name)
+        .ok_or_else(|| format!("Remote '{}' not found", remote_name))?;
+    let remote_dir = remote_bloc_dir(&remote.url)?;
+    let branches = remote_branches(&remote_dir, repo.object_hasher())?;
+
+    copy_objects(repo, &remote_dir.join("objects"), branches.values().cloned().collect(), None)?;
+
+    let tracking_dir = repo.refs_dir().join("remotes").join(remote_name);
+    let mut updated = false;
+    for (branch, hash) in &branches {
+        let ref_path = tracking_dir.join(branch);
+        let old = fs::read_to_string(&ref_path).ok().map(|content| content.trim().to_string());
+        if old.as_ref() == Some(hash) {
+            continue;
+        }
+        if let Some(parent) = ref_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&ref_path, hash)?;
+
+        let change = match &old {
+            Some(old) => format!("   {}..{}", old.get(..8).unwrap_or(old), hash.get(..8).unwrap_or(hash)).bright_yellow(),
+            None => " * [new branch]".bright_green(),
+        };
+        println!("{}\t{} -> {}", change, branch.bright_cyan(), format!("{}/{}", remote_name, branch).bright_red());
+        updated = true;
+    }
+
+    if prune {
+        for branch in refsort::ref_names(&tracking_dir)? {
+            if branches.contains_key(&branch) {
+                continue;
+            }
+            remove_ref(&tracking_dir, &branch)?;
+            println!("{}\t{} -> {}",
+                    " - [deleted]".bright_red(),
+                    "(none)".bright_black(),
+                    format!("{}/{}", remote_name, branch).bright_red());
+            updated = true;
+        }
+    }
+
+    if !updated {
+        println!("{} {}", "Already up to date with".bright_green(), remote_name.bright_cyan());
+    }
+    Ok(())
+}
+
+/// Push a local branch to a remote. Without arguments the current branch
+/// goes where `push.default` says, which for the default `simple` is its
+/// configured upstream; with only a remote, the current branch is pushed to
+/// the branch of the same name. The remote branch must be an ancestor of
+/// what is pushed unless `force` is given. With `set_upstream`, the remote
+/// branch becomes the current branch's upstream.
+pub fn push(repo: &mut BlocRepo, remote_name: Option<&str>, branch: Option<&str>, force: bool, set_upstream: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let current = repo.get_current_branch()?;
+    let upstream = repo.config.branches.get(&current).cloned();
+    let (remote_name, source, destination) = match (remote_name, branch) {
+        (Some(remote_name), Some(branch)) => (remote_name.to_string(), branch.to_string(), branch.to_string()),
+        (Some(remote_name), None) => (remote_name.to_string(), current.clone(), current.clone()),
+        (None, _) => match repo.config.push.default.as_str() {
+            "nothing" => return Err("push.default is 'nothing'; name the remote and branch to push".into()),
+            "current" => {
+                let remote_name = upstream.map(|upstream| upstream.remote).unwrap_or_else(|| "origin".to_string());
+                (remote_name, current.clone(), current.clone())
+            }
+            "upstream" | "simple" => {
+                let Some(upstream) = upstream else {
+                    return Err(no_upstream_hint(repo, &current, "push").into());
+                };
+                if repo.config.push.default == "simple" && upstream.merge != current {
+                    return Err(format!(
+                        "The upstream of '{}' is '{}/{}', which has a different name.\n\
+                         Name the branch to push, or set push.default to 'upstream'.",
+                        current, upstream.remote, upstream.merge,
+                    ).into());
+                }
+                (upstream.remote, current.clone(), upstream.merge)
+            }
+            other => return Err(format!("Unknown push.default '{}' (use simple, upstream, current or nothing)", other).into()),
+        },
+    };
+
+    let remote = repo.config.remotes.get(&remote_name)
+        .ok_or_else(|| format!("Remote '{}' not found", remote_name))?;
+    let url = remote.url.clone();
+    let remote_dir = remote_bloc_dir(&url)?;
+    let tip = repo.resolve_revision(&source)
+        .map_err(|_| format!("Branch '{}' has no commits to push", source))?;
+
+    // Like git, never move the branch a non-bare remote has checked out
+    let remote_head = fs::read_to_string(remote_dir.join("HEAD"))?;
+    if remote_dir.ends_with(".bloc") && remote_head.trim() == format!("ref: refs/heads/{}", destination) {
+        return Err(format!("Refusing to update the checked-out branch '{}' of non-bare repository {}", destination, url).into());
+    }
+
+    let remote_ref_path = remote_dir.join("refs").join("heads").join(&destination);
+    let old = fs::read_to_string(&remote_ref_path).ok().map(|content| content.trim().to_string());
+    println!("{} {}", "To".bright_blue(), url.white());
+    if old.as_deref() == Some(tip.as_str()) {
+        println!("{}", "Everything up-to-date".bright_green());
+    } else {
+        let fast_forward = match &old {
+            Some(old) => repo.has_object(old)? && merge::ancestors(repo, &tip)?.contains(old),
+            None => true,
+        };
+        if !fast_forward && !force {
+            println!("{}\t{} -> {} {}",
+                    " ! [rejected]".bright_red().bold(),
+                    source.bright_cyan(),
+                    destination.bright_cyan(),
+                    "(non-fast-forward)".bright_red());
+            return Err("The remote branch has commits you don't have; pull them first or push with --force".into());
+        }
+
+        send_objects(repo, &remote_dir.join("objects"), &tip)?;
+        if let Some(parent) = remote_ref_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&remote_ref_path, &tip)?;
+
+        let tracking_ref_path = repo.refs_dir().join("remotes").join(&remote_name).join(&destination);
+        if let Some(parent) = tracking_ref_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(tracking_ref_path, &tip)?;
+
+        let change = match &old {
+            Some(old) if fast_forward => format!("   {}..{}", old.get(..8).unwrap_or(old), tip.get(..8).unwrap_or(&tip)).bright_yellow(),
+            Some(old) => format!(" + {}...{}", old.get(..8).unwrap_or(old), tip.get(..8).unwrap_or(&tip)).bright_red(),
+            None => " * [new branch]".bright_green(),
+        };
+        println!("{}\t{} -> {}", change, source.bright_cyan(), destination.bright_cyan());
+    }
+
+    if set_upstream {
+        repo.config.branches.insert(source.clone(), BranchConfig {
+            remote: remote_name.clone(),
+            merge: destination.clone(),
+        });
+        repo.config.save()?;
+        println!("{} '{}' {} '{}/{}'",
+                "Branch".bright_green(),
+                source.bright_cyan(),
+                "set up to track".bright_green(),
+                remote_name.bright_red(),
+                destination.bright_red());
+    }
+    Ok(())
+}
+
+/// Fetch a remote branch and merge it into the current branch. Without
+/// arguments the current branch's upstream is used; with only a remote,
+/// the upstream's branch if it lives on that remote, else the branch of
+/// the same name.
+pub fn pull(repo: &mut BlocRepo, remote_name: Option<&str>, branch: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let current = repo.get_current_branch()?;
+    let upstream = repo.config.branches.get(&current).cloned();
+    let (remote_name, branch) = match (remote_name, branch, upstream) {
+        (Some(remote_name), Some(branch), _) => (remote_name.to_string(), branch.to_string()),
+        (Some(remote_name), None, Some(upstream)) if upstream.remote == remote_name => (upstream.remote, upstream.merge),
+        (Some(remote_name), None, _) => (remote_name.to_string(), current.clone()),
+        (None, _, Some(upstream)) => (upstream.remote, upstream.merge),
+        (None, _, None) => return Err(no_upstream_hint(repo, &current, "pull").into()),
+    };
+
+    fetch(repo, &remote_name, false)?;
+    let tracking = format!("{}/{}", remote_name, branch);
+    let tracking_ref_path = repo.refs_dir().join("remotes").join(&tracking);
+    if !tracking_ref_path.is_file() {
+        return Err(format!("Remote branch '{}' not found on '{}'", branch, remote_name).into());
+    }
+
+    // A branch with no commits yet simply starts at the remote branch
+    if repo.head_commit()?.is_none() {
+        let hash = fs::read_to_string(tracking_ref_path)?.trim().to_string();
+        repo.update_working_tree(&BTreeMap::new(), &*repo.read_tree(&hash)?)?;
+        repo.index.save()?;
+        fs::write(repo.refs_dir().join("heads").join(&current), &hash)?;
+        repo.record_ref_update(&current, None, &hash, &format!("pull {} {}: Fast-forward", remote_name, branch))?;
+        println!("{} '{}' {} {}",
+                "Branch".bright_green().bold(),
+                current.bright_cyan(),
+                "now starts at".bright_green(),
+                hash.get(..8).unwrap_or(&hash).bright_yellow());
+        return Ok(());
+    }
+    merge::merge(repo, &tracking, false)
+}
+
+fn no_upstream_hint(repo: &BlocRepo, branch: &str, command: &str) -> String {
+    let remote = if repo.config.remotes.contains_key("origin") || repo.config.remotes.is_empty() {
+        "origin".to_string()
+    } else {
+        let mut names: Vec<&String> = repo.config.remotes.keys().collect();
+        names.sort();
+        names[0].clone()
+    };
+    format!(
+        "The current branch '{}' has no upstream branch.\n\
+         Name the remote and branch ('bloc {} {} {}'), or set the upstream with\n\n    \
+         bloc push --set-upstream {} {}\n",
+        branch, command, remote, branch, remote, branch,
+    )
+}
+
+/// Store the commits reachable from `tip` and the blobs they record in a
+/// remote's object store as loose objects, stopping at history the remote
+/// already has. Parents are written before their children and blobs before
+/// the commit recording them, so an interrupted push never leaves the
+/// remote with a commit whose history or files are missing.
+fn send_objects(repo: &BlocRepo, target_objects: &Path, tip: &str) -> io::Result<()> {
+    let target_packs = PackStore::load(target_objects)?;
+    let has = |hash: &str| objects::loose_object_path(target_objects, hash).is_file() || target_packs.contains(hash);
+    let shallow = repo.shallow_commits()?;
+
+    // Depth-first, emitting each commit once all of its parents are out
+    let mut seen = HashSet::new();
+    let mut pending = vec![(tip.to_string(), false)];
+    while let Some((hash, parents_sent)) = pending.pop() {
+        if !parents_sent && (seen.contains(&hash) || has(&hash)) {
+            continue;
+        }
+        let commit = repo.read_commit(&hash)?;
+        if parents_sent {
+            for blob in commit.tree_entries().into_values() {
+                if !has(&blob) {
+                    write_loose(target_objects, &blob, &repo.read_object(&blob)?)?;
+                }
+            }
+            write_loose(target_objects, &hash, &repo.read_object(&hash)?)?;
+            continue;
+        }
+
+        seen.insert(hash.clone());
+        pending.push((hash.clone(), true));
+        if !shallow.contains(&hash) {
+            pending.extend(commit.parents().map(|parent| (parent.clone(), false)));
+        }
+    }
+    Ok(())
+}
+
+fn write_loose(objects_dir: &Path, hash: &str, data: &[u8]) -> io::Result<()> {
+    let path = objects::loose_object_path(objects_dir, hash);
+    if let Some(bucket) = path.parent() {
+        fs::create_dir_all(bucket)?;
+    }
+    fs::write(path, data)
+}
+
+/// Clone the repository at `url` into `directory` (by default named after
+/// the source), registering it as the `origin` remote and checking out
+/// `branch`, or the branch the source's HEAD points at, with `origin`'s
+/// branch of the same name as its upstream. With `depth`, only
+/// the most recent `depth` commits of each branch are copied and the
+/// commits at the cut are recorded in `.bloc/shallow`.
+pub fn clone(url: &str, directory: Option<&str>, branch: Option<&str>, depth: Option<usize>) -> Result<(), Box<dyn std::error::Error>> {
+    // Initializing the clone changes directory, so the source must be absolute
+    let source = fs::canonicalize(url.strip_prefix("file://").unwrap_or(url))
+        .map_err(|_| format!("Repository '{}' does not exist", url))?;
+    let remote_dir = remote_bloc_dir(&source.to_string_lossy())?;
+    let name = match directory {
+        Some(directory) => PathBuf::from(directory),
+        None => PathBuf::from(source.file_name().ok_or("Cannot infer a directory name; pass one explicitly")?),
+    };
+    // Initializing changes directory, so hand it an absolute destination too
+    let directory = std::env::current_dir()?.join(&name);
+    if directory.exists() && fs::read_dir(&directory)?.next().is_some() {
+        return Err(format!("Destination path '{}' already exists and is not empty", name.display()).into());
+    }
+
+    // The clone names its objects with the algorithm a new repository gets
+    let hasher = ObjectHasher::named(BlocConfig::load()?.inherited_hashalgo())?;
+    let branches = remote_branches(&remote_dir, hasher)?;
+    let branch = match branch {
+        Some(branch) => {
+            if !branches.contains_key(branch) {
+                return Err(format!("Remote branch '{}' not found in {}", branch, url).into());
+            }
+            Some(branch.to_string())
+        }
+        None => fs::read_to_string(remote_dir.join("HEAD"))?
+            .trim()
+            .strip_prefix("ref: refs/heads/")
+            .map(String::from)
+            .filter(|head| branches.contains_key(head))
+            .or_else(|| branches.keys().next().cloned()),
+    };
+
+    println!("{} '{}'...", "Cloning into".bright_green().bold(), name.display().to_string().bright_cyan());
+    let created = !directory.exists();
+    let result = BlocRepo::init(Some(&directory.to_string_lossy()), false)
+        .map_err(|e| e.into())
+        .and_then(|mut repo| populate_clone(&mut repo, &source, &remote_dir, &branches, branch, depth));
+    if result.is_err() {
+        // Leave the destination as it was found rather than half cloned
+        if created {
+            let _ = fs::remove_dir_all(&directory);
+        } else if let Ok(entries) = fs::read_dir(&directory) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let _ = if path.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+            }
+        }
+    }
+    result
+}
+
+/// Fill a freshly initialized clone: record the source as `origin`, copy
+/// its objects and branches, and check out `branch`.
+fn populate_clone(
+    repo: &mut BlocRepo,
+    source: &Path,
+    remote_dir: &Path,
+    branches: &BTreeMap<String, String>,
+    branch: Option<String>,
+    depth: Option<usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    repo.config.remotes.insert("origin".to_string(), RemoteConfig {
+        url: source.to_string_lossy().to_string(),
+        fetch: "+refs/heads/*:refs/remotes/origin/*".to_string(),
+        push: None,
+    });
+    repo.config.save()?;
+
+    let shallow = copy_objects(repo, &remote_dir.join("objects"), branches.values().cloned().collect(), depth)?;
+    if !shallow.is_empty() {
+        fs::write(repo.bloc_dir.join("shallow"), shallow.join("\n") + "\n")?;
+    }
+
+    let tracking_dir = repo.refs_dir().join("remotes").join("origin");
+    for (name, hash) in branches {
+        let ref_path = tracking_dir.join(name);
+        if let Some(parent) = ref_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(ref_path, hash)?;
+    }
+
+    let Some(branch) = branch else {
+        println!("{}", "warning: You appear to have cloned an empty repository.".bright_yellow());
+        return Ok(());
+    };
+    let hash = &branches[&branch];
+    let branch_ref_path = repo.refs_dir().join("heads").join(&branch);
+    if let Some(parent) = branch_ref_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(branch_ref_path, hash)?;
+    fs::write(repo.head_path(), format!("ref: refs/heads/{}\n", branch))?;
+    repo.record_ref_update(&branch, None, hash, &format!("clone: from {}", source.display()))?;
+    repo.config.branches.insert(branch.clone(), BranchConfig {
+        remote: "origin".to_string(),
+        merge: branch.clone(),
+    });
+    repo.config.save()?;
+
+    repo.update_working_tree(&BTreeMap::new(), &*repo.read_tree(hash)?)?;
+    repo.index.save()?;
+
+    println!("{} '{}' {} {}",
+            "Checked out branch".bright_green().bold(),
+            branch.bright_cyan(),
+            "at".bright_green(),
+            hash.get(..8).unwrap_or(hash).bright_yellow());
+    Ok(())
+}
+
+/// The `.bloc` directory of the repository a remote URL names. Only local
+/// paths (optionally prefixed with `file://`) are supported; both regular
+/// and bare repositories can be fetched from.
+pub fn remote_bloc_dir(url: &str) -> io::Result<PathBuf> {
+    let path = Path::new(url.strip_prefix("file://").unwrap_or(url));
+    if path.join(".bloc").join("HEAD").is_file() {
+        return Ok(path.join(".bloc"));
+    }
+    if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("'{}' does not appear to be a bloc repository", url),
+    ))
+}
+
+/// Branches of the repository at `bloc_dir`, by name.
+pub fn remote_branches(bloc_dir: &Path, hasher: ObjectHasher) -> io::Result<BTreeMap<String, String>> {
+    let heads_dir = bloc_dir.join("refs").join("heads");
+    let mut branches = BTreeMap::new();
+    for name in refsort::ref_names(&heads_dir)? {
+        let hash = fs::read_to_string(heads_dir.join(&name))?.trim().to_string();
+        if !hasher.is_object_name(&hash) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Bad ref refs/heads/{} in {}: '{}' is not an object name", name, bloc_dir.display(), hash),
+            ));
+        }
+        branches.insert(name, hash);
+    }
+    Ok(branches)
+}
+
+/// Delete the ref `name` under `dir` along with any directories the
+/// removal leaves empty.
+fn remove_ref(dir: &Path, name: &str) -> io::Result<()> {
+    let ref_path = dir.join(name);
+    fs::remove_file(&ref_path)?;
+    let mut parent = ref_path.parent();
+    while let Some(parent_dir) = parent {
+        if parent_dir == dir || fs::remove_dir(parent_dir).is_err() {
+            break;
+        }
+        parent = parent_dir.parent();
+    }
+    Ok(())
+}
+
+/// Copy the commits reachable from `tips` and the blobs they record from
+/// another repository's object store, loose or packed. History the local
+/// repository already has is not walked again. With `depth`, only that many
+/// commits are copied along each line of history; the commits whose parents
+/// were left out are returned as the shallow boundary.
+fn copy_objects(repo: &BlocRepo, source_objects: &Path, tips: Vec<String>, depth: Option<usize>) -> io::Result<Vec<String>> {
+    let source_packs = PackStore::load(source_objects)?;
+    let mut shallow = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending: VecDeque<(String, usize)> = tips.into_iter().map(|tip| (tip, 1)).collect();
+    while let Some((hash, generation)) = pending.pop_front() {
+        if !seen.insert(hash.clone()) || repo.has_object(&hash)? {
+            continue;
+        }
+        // The remote's history may itself be cut short
+        if !copy_object(repo, source_objects, &source_packs, &hash)? {
+            continue;
+        }
+
+        if let Ok(commit) = repo.read_commit(&hash) {
+            for blob in commit.tree_entries().into_values() {
+                if !repo.has_object(&blob)? {
+                    copy_object(repo, source_objects, &source_packs, &blob)?;
+                }
+            }
+            if depth.is_some_and(|depth| generation >= depth) {
+                if commit.parents().next().is_some() {
+                    shallow.push(hash);
+                }
+                continue;
+            }
+            pending.extend(commit.parents().map(|parent| (parent.clone(), generation + 1)));
+        }
+    }
+    Ok(shallow)
+}
+
+/// Store the source's object `hash` as a loose object. Returns whether the
+/// source had it.
+fn copy_object(repo: &BlocRepo, source_objects: &Path, source_packs: &PackStore, hash: &str) -> io::Result<bool> {
+    let loose = objects::loose_object_path(source_objects, hash);
+    let data = if loose.is_file() {
+        fs::read(loose)?
+    } else if let Some(data) = source_packs.read(hash)? {
+        data
+    } else {
+        return Ok(false);
+    };
+    repo.write_object(&data)?;
+    Ok(true)
+}
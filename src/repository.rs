@@ -1,45 +1,61 @@
 use crate::config::BlocConfig;
-use crate::objects::{Commit, TreeEntry, Index};
-use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use crate::dates;
+use crate::objects::{self, Commit, Index, ObjectCache, ObjectHasher, ObjectIndex, ObjectType, PackStore, Tag};
+use chrono::{DateTime, Utc};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use chrono::{DateTime, Utc};
-use walkdir::WalkDir;
+use std::rc::Rc;
 use colored::*;
 
 pub struct BlocRepo {
     pub config: BlocConfig,
     pub index: Index,
     pub is_bare: bool,
-    pub work_dir: PathBuf,
     pub bloc_dir: PathBuf,
+    /// Names objects, as recorded in `core.hashalgo`
+    hasher: ObjectHasher,
+    /// Prefix index over stored objects, built on first use
+    object_index: RefCell<Option<ObjectIndex>>,
+    /// Packed objects, loaded on first use
+    packs: RefCell<Option<PackStore>>,
+    /// Objects read so far, decoded at most once
+    cache: RefCell<ObjectCache>,
 }
 
 impl BlocRepo {
     pub fn new() -> io::Result<Self> {
         let current_dir = std::env::current_dir()?;
-        let bloc_dir = current_dir.join(".bloc");
-        
-        if !bloc_dir.exists() {
+        // A bare repository keeps HEAD, config and objects at its top level
+        let bloc_dir = if Path::new(".bloc").exists() {
+            current_dir.join(".bloc")
+        } else if Self::is_repo() {
+            current_dir
+        } else {
             return Err(io::Error::new(
                 io::ErrorKind::NotFound,
                 "Not a bloc repository"
             ));
-        }
+        };
 
         let config = BlocConfig::load()?;
-        let index = Index::load()?;
         let is_bare = config.core.bare;
+        let hasher = ObjectHasher::for_config(&config.core)?;
 
-        Ok(BlocRepo {
+        let mut repo = BlocRepo {
             config,
-            index,
+            index: Index::new(PathBuf::new()),
             is_bare,
-            work_dir: current_dir,
             bloc_dir,
-        })
+            hasher,
+            object_index: RefCell::new(None),
+            packs: RefCell::new(None),
+            cache: RefCell::new(ObjectCache::new(ObjectCache::DEFAULT_CAPACITY)),
+        };
+        repo.index = Index::load_from(&repo.index_path())?;
+        Ok(repo)
     }
 
     pub fn init(path: Option<&str>, bare: bool) -> io::Result<Self> {
@@ -67,6 +83,9 @@ impl BlocRepo {
             ));
         }
 
+        // A global core.hashalgo picks the algorithm for new repositories
+        let hasher = ObjectHasher::named(BlocConfig::load()?.inherited_hashalgo())?;
+
         // Create directory structure
         if !bare {
             fs::create_dir(&bloc_dir)?;
@@ -76,30 +95,30 @@ impl BlocRepo {
         fs::create_dir_all(bloc_dir.join("refs/heads"))?;
         fs::create_dir_all(bloc_dir.join("refs/tags"))?;
         fs::create_dir_all(bloc_dir.join("refs/remotes"))?;
+        fs::create_dir_all(bloc_dir.join("info"))?;
+        fs::write(bloc_dir.join("info/exclude"), "")?;
 
-        // Create config
-        let mut config = BlocConfig::default();
+        // Create config, recording only what differs from the inherited settings
+        std::env::set_current_dir(&work_dir)?;
+        let mut config = BlocConfig::load()?;
         config.core.bare = bare;
+        config.core.hashalgo = Some(hasher.name().to_string());
         
         // Save config
-        std::env::set_current_dir(&work_dir)?;
-        if bare {
-            let content = serde_json::to_string_pretty(&config)?;
-            fs::write("config", content)?;
-        } else {
-            config.save()?;
+        let config_path = Path::new(if bare { "config" } else { ".bloc/config" });
+        config.save_to(config_path)?;
+
+        // Probe the filesystem: if the config file can be found under an
+        // upper-cased name, paths differing only by case collide on disk
+        let probe_path = if bare { "CONFIG" } else { ".bloc/CONFIG" };
+        if Path::new(probe_path).exists() {
+            config.core.ignorecase = true;
+            config.save_to(config_path)?;
         }
 
         // Create HEAD
         let head_content = format!("ref: refs/heads/{}\n", config.core.default_branch);
-        let head_path = if bare { "HEAD" } else { ".bloc/HEAD" };
-        fs::write(head_path, head_content)?;
-
-        // Create index for non-bare repos
-        if !bare {
-            let index = Index::new();
-            index.save()?;
-        }
+        fs::write(bloc_dir.join("HEAD"), head_content)?;
 
         // Hide .bloc directory on Windows (for non-bare repos)
         #[cfg(windows)]
@@ -107,14 +126,23 @@ impl BlocRepo {
             let _ = crate::hide_directory(".bloc");
         }
 
-        let repo = BlocRepo {
+        let mut repo = BlocRepo {
             config,
-            index: if bare { Index::new() } else { Index::load()? },
+            index: Index::new(PathBuf::new()),
             is_bare: bare,
-            work_dir: work_dir.clone(),
             bloc_dir: bloc_dir.clone(),
+            hasher,
+            object_index: RefCell::new(None),
+            packs: RefCell::new(None),
+            cache: RefCell::new(ObjectCache::new(ObjectCache::DEFAULT_CAPACITY)),
         };
 
+        // Create index for non-bare repos
+        repo.index = Index::new(repo.index_path());
+        if !bare {
+            repo.index.save()?;
+        }
+
         if bare {
             println!("{} {} {}", 
                      "Initialized empty bare Bloc repository in".bright_green().bold(),
@@ -136,13 +164,39 @@ impl BlocRepo {
         (Path::new("HEAD").exists() && Path::new("config").exists())
     }
 
-    pub fn is_bare_repo() -> bool {
-        Path::new("HEAD").exists() && Path::new("config").exists() && !Path::new(".bloc").exists()
+    pub fn objects_dir(&self) -> PathBuf {
+        self.bloc_dir.join("objects")
+    }
+
+    pub fn refs_dir(&self) -> PathBuf {
+        self.bloc_dir.join("refs")
+    }
+
+    pub fn head_path(&self) -> PathBuf {
+        self.bloc_dir.join("HEAD")
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        self.bloc_dir.join("index")
+    }
+
+    /// Path of the file storing the object with the given hash.
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        objects::loose_object_path(&self.objects_dir(), hash)
+    }
+
+    /// The commit HEAD points at directly, when it is not on a branch.
+    pub fn detached_head(&self) -> io::Result<Option<String>> {
+        let head_content = fs::read_to_string(self.head_path())?;
+        if head_content.starts_with("ref: ") {
+            Ok(None)
+        } else {
+            Ok(Some(head_content.trim().to_string()))
+        }
     }
 
     pub fn get_current_branch(&self) -> io::Result<String> {
-        let head_path = if self.is_bare { "HEAD" } else { ".bloc/HEAD" };
-        let head_content = fs::read_to_string(head_path)?;
+        let head_content = fs::read_to_string(self.head_path())?;
         
         if head_content.starts_with("ref: ") {
             let branch_ref = head_content.trim().strip_prefix("ref: ").unwrap();
@@ -153,38 +207,552 @@ impl BlocRepo {
         }
     }
 
+    pub fn object_hasher(&self) -> ObjectHasher {
+        self.hasher
+    }
+
+    /// Administrative directories of linked worktrees, laid out as git's
+    /// `$GIT_DIR/worktrees/<name>/`: each holds that worktree's own `HEAD`,
+    /// `index` and `logs/`, while objects and refs stay shared.
+    pub fn worktree_admin_dirs(&self) -> io::Result<Vec<PathBuf>> {
+        let worktrees_dir = self.bloc_dir.join("worktrees");
+        let mut dirs = Vec::new();
+        if worktrees_dir.is_dir() {
+            for entry in fs::read_dir(&worktrees_dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                }
+            }
+        }
+        dirs.sort();
+        Ok(dirs)
+    }
+
     pub fn hash_object(&self, content: &[u8]) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(content);
-        format!("{:x}", hasher.finalize())
+        self.hasher.hash(content)
     }
 
     pub fn write_object(&self, content: &[u8]) -> io::Result<String> {
         let hash = self.hash_object(content);
-        let objects_dir = if self.is_bare { "objects" } else { ".bloc/objects" };
-        let object_dir = format!("{}/{}", objects_dir, &hash[..2]);
-        fs::create_dir_all(&object_dir)?;
-        
-        let object_path = format!("{}/{}", object_dir, &hash[2..]);
+        if self.with_packs(|packs| packs.contains(&hash))? {
+            return Ok(hash);
+        }
+        let object_path = self.object_path(&hash);
+        let is_new = !object_path.exists();
+        if let Some(object_dir) = object_path.parent() {
+            fs::create_dir_all(object_dir)?;
+        }
         fs::write(object_path, content)?;
+
+        if is_new {
+            ObjectIndex::record(&self.objects_dir(), &hash)?;
+        }
+        if let Some(index) = self.object_index.borrow_mut().as_mut() {
+            index.insert(hash.clone());
+        }
         
         Ok(hash)
     }
 
-    pub fn read_object(&self, hash: &str) -> io::Result<Vec<u8>> {
-        let objects_dir = if self.is_bare { "objects" } else { ".bloc/objects" };
-        let object_path = format!("{}/{}/{}", objects_dir, &hash[..2], &hash[2..]);
-        fs::read(object_path)
+    pub fn read_object(&self, hash: &str) -> io::Result<Rc<Vec<u8>>> {
+        if let Some(object) = self.cache.borrow().get(hash) {
+            return Ok(Rc::clone(&object.data));
+        }
+        let data = Rc::new(self.read_stored_object(hash)?);
+        self.cache.borrow_mut().insert(hash, Rc::clone(&data));
+        Ok(data)
     }
 
-    pub fn get_refs_dir(&self) -> String {
-        if self.is_bare {
-            "refs".to_string()
+    fn read_stored_object(&self, hash: &str) -> io::Result<Vec<u8>> {
+        if hash.len() < 3 {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Object {} not found", hash)));
+        }
+        match fs::read(self.object_path(hash)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.with_packs(|packs| packs.read(hash))??.ok_or(e)
+            }
+            result => result,
+        }
+    }
+
+    /// Whether the object is stored, loose or packed.
+    pub fn has_object(&self, hash: &str) -> io::Result<bool> {
+        Ok(self.cache.borrow().get(hash).is_some() ||
+           self.object_path(hash).is_file() ||
+           self.with_packs(|packs| packs.contains(hash))?)
+    }
+
+    /// The type of an object, worked out from its content once.
+    pub fn object_type(&self, hash: &str) -> io::Result<ObjectType> {
+        if let Some(object_type) = self.cache.borrow().get(hash).and_then(|object| object.object_type) {
+            return Ok(object_type);
+        }
+        let object_type = ObjectType::of(&self.read_object(hash)?);
+        self.cache.borrow_mut().set_object_type(hash, object_type);
+        Ok(object_type)
+    }
+
+    /// Forget every cached object, for when objects have been removed or
+    /// moved behind the cache's back.
+    pub fn clear_object_cache(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    /// The stored size of an object in bytes.
+    pub fn object_size(&self, hash: &str) -> io::Result<u64> {
+        match fs::metadata(self.object_path(hash)) {
+            Ok(metadata) if metadata.is_file() => Ok(metadata.len()),
+            Ok(_) => Err(io::Error::new(io::ErrorKind::NotFound, format!("Object {} not found", hash))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.with_packs(|packs| packs.size(hash))?.ok_or(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn with_packs<R>(&self, f: impl FnOnce(&PackStore) -> R) -> io::Result<R> {
+        let mut packs = self.packs.borrow_mut();
+        if packs.is_none() {
+            *packs = Some(PackStore::load(&self.objects_dir())?);
+        }
+        Ok(f(packs.as_ref().unwrap()))
+    }
+
+    pub fn head_commit(&self) -> io::Result<Option<String>> {
+        if let Some(hash) = self.detached_head()? {
+            return Ok(Some(hash));
+        }
+        let branch_ref_path = self.refs_dir().join("heads").join(self.get_current_branch()?);
+        if branch_ref_path.exists() {
+            Ok(Some(fs::read_to_string(branch_ref_path)?.trim().to_string()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The commit being merged in, while a merge is waiting to be committed.
+    pub fn merge_head(&self) -> io::Result<Option<String>> {
+        let merge_head_path = self.bloc_dir.join("MERGE_HEAD");
+        if merge_head_path.is_file() {
+            Ok(Some(fs::read_to_string(merge_head_path)?.trim().to_string()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The prepared message for the merge commit, from `.bloc/MERGE_MSG`.
+    pub fn merge_message(&self) -> io::Result<Option<String>> {
+        let merge_msg_path = self.bloc_dir.join("MERGE_MSG");
+        if merge_msg_path.is_file() {
+            Ok(Some(fs::read_to_string(merge_msg_path)?.trim_end().to_string()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Forget an in-progress merge.
+    pub fn clear_merge_state(&self) -> io::Result<()> {
+        for name in ["MERGE_HEAD", "MERGE_MSG"] {
+            let path = self.bloc_dir.join(name);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve a revision (`HEAD`, a branch, tag or remote-tracking branch
+    /// name, a full or abbreviated commit hash, or a reflog selector like
+    /// `HEAD@{2}` or `main@{yesterday}`, optionally followed by `~<n>` or
+    /// `^<n>`) to a commit hash.
+    pub fn resolve_revision(&self, rev: &str) -> io::Result<String> {
+        if let Some(pos) = rev.rfind(['~', '^']) {
+            let (base, suffix) = rev.split_at(pos);
+            let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid revision '{}'", rev));
+            // `~<n>` follows n first parents; `^<n>` picks the n-th parent
+            let (steps, parent_number) = match &suffix[1..] {
+                "" => (1, 1),
+                n if suffix.starts_with('~') => (n.parse::<usize>().map_err(|_| invalid())?, 1),
+                n => (1, n.parse::<usize>().map_err(|_| invalid())?),
+            };
+
+            let mut hash = self.resolve_revision(base)?;
+            if parent_number == 0 {
+                return Ok(hash);
+            }
+            for _ in 0..steps {
+                hash = self.read_commit(&hash)?.parents().nth(parent_number - 1).cloned().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("Revision '{}' does not exist", rev))
+                })?;
+            }
+            return Ok(hash);
+        }
+
+        if let Some((name, selector)) = rev.strip_suffix('}').and_then(|rev| rev.split_once("@{")) {
+            return self.resolve_reflog_selector(if name.is_empty() { "HEAD" } else { name }, selector);
+        }
+
+        if rev == "HEAD" {
+            return self.head_commit()?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "HEAD does not point to a commit yet")
+            });
+        }
+
+        let branch_ref_path = self.refs_dir().join("heads").join(rev);
+        if branch_ref_path.is_file() {
+            return Ok(fs::read_to_string(branch_ref_path)?.trim().to_string());
+        }
+
+        let tag_ref_path = self.refs_dir().join("tags").join(rev);
+        if tag_ref_path.is_file() {
+            return self.peel_to_commit(fs::read_to_string(tag_ref_path)?.trim());
+        }
+
+        let remote_ref_path = self.refs_dir().join("remotes").join(rev);
+        if remote_ref_path.is_file() {
+            return Ok(fs::read_to_string(remote_ref_path)?.trim().to_string());
+        }
+
+        self.peel_to_commit(&self.resolve_object(rev)?)
+    }
+
+    /// Where `name` (`HEAD` or a branch) pointed according to its reflog:
+    /// `<n>` entries ago, or at a date such as `yesterday`, `2.hours.ago`
+    /// or `2024-01-31`.
+    fn resolve_reflog_selector(&self, name: &str, selector: &str) -> io::Result<String> {
+        let ref_name = if name == "HEAD" || name.starts_with("refs/") {
+            name.to_string()
         } else {
-            ".bloc/refs".to_string()
+            format!("refs/heads/{}", name)
+        };
+        let entries = self.read_reflog(&ref_name)?;
+        let not_found = |message: String| io::Error::new(io::ErrorKind::NotFound, message);
+        if entries.is_empty() {
+            return Err(not_found(format!("No reflog entries for '{}'", name)));
+        }
+
+        if let Ok(n) = selector.parse::<usize>() {
+            return entries.iter().rev().nth(n).map(|entry| entry.new.clone()).ok_or_else(|| {
+                not_found(format!("Log for '{}' only has {} entries", name, entries.len()))
+            });
+        }
+
+        let date = dates::parse_date(selector).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid reflog selector '{}@{{{}}}'", name, selector))
+        })?;
+        // The ref held whatever the last update before `date` set it to
+        match entries.iter().rev().find(|entry| entry.timestamp <= date) {
+            Some(entry) => Ok(entry.new.clone()),
+            None => {
+                let oldest = &entries[0];
+                if oldest.old.chars().all(|c| c == '0') {
+                    Err(not_found(format!("Log for '{}' only goes back to {}",
+                                          name, oldest.timestamp.format("%Y-%m-%d %H:%M:%S"))))
+                } else {
+                    Ok(oldest.old.clone())
+                }
+            }
+        }
+    }
+
+    /// The commit an object names: annotated tags are followed to their
+    /// target, anything else is returned unchanged.
+    pub fn peel_to_commit(&self, hash: &str) -> io::Result<String> {
+        match self.read_tag(hash) {
+            Ok(tag) => Ok(tag.object.clone()),
+            Err(_) => Ok(hash.to_string()),
         }
     }
 
+    /// Expand a full or abbreviated object hash to the full hash of an
+    /// existing object.
+    pub fn resolve_object(&self, prefix: &str) -> io::Result<String> {
+        if prefix.len() < 4 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Unknown revision '{}'", prefix)));
+        }
+
+        let prefix = prefix.to_lowercase();
+        let mut rebuilt = false;
+        if self.object_index.borrow().is_none() {
+            let stored = ObjectIndex::load(&self.objects_dir())?;
+            rebuilt = stored.is_none();
+            match stored {
+                Some(index) => *self.object_index.borrow_mut() = Some(index),
+                None => self.rebuild_object_index()?,
+            }
+        }
+
+        // The stored index can miss objects another tool wrote, or still
+        // list pruned ones, so a lookup it can't settle rescans the disk
+        let mut matches = self.indexed_objects(&prefix)?;
+        if matches.len() != 1 && !rebuilt {
+            self.rebuild_object_index()?;
+            matches = self.indexed_objects(&prefix)?;
+        }
+
+        match matches.len() {
+            0 => Err(io::Error::new(io::ErrorKind::NotFound, format!("Unknown revision '{}'", prefix))),
+            1 => Ok(matches.remove(0)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Ambiguous revision '{}'", prefix))),
+        }
+    }
+
+    /// Stored objects the prefix index lists under `prefix`.
+    fn indexed_objects(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let candidates: Vec<String> = self.object_index
+            .borrow()
+            .as_ref()
+            .map(|index| index.find_prefix(prefix).into_iter().cloned().collect())
+            .unwrap_or_default();
+        let mut matches = Vec::new();
+        for hash in candidates {
+            if self.has_object(&hash)? {
+                matches.push(hash);
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Rebuild the prefix index from the objects currently on disk and
+    /// store it, and forget the packs read so far.
+    pub fn rebuild_object_index(&self) -> io::Result<()> {
+        *self.packs.borrow_mut() = None;
+        self.clear_object_cache();
+        let index = ObjectIndex::build(&self.objects_dir())?;
+        index.save(&self.objects_dir())?;
+        *self.object_index.borrow_mut() = Some(index);
+        Ok(())
+    }
+
+    pub fn read_commit(&self, hash: &str) -> io::Result<Rc<Commit>> {
+        if let Some(commit) = self.cache.borrow().get(hash).and_then(|object| object.commit.clone()) {
+            return Ok(commit);
+        }
+        let content = self.read_object(hash)?;
+        let commit: Commit = serde_json::from_slice(&content).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Object {} is not a commit", hash))
+        })?;
+        let commit = Rc::new(commit);
+        self.cache.borrow_mut().set_commit(hash, Rc::clone(&commit));
+        Ok(commit)
+    }
+
+    pub fn read_tag(&self, hash: &str) -> io::Result<Rc<Tag>> {
+        if let Some(tag) = self.cache.borrow().get(hash).and_then(|object| object.tag.clone()) {
+            return Ok(tag);
+        }
+        let content = self.read_object(hash)?;
+        let tag: Tag = serde_json::from_slice(&content).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Object {} is not a tag", hash))
+        })?;
+        let tag = Rc::new(tag);
+        self.cache.borrow_mut().set_tag(hash, Rc::clone(&tag));
+        Ok(tag)
+    }
+
+    /// Commits at the boundary of a shallow clone, whose parents were never
+    /// copied; listed one per line in `.bloc/shallow`.
+    pub fn shallow_commits(&self) -> io::Result<HashSet<String>> {
+        match fs::read_to_string(self.bloc_dir.join("shallow")) {
+            Ok(content) => Ok(content.lines().map(|line| line.trim().to_string()).filter(|line| !line.is_empty()).collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Record that `branch` moved from `old` to `new` in its reflog, and in
+    /// HEAD's when it is the current branch.
+    pub fn record_ref_update(&self, branch: &str, old: Option<&str>, new: &str, message: &str) -> io::Result<()> {
+        self.append_reflog(&format!("refs/heads/{}", branch), old, new, message)?;
+        if self.get_current_branch()? == branch {
+            self.append_reflog("HEAD", old, new, message)?;
+        }
+        Ok(())
+    }
+
+    /// Move the current branch, or HEAD itself when it is detached, from
+    /// `old` to `new`, logging the move in the reflogs as `message`.
+    pub fn update_head(&self, old: Option<&str>, new: &str, message: &str) -> io::Result<()> {
+        if self.detached_head()?.is_some() {
+            fs::write(self.head_path(), new)?;
+            return self.append_reflog("HEAD", old, new, message);
+        }
+        let branch = self.get_current_branch()?;
+        fs::write(self.refs_dir().join("heads").join(&branch), new)?;
+        self.record_ref_update(&branch, old, new, message)
+    }
+
+    /// Append a line to the reflog of `ref_name` (`HEAD` or a full ref name)
+    /// under `.bloc/logs`. A missing `old` is written as all zeros.
+    pub fn append_reflog(&self, ref_name: &str, old: Option<&str>, new: &str, message: &str) -> io::Result<()> {
+        let log_path = self.bloc_dir.join("logs").join(ref_name);
+        if let Some(parent) = log_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let old = old.map(String::from).unwrap_or_else(|| "0".repeat(new.len()));
+        let mut log = fs::OpenOptions::new().create(true).append(true).open(log_path)?;
+        writeln!(log, "{} {} {} {} +0000\t{}",
+                old,
+                new,
+                self.get_author_signature(),
+                Utc::now().timestamp(),
+                message.lines().next().unwrap_or_default())
+    }
+
+    /// Entries of the reflog of `ref_name`, oldest first.
+    pub fn read_reflog(&self, ref_name: &str) -> io::Result<Vec<ReflogEntry>> {
+        let content = match fs::read_to_string(self.bloc_dir.join("logs").join(ref_name)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(content.lines().filter_map(ReflogEntry::parse).collect())
+    }
+
+    /// Files recorded in a commit, keyed by path.
+    pub fn read_tree(&self, commit_hash: &str) -> io::Result<Rc<BTreeMap<String, String>>> {
+        if let Some(tree) = self.cache.borrow().get(commit_hash).and_then(|object| object.tree.clone()) {
+            return Ok(tree);
+        }
+        let tree = Rc::new(self.read_commit(commit_hash)?.tree_entries());
+        self.cache.borrow_mut().set_tree(commit_hash, Rc::clone(&tree));
+        Ok(tree)
+    }
+
+    /// The snapshot the next commit would record: HEAD's files with the
+    /// staging area applied on top.
+    pub fn staged_tree(&self) -> io::Result<BTreeMap<String, String>> {
+        let mut tree = match self.head_commit()? {
+            Some(head_hash) => Rc::unwrap_or_clone(self.read_tree(&head_hash)?),
+            None => BTreeMap::new(),
+        };
+        for (path, entry) in &self.index.entries {
+            match entry.staged_hash() {
+                Some(hash) => tree.insert(path.clone(), hash.clone()),
+                None => tree.remove(path),
+            };
+        }
+        Ok(tree)
+    }
+
+    /// Write a blob's content to `path` in the working tree, creating
+    /// parent directories as needed.
+    ///
+    /// Like git, the file gets the current time as its mtime, so build tools
+    /// see it as changed; files a checkout leaves alone keep theirs. The
+    /// file's size and mtime are recorded in the index's stat cache so the
+    /// next status or add knows it is clean without re-hashing it.
+    pub fn checkout_file(&self, path: &str, hash: &str) -> io::Result<()> {
+        let content = self.read_object(hash)?;
+        if let Some(parent) = Path::new(path).parent()
+            && !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, &*content)?;
+        self.index.record_stat(path, hash, &fs::metadata(path)?);
+        Ok(())
+    }
+
+    /// Hash of a working tree file's current content, if it exists. Files
+    /// whose size and mtime match the stat cache are not read again.
+    pub fn working_file_hash(&self, path: &str) -> Option<String> {
+        let metadata = fs::metadata(path).ok()?;
+        if let Some(hash) = self.index.cached_hash(path, &metadata) {
+            return Some(hash);
+        }
+        let hash = self.hash_object(&fs::read(path).ok()?);
+        self.index.record_stat(path, &hash, &metadata);
+        Some(hash)
+    }
+
+    /// Paths whose local changes would be overwritten by moving the working
+    /// tree from the `from` snapshot to the `to` snapshot.
+    pub fn overwritten_paths(&self, from: &BTreeMap<String, String>, to: &BTreeMap<String, String>) -> Vec<String> {
+        let sparse_patterns = self.sparse_patterns();
+        let mut paths: Vec<&String> = from.keys().chain(to.keys()).collect();
+        paths.sort();
+        paths.dedup();
+
+        let mut blocked = Vec::new();
+        for path in paths {
+            let (old, new) = (from.get(path), to.get(path));
+            if old == new {
+                continue;
+            }
+
+            let staged = self.index.entries.get(path);
+            if staged.is_some_and(|entry| entry.staged_hash() != new) {
+                blocked.push(path.clone());
+                continue;
+            }
+
+            // Paths outside the sparse checkout are never on disk
+            if !in_sparse_checkout(sparse_patterns.as_deref(), path) {
+                continue;
+            }
+            let working = self.working_file_hash(path);
+            if working.as_ref() != old && working.as_ref() != new {
+                blocked.push(path.clone());
+            }
+        }
+        blocked
+    }
+
+    /// Move the working tree from the `from` snapshot to the `to` snapshot,
+    /// leaving paths that are identical in both untouched and skipping paths
+    /// outside the sparse checkout.
+    pub fn update_working_tree(&self, from: &BTreeMap<String, String>, to: &BTreeMap<String, String>) -> io::Result<()> {
+        let sparse_patterns = self.sparse_patterns();
+        for (path, hash) in to {
+            if from.get(path) != Some(hash) && in_sparse_checkout(sparse_patterns.as_deref(), path) {
+                self.checkout_file(path, hash)?;
+            }
+        }
+
+        for path in from.keys() {
+            if !to.contains_key(path) {
+                self.remove_working_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete a file from the working tree along with any directories the
+    /// removal leaves empty.
+    pub fn remove_working_file(&self, path: &str) -> io::Result<()> {
+        self.index.forget_stat(path);
+        if Path::new(path).exists() {
+            fs::remove_file(path)?;
+        }
+        let mut parent = Path::new(path).parent();
+        while let Some(dir) = parent {
+            if dir.as_os_str().is_empty() || fs::remove_dir(dir).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+        Ok(())
+    }
+
+    /// Patterns from `.bloc/sparse-checkout`, or `None` when sparse checkout
+    /// is disabled and the whole tree is materialized.
+    pub fn sparse_patterns(&self) -> Option<Vec<String>> {
+        let content = fs::read_to_string(self.bloc_dir.join("sparse-checkout")).ok()?;
+        Some(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect())
+    }
+
+    /// Whether `path` lies inside the `.bloc` directory.
+    pub fn is_internal_path(path: &Path) -> bool {
+        path.components().any(|component| component.as_os_str() == ".bloc")
+    }
+
     pub fn should_ignore(&self, path: &Path) -> bool {
         if self.is_bare {
             return false; // Bare repos don't have working directory files
@@ -197,48 +765,11 @@ impl BlocRepo {
             return true;
         }
 
-        // Check .blocignore
-        if let Ok(ignore_content) = fs::read_to_string(".blocignore") {
-            for line in ignore_content.lines() {
-                let pattern = line.trim();
-                if pattern.is_empty() || pattern.starts_with('#') {
-                    continue;
-                }
-                
-                // Handle directory patterns ending with /
-                if pattern.ends_with('/') {
-                    let dir_pattern = &pattern[..pattern.len() - 1];
-                    if path_str.starts_with(dir_pattern) || 
-                       path_str.starts_with(&format!("./{}", dir_pattern)) ||
-                       path_str.contains(&format!("/{}", dir_pattern)) {
-                        return true;
-                    }
-                }
-                
-                // Handle wildcard patterns
-                if pattern.contains('*') {
-                    if pattern.starts_with('*') && pattern.ends_with('*') {
-                        let middle = &pattern[1..pattern.len() - 1];
-                        if path_str.contains(middle) {
-                            return true;
-                        }
-                    } else if pattern.starts_with('*') {
-                        let suffix = &pattern[1..];
-                        if path_str.ends_with(suffix) {
-                            return true;
-                        }
-                    } else if pattern.ends_with('*') {
-                        let prefix = &pattern[..pattern.len() - 1];
-                        if path_str.starts_with(prefix) {
-                            return true;
-                        }
-                    }
-                }
-                
-                // Exact match
-                if path_str.contains(pattern) {
-                    return true;
-                }
+        // Shared patterns from .blocignore, then local ones from .bloc/info/exclude
+        for ignore_file in [PathBuf::from(".blocignore"), self.bloc_dir.join("info").join("exclude")] {
+            if let Ok(ignore_content) = fs::read_to_string(ignore_file)
+                && ignore_content.lines().any(|line| ignore_pattern_matches(line.trim(), &path_str)) {
+                return true;
             }
         }
         
@@ -249,3 +780,123 @@ impl BlocRepo {
         format!("{} <{}>", self.config.user.name, self.config.user.email)
     }
 }
+
+/// One line of a reflog: `<old> <new> <identity> <time> <zone>\t<message>`.
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    fn parse(line: &str) -> Option<ReflogEntry> {
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = header.split(' ');
+        let old = fields.next()?.to_string();
+        let new = fields.next()?.to_string();
+        // The identity holds spaces, so the time is found from the end
+        let mut trailing = header.rsplit(' ');
+        let _zone = trailing.next()?;
+        let timestamp = DateTime::from_timestamp(trailing.next()?.parse().ok()?, 0)?;
+        Some(ReflogEntry { old, new, timestamp, message: message.to_string() })
+    }
+}
+
+/// Whether one line of an ignore file matches `path_str`.
+fn ignore_pattern_matches(pattern: &str, path_str: &str) -> bool {
+    if pattern.is_empty() || pattern.starts_with('#') {
+        return false;
+    }
+
+    // Handle directory patterns ending with /
+    if let Some(dir_pattern) = pattern.strip_suffix('/')
+        && (path_str.starts_with(dir_pattern) || 
+            path_str.starts_with(&format!("./{}", dir_pattern)) ||
+            path_str.contains(&format!("/{}", dir_pattern))) {
+        return true;
+    }
+    
+    // Handle wildcard patterns
+    if pattern.contains('*') {
+        if pattern.starts_with('*') && pattern.ends_with('*') {
+            let middle = &pattern[1..pattern.len() - 1];
+            if path_str.contains(middle) {
+                return true;
+            }
+        } else if let Some(suffix) = pattern.strip_prefix('*') {
+            if path_str.ends_with(suffix) {
+                return true;
+            }
+        } else if let Some(prefix) = pattern.strip_suffix('*')
+            && path_str.starts_with(prefix) {
+            return true;
+        }
+    }
+    
+    // Exact match
+    path_str.contains(pattern)
+}
+
+/// Whether `path` is materialized under the given sparse-checkout patterns.
+/// A pattern ending in `/` selects a directory, a pattern containing `*`
+/// is matched as a glob against the whole path, and any other pattern
+/// selects that exact file or directory.
+pub fn in_sparse_checkout(patterns: Option<&[String]>, path: &str) -> bool {
+    let Some(patterns) = patterns else {
+        return true;
+    };
+
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.trim_start_matches('/');
+        if pattern.contains('*') {
+            glob_match(pattern, path)
+        } else {
+            let dir = pattern.trim_end_matches('/');
+            path == dir || path.starts_with(&format!("{}/", dir))
+        }
+    })
+}
+
+/// Match `text` against a glob where `*` matches any run of characters.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+    if !text.starts_with(first) || !text[first.len()..].ends_with(last) {
+        return false;
+    }
+
+    let mut remaining = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match remaining.find(part) {
+            Some(pos) => remaining = &remaining[pos + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use super::BlocRepo;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Repositories are opened relative to the current directory, which the
+    /// whole test process shares, so tests using one take turns.
+    static CURRENT_DIR: Mutex<()> = Mutex::new(());
+
+    /// A fresh repository in its own temporary directory, made the current
+    /// directory for as long as the returned guard is held.
+    pub fn temp_repo(name: &str) -> (MutexGuard<'static, ()>, BlocRepo, PathBuf) {
+        let guard = CURRENT_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = std::env::temp_dir().join(format!("bloc-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = BlocRepo::init(Some(dir.to_str().unwrap()), false).unwrap();
+        (guard, repo, dir)
+    }
+}
@@ -0,0 +1,94 @@
+use crate::objects::{Commit, Tag};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+const BLOCK_SIZE: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureStatus {
+    Unsigned,
+    Good,
+    Bad,
+}
+
+/// An object carrying an optional signature over the rest of its content.
+trait Signable: Serialize + Clone {
+    fn signature_mut(&mut self) -> &mut Option<String>;
+}
+
+impl Signable for Commit {
+    fn signature_mut(&mut self) -> &mut Option<String> {
+        &mut self.signature
+    }
+}
+
+impl Signable for Tag {
+    fn signature_mut(&mut self) -> &mut Option<String> {
+        &mut self.signature
+    }
+}
+
+/// Sign a commit with an HMAC-SHA256 over its serialized content.
+pub fn sign_commit(commit: &Commit, key: &str) -> Result<String, serde_json::Error> {
+    sign(commit, key)
+}
+
+pub fn verify_commit(commit: &Commit, key: &str) -> Result<SignatureStatus, serde_json::Error> {
+    verify(commit, key)
+}
+
+/// Sign an annotated tag the same way commits are signed.
+pub fn sign_tag(tag: &Tag, key: &str) -> Result<String, serde_json::Error> {
+    sign(tag, key)
+}
+
+pub fn verify_tag(tag: &Tag, key: &str) -> Result<SignatureStatus, serde_json::Error> {
+    verify(tag, key)
+}
+
+fn sign<T: Signable>(object: &T, key: &str) -> Result<String, serde_json::Error> {
+    Ok(hmac_sha256(key.as_bytes(), signing_payload(object)?.as_bytes()))
+}
+
+fn verify<T: Signable>(object: &T, key: &str) -> Result<SignatureStatus, serde_json::Error> {
+    let Some(signature) = object.clone().signature_mut().take() else {
+        return Ok(SignatureStatus::Unsigned);
+    };
+
+    if constant_time_eq(sign(object, key)?.as_bytes(), signature.as_bytes()) {
+        Ok(SignatureStatus::Good)
+    } else {
+        Ok(SignatureStatus::Bad)
+    }
+}
+
+/// Compare two MACs in time that depends only on their length, so a forger
+/// can't learn how much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The object as serialized without its signature, which is what gets signed.
+fn signing_payload<T: Signable>(object: &T) -> Result<String, serde_json::Error> {
+    let mut unsigned = object.clone();
+    *unsigned.signature_mut() = None;
+    serde_json::to_string_pretty(&unsigned)
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> String {
+    let mut block_key = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block_key[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block_key.map(|b| b ^ 0x36));
+    inner.update(message);
+
+    let mut outer = Sha256::new();
+    outer.update(block_key.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    format!("{:x}", outer.finalize())
+}
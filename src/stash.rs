@@ -0,0 +1,293 @@
+use crate::commands;
+use crate::merge;
+use crate::objects::Commit;
+use crate::repository::{in_sparse_checkout, BlocRepo};
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::path::Path;
+use walkdir::WalkDir;
+use colored::*;
+
+/// What `stash push` does with files that aren't tracked.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UntrackedFiles {
+    /// Leave them in the working tree
+    Leave,
+    /// Stash them too, except ignored ones
+    Include,
+    /// Stash them too, ignored ones included
+    IncludeIgnored,
+}
+
+/// Save local changes as a stash entry and reset the working tree and
+/// index to HEAD.
+///
+/// An entry is a commit whose tree is the working tree's tracked files,
+/// whose first parent is HEAD and whose second parent is a commit of the
+/// staged snapshot. Untracked files stashed as `untracked` asks are kept in
+/// a parentless commit recorded as the third parent, and removed from the
+/// working tree. The newest entry is `refs/stash`; the whole stack is the
+/// reflog `logs/refs/stash`, oldest first, so `stash@{0}` is its last line.
+pub fn push(repo: &mut BlocRepo, message: Option<&str>, untracked: UntrackedFiles) -> Result<(), Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        return Err("Cannot stash in a bare repository".into());
+    }
+    let Some(head) = repo.head_commit()? else {
+        return Err("You do not have the initial commit yet".into());
+    };
+    if repo.index.entries.values().any(|entry| entry.conflicted) {
+        return Err("Cannot stash while there are unresolved conflicts".into());
+    }
+
+    let head_tree = repo.read_tree(&head)?;
+    let staged_tree = repo.staged_tree()?;
+    let working_tree = working_snapshot(repo, &staged_tree)?;
+    let untracked_tree = match untracked {
+        UntrackedFiles::Leave => BTreeMap::new(),
+        _ => untracked_snapshot(repo, &staged_tree, untracked == UntrackedFiles::IncludeIgnored)?,
+    };
+    if staged_tree == *head_tree && working_tree == staged_tree && untracked_tree.is_empty() {
+        println!("{}", "No local changes to save".bright_yellow());
+        return Ok(());
+    }
+
+    let head_commit = repo.read_commit(&head)?;
+    let branch = match repo.detached_head()? {
+        Some(_) => "(no branch)".to_string(),
+        None => repo.get_current_branch()?,
+    };
+    let summary = format!("{} {}", &head[..8], head_commit.message.lines().next().unwrap_or_default());
+    let message = match message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}: {}", branch, summary),
+    };
+
+    let mut parents = vec![write_stash_commit(repo, Some(&head), Vec::new(), &staged_tree, &format!("index on {}: {}", branch, summary))?];
+    if !untracked_tree.is_empty() {
+        parents.push(write_stash_commit(repo, None, Vec::new(), &untracked_tree, &format!("untracked files on {}: {}", branch, summary))?);
+    }
+    let stash_commit = write_stash_commit(repo, Some(&head), parents, &working_tree, &message)?;
+
+    let stash_ref = repo.refs_dir().join("stash");
+    let previous = fs::read_to_string(&stash_ref).ok().map(|hash| hash.trim().to_string());
+    fs::write(&stash_ref, &stash_commit)?;
+    repo.append_reflog("refs/stash", previous.as_deref(), &stash_commit, &message)?;
+
+    repo.update_working_tree(&working_tree, &head_tree)?;
+    for path in untracked_tree.keys() {
+        repo.remove_working_file(path)?;
+    }
+    repo.index.entries.clear();
+    repo.index.save()?;
+
+    println!("{} {}", "Saved working directory and index state".bright_green(), message.white());
+    Ok(())
+}
+
+/// List the stash entries, newest first.
+pub fn list(repo: &BlocRepo) -> io::Result<()> {
+    for (n, entry) in repo.read_reflog("refs/stash")?.iter().rev().enumerate() {
+        println!("{}: {}", format!("stash@{{{}}}", n).bright_yellow(), entry.message);
+    }
+    Ok(())
+}
+
+/// Reapply the changes of stash entry `stash` (`stash@{n}` or `n`, the
+/// newest by default) on top of the current working tree, keeping the
+/// entry, and restore the untracked files it holds. Returns whether it
+/// applied without conflicts.
+pub fn apply(repo: &mut BlocRepo, stash: Option<&str>) -> Result<bool, Box<dyn std::error::Error>> {
+    if repo.is_bare {
+        return Err("Cannot apply a stash in a bare repository".into());
+    }
+    let (n, hash) = stash_entry(repo, stash)?;
+    let stash_commit = repo.read_commit(&hash)?;
+    let base_tree = match &stash_commit.parent {
+        Some(parent) => repo.read_tree(parent)?,
+        None => Rc::default(),
+    };
+    let stash_tree = stash_commit.tree_entries();
+    let untracked_tree = match stash_commit.merge_parents.get(1) {
+        Some(untracked_commit) => repo.read_tree(untracked_commit)?,
+        None => Rc::default(),
+    };
+    if let Some(path) = untracked_tree.keys().find(|path| Path::new(path).exists()) {
+        return Err(format!("{} already exists, no checkout", path).into());
+    }
+
+    let ours_tree = repo.staged_tree()?;
+    let outcomes = merge::merge_trees(repo, &base_tree, &ours_tree, &stash_tree, "Stashed changes")?;
+    let result = merge::result_tree(repo, &ours_tree, &outcomes);
+
+    // Local changes the stash would overwrite must be committed or restored first
+    let blocked = repo.overwritten_paths(&ours_tree, &result);
+    if !blocked.is_empty() {
+        println!("{}: {}",
+                "Cannot apply stash".bright_red().bold(),
+                "local changes to the following files would be overwritten".bright_red());
+        for path in &blocked {
+            println!("  {}", path.bright_red());
+        }
+        println!("{}", "Commit or restore your changes before applying the stash".bright_yellow());
+        return Ok(false);
+    }
+
+    // Like git, the applied changes are left unstaged, except for files the
+    // stash adds, which are staged so they stay tracked
+    let head_tree = match repo.head_commit()? {
+        Some(head) => repo.read_tree(&head)?,
+        None => Rc::default(),
+    };
+    let previous_entries = repo.index.entries.clone();
+    let conflicts = merge::apply_outcomes(repo, &ours_tree, outcomes)?;
+    let applied: Vec<String> = result.keys().chain(ours_tree.keys()).cloned().collect();
+    for path in applied {
+        if conflicts.contains(&path) || (!head_tree.contains_key(&path) && result.contains_key(&path)) {
+            continue;
+        }
+        match previous_entries.get(&path) {
+            Some(entry) => repo.index.entries.insert(path, entry.clone()),
+            None => repo.index.entries.remove(&path),
+        };
+    }
+    for (path, hash) in untracked_tree.iter() {
+        repo.checkout_file(path, hash)?;
+    }
+    repo.index.save()?;
+
+    if conflicts.is_empty() {
+        println!("{} {}", "Applied".bright_green(), format!("stash@{{{}}}", n).bright_yellow());
+        return Ok(true);
+    }
+    for path in &conflicts {
+        println!("{}: {}", "CONFLICT".bright_red().bold(), path.bright_cyan());
+    }
+    println!("{}", "Fix the conflicts and 'bloc add' the results; the stash entry is kept.".bright_yellow());
+    Ok(false)
+}
+
+/// Apply stash entry `stash` and drop it once it applied cleanly.
+pub fn pop(repo: &mut BlocRepo, stash: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    if apply(repo, stash)? {
+        drop(repo, stash)?;
+    }
+    Ok(())
+}
+
+/// Remove stash entry `stash` without applying it.
+pub fn drop(repo: &BlocRepo, stash: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let (n, hash) = stash_entry(repo, stash)?;
+    let log_path = repo.bloc_dir.join("logs/refs/stash");
+    let mut lines: Vec<String> = fs::read_to_string(&log_path)?.lines().map(String::from).collect();
+    lines.remove(lines.len() - 1 - n);
+
+    let stash_ref = repo.refs_dir().join("stash");
+    match lines.last() {
+        Some(newest) => {
+            // The entry below the dropped one becomes the top of the stack
+            let newest_hash = newest.split(' ').nth(1).unwrap_or_default();
+            fs::write(&stash_ref, newest_hash)?;
+            fs::write(&log_path, lines.join("\n") + "\n")?;
+        }
+        None => {
+            fs::remove_file(&stash_ref)?;
+            fs::remove_file(&log_path)?;
+        }
+    }
+
+    println!("{} {} ({})", "Dropped".bright_green(), format!("stash@{{{}}}", n).bright_yellow(), hash[..8].bright_black());
+    Ok(())
+}
+
+/// Remove every stash entry.
+pub fn clear(repo: &BlocRepo) -> io::Result<()> {
+    for path in [repo.refs_dir().join("stash"), repo.bloc_dir.join("logs/refs/stash")] {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// The position and commit of the stash entry `stash` names: `stash@{n}`
+/// or a bare `n`, counting from the newest.
+fn stash_entry(repo: &BlocRepo, stash: Option<&str>) -> Result<(usize, String), Box<dyn std::error::Error>> {
+    let entries = repo.read_reflog("refs/stash")?;
+    if entries.is_empty() {
+        return Err("No stash entries found".into());
+    }
+    let name = stash.unwrap_or("0");
+    let position = name
+        .strip_prefix("stash@{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(name);
+    let n: usize = position.parse().map_err(|_| format!("'{}' is not a stash reference", name))?;
+    match entries.iter().rev().nth(n) {
+        Some(entry) => Ok((n, entry.new.clone())),
+        None => Err(format!("stash@{{{}}} does not exist", n).into()),
+    }
+}
+
+/// The tracked files as they are in the working tree, their contents
+/// written to the object store. Paths outside the sparse checkout keep
+/// their staged version.
+fn working_snapshot(repo: &BlocRepo, staged_tree: &BTreeMap<String, String>) -> io::Result<BTreeMap<String, String>> {
+    let sparse_patterns = repo.sparse_patterns();
+    let mut snapshot = BTreeMap::new();
+    for (path, staged_hash) in staged_tree {
+        if !in_sparse_checkout(sparse_patterns.as_deref(), path) {
+            snapshot.insert(path.clone(), staged_hash.clone());
+            continue;
+        }
+        let Some(hash) = repo.working_file_hash(path) else {
+            continue;
+        };
+        if !repo.has_object(&hash)? {
+            repo.write_object(&fs::read(path)?)?;
+        }
+        snapshot.insert(path.clone(), hash);
+    }
+    Ok(snapshot)
+}
+
+/// The files `status` would list as untracked, or with `include_ignored`
+/// ignored ones as well, their contents written to the object store.
+fn untracked_snapshot(repo: &BlocRepo, tracked: &BTreeMap<String, String>, include_ignored: bool) -> io::Result<BTreeMap<String, String>> {
+    let sparse_patterns = repo.sparse_patterns();
+    let mut snapshot = BTreeMap::new();
+    let walker = WalkDir::new(".").into_iter().filter_entry(|entry| !BlocRepo::is_internal_path(entry.path()));
+    for entry in walker.filter_map(|e| e.ok()) {
+        let path = commands::relative_path(entry.path());
+        if !entry.file_type().is_file() ||
+           tracked.contains_key(&path) ||
+           !in_sparse_checkout(sparse_patterns.as_deref(), &path) ||
+           (!include_ignored && repo.should_ignore(entry.path())) {
+            continue;
+        }
+        snapshot.insert(path, repo.write_object(&fs::read(entry.path())?)?);
+    }
+    Ok(snapshot)
+}
+
+fn write_stash_commit(
+    repo: &BlocRepo,
+    parent: Option<&str>,
+    merge_parents: Vec<String>,
+    tree: &BTreeMap<String, String>,
+    message: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let commit = Commit {
+        parent: parent.map(String::from),
+        merge_parents,
+        author: repo.get_author_signature(),
+        committer: repo.get_author_signature(),
+        timestamp: Utc::now(),
+        message: message.to_string(),
+        tree: commands::serialize_tree(tree),
+        signature: None,
+    };
+    Ok(repo.write_object(serde_json::to_string_pretty(&commit)?.as_bytes())?)
+}
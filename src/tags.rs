@@ -0,0 +1,160 @@
+use crate::objects::Tag;
+use crate::refsort::{self, RefSort};
+use crate::repository::BlocRepo;
+use crate::signing::{self, SignatureStatus};
+use chrono::Utc;
+use std::fs;
+use std::io;
+use colored::*;
+
+/// Create a tag at `revision` (HEAD by default). With a message the tag is
+/// annotated: a tag object recording the tagger, date and message, which
+/// can also be signed.
+pub fn create_tag(
+    repo: &BlocRepo,
+    name: &str,
+    revision: Option<&str>,
+    message: Option<&str>,
+    sign: bool,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if name.is_empty() || name.starts_with('-') || name.contains("..") || name.contains(char::is_whitespace) {
+        return Err(format!("'{}' is not a valid tag name", name).into());
+    }
+
+    let tag_ref_path = repo.refs_dir().join("tags").join(name);
+    if tag_ref_path.exists() && !force {
+        return Err(format!("Tag '{}' already exists (use --force to replace it)", name).into());
+    }
+
+    let target = repo.resolve_revision(revision.unwrap_or("HEAD"))?;
+    repo.read_commit(&target)?;
+
+    let tag_hash = match message {
+        Some(message) => {
+            let mut tag = Tag {
+                object: target.clone(),
+                tag: name.to_string(),
+                tagger: repo.get_author_signature(),
+                timestamp: Utc::now(),
+                message: message.to_string(),
+                signature: None,
+            };
+            if sign {
+                let key = repo.config.user.signingkey.as_ref().ok_or(
+                    "No signing key configured (set one with 'bloc config set user.signingkey <secret>')"
+                )?;
+                tag.signature = Some(signing::sign_tag(&tag, key)?);
+            }
+            repo.write_object(serde_json::to_string_pretty(&tag)?.as_bytes())?
+        }
+        None => target.clone(),
+    };
+
+    if let Some(parent) = tag_ref_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&tag_ref_path, &tag_hash)?;
+
+    println!("{} '{}' {} {}",
+            "Created tag".bright_green().bold(),
+            name.bright_cyan().bold(),
+            "at".bright_green(),
+            target.get(..8).unwrap_or(&target).bright_yellow());
+    Ok(())
+}
+
+pub fn delete_tag(repo: &BlocRepo, name: &str) -> io::Result<()> {
+    let tag_ref_path = repo.refs_dir().join("tags").join(name);
+    if !tag_ref_path.is_file() {
+        println!("{} '{}' {}",
+                "Tag".bright_yellow(),
+                name.bright_cyan(),
+                "does not exist".bright_yellow());
+        return Ok(());
+    }
+
+    let target = fs::read_to_string(&tag_ref_path)?;
+    let target = target.trim();
+    fs::remove_file(&tag_ref_path)?;
+    println!("{} '{}' {}",
+            "Deleted tag".bright_red().bold(),
+            name.bright_cyan(),
+            format!("(was {})", target.get(..8).unwrap_or(target)).bright_black());
+    Ok(())
+}
+
+/// List tags by name. With `annotation_lines`, each tag is followed by the
+/// first lines of its annotation, or its commit's subject for lightweight
+/// tags.
+pub fn list_tags(repo: &BlocRepo, annotation_lines: Option<usize>, sort: RefSort) -> Result<(), Box<dyn std::error::Error>> {
+    let tags_dir = repo.refs_dir().join("tags");
+    let mut names = refsort::ref_names(&tags_dir)?;
+    sort.sort(repo, &tags_dir, &mut names);
+
+    for name in names {
+        let Some(lines) = annotation_lines else {
+            println!("{}", name.bright_cyan());
+            continue;
+        };
+
+        let hash = fs::read_to_string(tags_dir.join(&name))?.trim().to_string();
+        let text = match repo.read_tag(&hash) {
+            Ok(tag) => tag.message.clone(),
+            Err(_) => repo.read_commit(&hash)?.message.lines().next().unwrap_or_default().to_string(),
+        };
+
+        let mut annotation = text.lines().take(lines.max(1));
+        println!("{} {}", format!("{:<15}", name).bright_cyan(), annotation.next().unwrap_or_default().white());
+        for line in annotation {
+            println!("{:<15} {}", "", line.white());
+        }
+    }
+
+    Ok(())
+}
+
+/// Check an annotated tag's signature. Returns whether the signature is valid.
+pub fn verify_tag(repo: &BlocRepo, name: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let tag_ref_path = repo.refs_dir().join("tags").join(name);
+    if !tag_ref_path.is_file() {
+        return Err(format!("Tag '{}' does not exist", name).into());
+    }
+
+    let hash = fs::read_to_string(&tag_ref_path)?.trim().to_string();
+    let Ok(tag) = repo.read_tag(&hash) else {
+        println!("{} '{}' {}",
+                "Tag".bright_yellow(),
+                name.bright_cyan(),
+                "is a lightweight tag and cannot be signed".bright_yellow());
+        return Ok(false);
+    };
+    let key = repo.config.user.signingkey.as_ref().ok_or(
+        "No signing key configured (set one with 'bloc config set user.signingkey <secret>')"
+    )?;
+
+    match signing::verify_tag(&tag, key)? {
+        SignatureStatus::Good => {
+            println!("{} {} '{}' {}",
+                    "Good signature".bright_green().bold(),
+                    "on tag".bright_green(),
+                    name.bright_cyan(),
+                    format!("by {}", tag.tagger).bright_black());
+            Ok(true)
+        }
+        SignatureStatus::Bad => {
+            println!("{} {} '{}'",
+                    "BAD signature".bright_red().bold(),
+                    "on tag".bright_red(),
+                    name.bright_cyan());
+            Ok(false)
+        }
+        SignatureStatus::Unsigned => {
+            println!("{} '{}' {}",
+                    "Tag".bright_yellow(),
+                    name.bright_cyan(),
+                    "is not signed".bright_yellow());
+            Ok(false)
+        }
+    }
+}